@@ -0,0 +1,227 @@
+use mtf::files::StreamStatusAttrs;
+use mtf::mdf::MTFPageProvider;
+use mtf::prelude::*;
+use mtf::schema::{IndexKind, Schema};
+use mtf::{DateTime, ErrorClass};
+use std::io::Write;
+
+type Result<T> = std::result::Result<T, failure::Error>;
+
+const USAGE: &str = "usage:
+    mtf sets <file>
+    mtf files <file>
+    mtf streams <file> --set <n> --id <id> --out <path>
+    mtf sql schema <file> --set <n>
+
+exit codes:
+    0   ok
+    1   ok, with warnings on stderr
+    2   corrupt input
+    3   unsupported feature
+    4   i/o error
+    64  usage error";
+
+const EXIT_OK: i32 = 0;
+const EXIT_WARNINGS: i32 = 1;
+const EXIT_CORRUPT_INPUT: i32 = 2;
+const EXIT_UNSUPPORTED_FEATURE: i32 = 3;
+const EXIT_IO_ERROR: i32 = 4;
+const EXIT_USAGE: i32 = 64;
+
+// Wrong arguments, kept apart from the errors of the library
+#[derive(Debug)]
+struct UsageError(String);
+
+impl std::fmt::Display for UsageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", self.0)?;
+        write!(f, "{}", USAGE)
+    }
+}
+
+impl std::error::Error for UsageError {}
+
+fn usage(message: &str) -> failure::Error {
+    UsageError(message.to_string()).into()
+}
+
+fn exit_code(err: &failure::Error) -> i32 {
+    if err.downcast_ref::<UsageError>().is_some() {
+        return EXIT_USAGE;
+    }
+
+    match ErrorClass::of(err) {
+        ErrorClass::CORRUPT_INPUT => EXIT_CORRUPT_INPUT,
+        ErrorClass::UNSUPPORTED_FEATURE => EXIT_UNSUPPORTED_FEATURE,
+        ErrorClass::IO => EXIT_IO_ERROR,
+    }
+}
+
+fn date(date: &DateTime) -> String {
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+// The commands return the number of warnings they printed
+fn sets(parser: &mut MTFParser) -> Result<usize> {
+    let mut warnings = 0;
+
+    for set in parser.backup_sets()? {
+        // readable anyway, but the backup software would ask for the password
+        if set.password_protected {
+            eprintln!(
+                "warning: set {} is password protected, its data is not encrypted",
+                set.data_set_number
+            );
+            warnings += 1;
+        }
+
+        println!(
+            "{:>4}  {}  {:>12}  {}",
+            set.data_set_number,
+            date(&set.write_date),
+            set.offset,
+            set.name.unwrap_or_default()
+        );
+    }
+
+    Ok(warnings)
+}
+
+fn files(parser: &mut MTFParser) -> Result<usize> {
+    let mut warnings = 0;
+
+    for file in parser.files()? {
+        if file.stream_status().contains(StreamStatusAttrs::TRUNCATED) {
+            eprintln!("warning: {} is truncated", file.full_path.display());
+            warnings += 1;
+        }
+
+        println!("{:>12}  {}", file.size, file.full_path.display());
+    }
+
+    Ok(warnings)
+}
+
+fn option<'a>(options: &'a [String], name: &str) -> Result<&'a String> {
+    options
+        .iter()
+        .position(|option| option == name)
+        .and_then(|idx| options.get(idx + 1))
+        .ok_or_else(|| usage(&format!("missing {}", name)))
+}
+
+fn set_number(options: &[String]) -> Result<u16> {
+    option(options, "--set")?
+        .parse()
+        .map_err(|_| usage("--set needs a data set number"))
+}
+
+// The raw bytes of the streams with the given id of a set, continuations merged
+fn streams(parser: &mut MTFParser, options: &[String]) -> Result<usize> {
+    let set_number = set_number(options)?;
+    let id = option(options, "--id")?;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(option(options, "--out")?)?);
+
+    let written = parser.write_streams(set_number, id, &mut out)?;
+    out.flush()?;
+    eprintln!("wrote {} bytes", written);
+
+    Ok(0)
+}
+
+// Tables with their columns and indexes of the database in a SQL Server backup set
+fn sql_schema(parser: &mut MTFParser, options: &[String]) -> Result<usize> {
+    let provider = MTFPageProvider::for_set(parser, set_number(options)?)?;
+    let schema = Schema::read(&provider)?;
+
+    for table in &schema.tables {
+        println!("{}.{}", table.schema, table.name);
+
+        for column in &table.columns {
+            println!(
+                "    {:<32} {:<20} {}",
+                column.name,
+                column.type_name(),
+                if column.nullable { "null" } else { "not null" }
+            );
+        }
+
+        for index in &table.indexes {
+            let kind = match index.kind {
+                IndexKind::HEAP => "heap".to_string(),
+                IndexKind::CLUSTERED => "clustered".to_string(),
+                IndexKind::NONCLUSTERED => "nonclustered".to_string(),
+                IndexKind::OTHER(kind) => format!("index type {}", kind),
+            };
+            let columns: Vec<_> = index
+                .columns
+                .iter()
+                .map(|key| {
+                    let name = table
+                        .column(key.column_id)
+                        .map_or_else(|| format!("column {}", key.column_id), |c| c.name.clone());
+                    match (key.included, key.descending) {
+                        (true, _) => format!("{} (included)", name),
+                        (false, true) => format!("{} desc", name),
+                        (false, false) => name,
+                    }
+                })
+                .collect();
+
+            println!(
+                "    index {} {}{}{} ({})",
+                index.name.as_deref().unwrap_or("-"),
+                kind,
+                if index.unique { " unique" } else { "" },
+                if index.primary_key {
+                    " primary key"
+                } else {
+                    ""
+                },
+                columns.join(", ")
+            );
+        }
+    }
+
+    Ok(0)
+}
+
+fn run(args: &[String]) -> Result<usize> {
+    let (command, file, options) = match args {
+        [sql, subcommand, file, options @ ..] if sql == "sql" => {
+            (format!("sql {}", subcommand), file, options)
+        }
+        [command, file, options @ ..] => (command.clone(), file, options),
+        _ => return Err(usage("missing command or file")),
+    };
+
+    let mut backup = Backup::open(file)?;
+    let parser = backup.parser();
+
+    match command.as_str() {
+        "sets" => sets(parser),
+        "files" => files(parser),
+        "streams" => streams(parser, options),
+        "sql schema" => sql_schema(parser, options),
+        _ => Err(usage(&format!("unknown command {:?}", command))),
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = match run(&args) {
+        Ok(0) => EXIT_OK,
+        Ok(_) => EXIT_WARNINGS,
+        Err(err) => {
+            eprintln!("{}", err);
+            exit_code(&err)
+        }
+    };
+
+    std::process::exit(code);
+}
@@ -0,0 +1,116 @@
+use crate::Result;
+use log::warn;
+use serde_json::{json, Value};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+
+// Chain of custody log, every operation on a medium is appended to it as one JSON object per
+// line. Installed globally like metrics::set_metrics, so it is written no matter which API the
+// operations went through
+pub struct AuditLog {
+    out: Mutex<Box<dyn Write + Send + Sync>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum AuditEvent<'e> {
+    // a medium was opened by MTFParser
+    OPENED {
+        path: &'e str,
+    },
+    // a DBLKIterator ran to completion over start..end of the medium
+    SCANNED {
+        start: u64,
+        end: u64,
+        blocks: u64,
+    },
+    // restore::Restorer wrote a file, with the hash of what ended up on disk
+    EXTRACTED {
+        entry: &'e Path,
+        target: &'e Path,
+        bytes: u64,
+        sha256: [u8; 32],
+    },
+    ERROR {
+        operation: &'e str,
+        message: String,
+    },
+}
+
+impl AuditLog {
+    pub fn new<W: Write + Send + Sync + 'static>(out: W) -> AuditLog {
+        AuditLog {
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    // Appends to the file, earlier sessions are kept
+    pub fn create(path: &Path) -> Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog::new(file))
+    }
+
+    fn write(&self, event: &AuditEvent) {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0.0, |time| time.as_secs_f64());
+
+        let mut line = event.to_json();
+        line["time"] = json!(time);
+
+        let mut out = self.out.lock().unwrap();
+        if let Err(err) = writeln!(out, "{}", line).and_then(|_| out.flush()) {
+            warn!("could not write to the audit log: {}", err);
+        }
+    }
+}
+
+impl<'e> AuditEvent<'e> {
+    fn to_json(&self) -> Value {
+        match self {
+            AuditEvent::OPENED { path } => json!({ "event": "opened", "path": path }),
+            AuditEvent::SCANNED { start, end, blocks } => json!({
+                "event": "scanned",
+                "start": start,
+                "end": end,
+                "blocks": blocks,
+            }),
+            AuditEvent::EXTRACTED {
+                entry,
+                target,
+                bytes,
+                sha256,
+            } => json!({
+                "event": "extracted",
+                "entry": entry.to_string_lossy(),
+                "target": target.to_string_lossy(),
+                "bytes": bytes,
+                "sha256": sha256.iter().map(|b| format!("{:02x}", b)).collect::<String>(),
+            }),
+            AuditEvent::ERROR { operation, message } => json!({
+                "event": "error",
+                "operation": operation,
+                "message": message,
+            }),
+        }
+    }
+}
+
+static AUDIT_LOG: RwLock<Option<Arc<AuditLog>>> = RwLock::new(None);
+
+pub fn set_audit_log(log: AuditLog) {
+    *AUDIT_LOG.write().unwrap() = Some(Arc::new(log));
+}
+
+// Some events are expensive to gather, like the hash of extracted files
+pub(crate) fn enabled() -> bool {
+    AUDIT_LOG.read().unwrap().is_some()
+}
+
+pub(crate) fn record(event: AuditEvent) {
+    if let Some(log) = AUDIT_LOG.read().unwrap().as_ref() {
+        log.write(&event)
+    }
+}
@@ -0,0 +1,132 @@
+use crate::compression::{CompressionAlgorithm, Frames};
+use crate::files::FileEntry;
+use crate::sets::BackupSet;
+use crate::{MTFOptions, MTFParser, Result, StreamWithData};
+use std::borrow::Cow;
+use std::io::Read;
+use std::ops::Deref;
+
+// A facade over MTFParser for the common case of reading the files of a backup, without dealing
+// with DBLKs and streams:
+//
+// let mut backup = Backup::open("backup.bkf")?;
+// for set in backup.sets()? {
+//     for file in set.files() {
+//         std::io::copy(&mut file.reader(), &mut std::io::sink())?;
+//     }
+// }
+#[derive(Debug)]
+pub struct Backup {
+    parser: MTFParser,
+}
+
+impl Backup {
+    pub fn open(filename: &str) -> Result<Backup> {
+        Backup::with_options(filename, MTFOptions::default())
+    }
+
+    // Fails if the file can't be opened or is no MTF file
+    pub fn with_options(filename: &str, options: MTFOptions) -> Result<Backup> {
+        let mut parser = MTFParser::open(filename, options)?;
+        parser.media_start()?;
+
+        Ok(Backup { parser })
+    }
+
+    pub fn sets(&mut self) -> Result<Vec<BackupSet<'_>>> {
+        self.parser.sets()
+    }
+
+    // For everything the facade doesn't cover
+    pub fn parser(&mut self) -> &mut MTFParser {
+        &mut self.parser
+    }
+}
+
+// A file of a set, together with the compression algorithm of the set its data needs
+#[derive(Debug, Clone, Copy)]
+pub struct SetFile<'s, 'a> {
+    pub entry: &'s FileEntry<'a>,
+    pub compression_algorithm: CompressionAlgorithm,
+}
+
+impl<'s, 'a> Deref for SetFile<'s, 'a> {
+    type Target = FileEntry<'a>;
+
+    fn deref(&self) -> &FileEntry<'a> {
+        self.entry
+    }
+}
+
+impl<'s, 'a> SetFile<'s, 'a> {
+    pub fn reader(&self) -> FileReader<'s, 'a> {
+        FileReader {
+            streams: self.entry.streams.iter(),
+            algorithm: self.compression_algorithm,
+            frames: None,
+            chunk: Cow::Borrowed(&[]),
+            position: 0,
+        }
+    }
+}
+
+// The data of a file (its STAN streams), decompressed and decrypted. Holes of sparse files are
+// left out, restore::Restorer recreates them
+pub struct FileReader<'s, 'a> {
+    streams: std::slice::Iter<'s, StreamWithData<'a>>,
+    algorithm: CompressionAlgorithm,
+    frames: Option<Frames<'a>>,
+    chunk: Cow<'a, [u8]>,
+    position: usize,
+}
+
+impl<'s, 'a> FileReader<'s, 'a> {
+    // Moves to the next chunk of data, false at the end of the file
+    fn next_chunk(&mut self) -> Result<bool> {
+        if let Some(frames) = &mut self.frames {
+            match frames.next() {
+                Some(chunk) => {
+                    self.chunk = Cow::Owned(chunk?);
+                    self.position = 0;
+                    return Ok(true);
+                }
+                None => self.frames = None,
+            }
+        }
+
+        let stream = match self.streams.find(|stream| stream.id() == "STAN") {
+            Some(stream) => stream,
+            None => return Ok(false),
+        };
+
+        if stream.is_compressed() {
+            self.frames = Some(stream.decompressed_chunks(self.algorithm)?);
+            self.chunk = Cow::Borrowed(&[]);
+        } else {
+            self.chunk = stream.decrypted()?;
+        }
+        self.position = 0;
+
+        Ok(true)
+    }
+}
+
+impl<'s, 'a> Read for FileReader<'s, 'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            let more = self.next_chunk().map_err(|err| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string())
+            })?;
+
+            if !more {
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.position);
+        buf[..len].copy_from_slice(&self.chunk[self.position..][..len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
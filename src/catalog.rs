@@ -0,0 +1,621 @@
+use crate::compression::CompressionAlgorithm;
+use crate::files::{EntryHandle, FileEntry};
+use crate::{
+    resolve_with, split_path, DBLKIterator, DateTime, DirbAttrs, FileAttrs, MTFOptions, Result,
+    SSetAttrs, StringDecoder, StringType, TapeAddress, TimeZone, VolbAttrs, OS,
+};
+use byteorder::{LittleEndian, ReadBytesExt};
+use failure::format_err;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+// The set map of the type 2 media based catalog, a summary of every set of the media family.
+// It is rewritten after every set, so the last one on the medium is the most complete
+#[derive(Debug, Clone)]
+pub struct SetMap {
+    pub media_family_id: u32,
+    pub entries: Vec<SetMapEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SetMapEntry {
+    pub attrs: SSetAttrs,
+    pub password_encryption_algorithm: u16,
+    pub software_compression_algorithm: CompressionAlgorithm,
+    pub software_vendor_id: u16,
+    pub data_set_number: u16,
+    pub data_set_name: Option<String>,
+    pub data_set_description: Option<String>,
+    pub data_set_password: Option<String>,
+    pub username: Option<String>,
+    // physical block addresses of the SSET and of the FDD of the set
+    pub sset_pba: u64,
+    pub fdd_pba: u64,
+    pub fdd_media_sequence_number: u16,
+    pub media_sequence_number: u16,
+    pub number_of_directories: u32,
+    pub number_of_files: u32,
+    pub number_of_corrupt_files: u32,
+    // bytes of data backed up in the set
+    pub data_set_size: u64,
+    pub write_date: DateTime,
+    pub software_major_version: u8,
+    pub software_minor_version: u8,
+    pub timezone: TimeZone,
+    pub minor_version: u8,
+    pub media_catalog_version: u8,
+    pub volumes: Vec<SetMapVolume>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SetMapVolume {
+    pub attrs: VolbAttrs,
+    pub media_sequence_number: u16,
+    pub device_name: Option<String>,
+    pub volume_name: Option<String>,
+    pub machine_name: Option<String>,
+    pub write_date: DateTime,
+}
+
+impl SetMap {
+    // The TSMP (or MAP2) stream data. The strings of the entries are addressed relative to the entry
+    pub(crate) fn parse(data: &[u8], strings: &StringDecoder) -> Result<SetMap> {
+        let mut data = Cursor::new(data);
+
+        let media_family_id = data.read_u32::<LittleEndian>()?;
+        let number_of_entries = data.read_u16::<LittleEndian>()?;
+        data.set_position(data.position() + 2);
+
+        let entries = (0..number_of_entries)
+            .map(|_| SetMapEntry::parse(&mut data, strings))
+            .collect::<Result<_>>()?;
+
+        Ok(SetMap {
+            media_family_id,
+            entries,
+        })
+    }
+}
+
+impl SetMapEntry {
+    fn parse(data: &mut Cursor<&[u8]>, strings: &StringDecoder) -> Result<SetMapEntry> {
+        let base = data.position();
+        let length = data.read_u16::<LittleEndian>()? as u64;
+        if length == 0 {
+            return Err(format_err!("set map entry at {} has zero length", base));
+        }
+
+        let number_of_volumes = data.read_u16::<LittleEndian>()?;
+        let attrs = data.read_u32::<LittleEndian>()?;
+        let attrs = SSetAttrs::from_bits(attrs).ok_or_else(|| {
+            format_err!("could not parse set map sset attributes from {:#b}", attrs)
+        })?;
+
+        let password_encryption_algorithm = data.read_u16::<LittleEndian>()?;
+        let software_compression_algorithm =
+            CompressionAlgorithm::parse(data.read_u16::<LittleEndian>()?);
+        let software_vendor_id = data.read_u16::<LittleEndian>()?;
+        let data_set_number = data.read_u16::<LittleEndian>()?;
+        let data_set_name =
+            TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_str(strings, data)?;
+        let data_set_description =
+            TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_str(strings, data)?;
+        let data_set_password =
+            TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_str(strings, data)?;
+        let username =
+            TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_str(strings, data)?;
+        let sset_pba = data.read_u64::<LittleEndian>()?;
+        let fdd_pba = data.read_u64::<LittleEndian>()?;
+        let fdd_media_sequence_number = data.read_u16::<LittleEndian>()?;
+        let media_sequence_number = data.read_u16::<LittleEndian>()?;
+        let number_of_directories = data.read_u32::<LittleEndian>()?;
+        let number_of_files = data.read_u32::<LittleEndian>()?;
+        let number_of_corrupt_files = data.read_u32::<LittleEndian>()?;
+        let data_set_size = data.read_u64::<LittleEndian>()?;
+        let write_date = DateTime::read(data)?;
+        let software_major_version = data.read_u8()?;
+        let software_minor_version = data.read_u8()?;
+        let timezone = TimeZone::parse(data.read_i8()?);
+        let minor_version = data.read_u8()?;
+        let media_catalog_version = data.read_u8()?;
+
+        // the volume entries follow the fixed part, the strings come after all of them
+        let volumes = (0..number_of_volumes)
+            .map(|_| SetMapVolume::parse(data, strings))
+            .collect::<Result<_>>()?;
+
+        data.set_position(base + length);
+
+        Ok(SetMapEntry {
+            attrs,
+            password_encryption_algorithm,
+            software_compression_algorithm,
+            software_vendor_id,
+            data_set_number,
+            data_set_name,
+            data_set_description,
+            data_set_password,
+            username,
+            sset_pba,
+            fdd_pba,
+            fdd_media_sequence_number,
+            media_sequence_number,
+            number_of_directories,
+            number_of_files,
+            number_of_corrupt_files,
+            data_set_size,
+            write_date,
+            software_major_version,
+            software_minor_version,
+            timezone,
+            minor_version,
+            media_catalog_version,
+            volumes,
+        })
+    }
+}
+
+impl SetMapVolume {
+    fn parse(data: &mut Cursor<&[u8]>, strings: &StringDecoder) -> Result<SetMapVolume> {
+        let base = data.position();
+        let length = data.read_u16::<LittleEndian>()? as u64;
+        if length == 0 {
+            return Err(format_err!(
+                "set map volume entry at {} has zero length",
+                base
+            ));
+        }
+
+        let attrs = data.read_u32::<LittleEndian>()?;
+        let attrs = VolbAttrs::from_bits(attrs).ok_or_else(|| {
+            format_err!("could not parse set map volb attributes from {:#b}", attrs)
+        })?;
+
+        let media_sequence_number = data.read_u16::<LittleEndian>()?;
+        let device_name =
+            TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_str(strings, data)?;
+        let volume_name =
+            TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_str(strings, data)?;
+        let machine_name =
+            TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_str(strings, data)?;
+        let write_date = DateTime::read(data)?;
+
+        data.set_position(base + length);
+
+        Ok(SetMapVolume {
+            attrs,
+            media_sequence_number,
+            device_name,
+            volume_name,
+            machine_name,
+            write_date,
+        })
+    }
+}
+
+// The file/directory detail of the type 2 catalog, a condensed copy of the VOLB, DIRB and FILE
+// blocks of one set
+#[derive(Debug, Clone)]
+pub struct Fdd {
+    pub entries: Vec<FddEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FddEntry {
+    pub media_sequence_number: u16,
+    // of the DBLK this entry describes
+    pub format_logical_address: u64,
+    pub display_size: u64,
+    pub link: u32,
+    pub osid: OS,
+    pub osver: u8,
+    pub os_specific_data: Option<Vec<u8>>,
+    pub body: FddBody,
+}
+
+#[derive(Debug, Clone)]
+pub enum FddBody {
+    VOLB {
+        attrs: VolbAttrs,
+        device_name: Option<String>,
+        volume_name: Option<String>,
+        machine_name: Option<String>,
+        write_date: DateTime,
+    },
+    DIRB {
+        attrs: DirbAttrs,
+        last_modification_date: DateTime,
+        creation_date: DateTime,
+        backup_date: DateTime,
+        last_access_date: DateTime,
+        // relative to the volume, empty for the root directory
+        path: Vec<String>,
+    },
+    FILE {
+        attrs: FileAttrs,
+        last_modification_date: DateTime,
+        creation_date: DateTime,
+        backup_date: DateTime,
+        last_access_date: DateTime,
+        file_name: Option<String>,
+    },
+    UNKNOWN(String),
+}
+
+impl Fdd {
+    // The TFDD stream data, a list of entries ended by an FEND entry
+    pub(crate) fn parse(data: &[u8], options: &MTFOptions) -> Result<Fdd> {
+        let mut data = Cursor::new(data);
+        let mut entries = Vec::new();
+
+        while data.position() < data.get_ref().len() as u64 {
+            match FddEntry::parse(&mut data, options)? {
+                Some(entry) => entries.push(entry),
+                None => break,
+            }
+        }
+
+        Ok(Fdd { entries })
+    }
+
+    // Every file with its path relative to its volume, the entries of a directory follow its DIRB
+    pub fn files(&self) -> Vec<(PathBuf, &FddEntry)> {
+        let mut directory = PathBuf::new();
+        let mut files = Vec::new();
+
+        for entry in &self.entries {
+            match &entry.body {
+                FddBody::VOLB { .. } => directory = PathBuf::new(),
+                FddBody::DIRB { path, .. } => directory = path.iter().collect(),
+                FddBody::FILE {
+                    file_name: Some(file_name),
+                    ..
+                } => files.push((directory.join(file_name), entry)),
+                _ => {}
+            }
+        }
+
+        files
+    }
+}
+
+// The files of a set in the order of its FDD (MTFParser::catalog_files). The catalog tells how many
+// there are and where their blocks are, so the length is exact and nth only parses the blocks of
+// the file it lands on, to page through large sets
+pub struct CatalogFiles<'a> {
+    dblks: DBLKIterator<'a>,
+    handles: std::vec::IntoIter<EntryHandle>,
+}
+
+impl<'a> CatalogFiles<'a> {
+    pub(crate) fn new(dblks: DBLKIterator<'a>, handles: Vec<EntryHandle>) -> Self {
+        Self {
+            dblks,
+            handles: handles.into_iter(),
+        }
+    }
+
+    // Of the files not yet returned
+    pub fn handles(&self) -> &[EntryHandle] {
+        self.handles.as_slice()
+    }
+}
+
+impl<'a> Iterator for CatalogFiles<'a> {
+    type Item = Result<FileEntry<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let handle = self.handles.next()?;
+        Some(resolve_with(&mut self.dblks, handle))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.handles.size_hint()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let handle = self.handles.nth(n)?;
+        Some(resolve_with(&mut self.dblks, handle))
+    }
+}
+
+impl ExactSizeIterator for CatalogFiles<'_> {}
+
+impl FddEntry {
+    // None for the FEND entry
+    fn parse(data: &mut Cursor<&[u8]>, options: &MTFOptions) -> Result<Option<FddEntry>> {
+        let base = data.position();
+        let length = data.read_u16::<LittleEndian>()? as u64;
+
+        let mut ty = [0; 4];
+        std::io::Read::read_exact(data, &mut ty)?;
+        let ty = String::from_utf8_lossy(&ty).into_owned();
+        if ty == "FEND" {
+            return Ok(None);
+        }
+        if length == 0 {
+            return Err(format_err!("fdd entry at {} has zero length", base));
+        }
+
+        let media_sequence_number = data.read_u16::<LittleEndian>()?;
+        // the common block attributes are not needed to read the entry
+        data.read_u32::<LittleEndian>()?;
+        let format_logical_address = data.read_u64::<LittleEndian>()?;
+        let display_size = data.read_u64::<LittleEndian>()?;
+        let link = data.read_u32::<LittleEndian>()?;
+        let osid = OS::parse(data.read_u8()?);
+        let osver = data.read_u8()?;
+        let string_type = StringType::parse(data.read_u8()?)?;
+        data.read_u8()?;
+
+        let strings = StringDecoder::for_os(&osid, string_type, options);
+        let string = |data: &mut Cursor<&[u8]>| -> Result<Option<String>> {
+            TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_str(&strings, data)
+        };
+
+        let (body, os_specific_data) = match &*ty {
+            "VOLB" => {
+                let attrs = data.read_u32::<LittleEndian>()?;
+                let attrs = VolbAttrs::from_bits(attrs).ok_or_else(|| {
+                    format_err!("could not parse fdd volb attributes from {:#b}", attrs)
+                })?;
+                let device_name = string(data)?;
+                let volume_name = string(data)?;
+                let machine_name = string(data)?;
+                let os_specific_data =
+                    TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_bytes(data)?;
+                let write_date = DateTime::read(data)?;
+
+                (
+                    FddBody::VOLB {
+                        attrs,
+                        device_name,
+                        volume_name,
+                        machine_name,
+                        write_date,
+                    },
+                    os_specific_data,
+                )
+            }
+            "DIRB" => {
+                let attrs = data.read_u32::<LittleEndian>()?;
+                let attrs = DirbAttrs::from_bits(attrs).ok_or_else(|| {
+                    format_err!("could not parse fdd dirb attributes from {:#b}", attrs)
+                })?;
+                let last_modification_date = DateTime::read(data)?;
+                let creation_date = DateTime::read(data)?;
+                let backup_date = DateTime::read(data)?;
+                let last_access_date = DateTime::read(data)?;
+                let path = string(data)?
+                    .map(|path| split_path(&path))
+                    .unwrap_or_default();
+                let os_specific_data =
+                    TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_bytes(data)?;
+
+                (
+                    FddBody::DIRB {
+                        attrs,
+                        last_modification_date,
+                        creation_date,
+                        backup_date,
+                        last_access_date,
+                        path,
+                    },
+                    os_specific_data,
+                )
+            }
+            "FILE" => {
+                let attrs = data.read_u32::<LittleEndian>()?;
+                let attrs = FileAttrs::from_bits(attrs).ok_or_else(|| {
+                    format_err!("could not parse fdd file attributes from {:#b}", attrs)
+                })?;
+                let last_modification_date = DateTime::read(data)?;
+                let creation_date = DateTime::read(data)?;
+                let backup_date = DateTime::read(data)?;
+                let last_access_date = DateTime::read(data)?;
+                let file_name = string(data)?;
+                let os_specific_data =
+                    TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?.read_bytes(data)?;
+
+                (
+                    FddBody::FILE {
+                        attrs,
+                        last_modification_date,
+                        creation_date,
+                        backup_date,
+                        last_access_date,
+                        file_name,
+                    },
+                    os_specific_data,
+                )
+            }
+            _ => (FddBody::UNKNOWN(ty), None),
+        };
+
+        data.set_position(base + length);
+
+        Ok(Some(FddEntry {
+            media_sequence_number,
+            format_logical_address,
+            display_size,
+            link,
+            osid,
+            osver,
+            os_specific_data,
+            body,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, FileBuilder, MediumBuilder, SetBuilder};
+    use crate::{DBLKSpecific, MTFParser};
+
+    fn set(number: u16) -> SetBuilder {
+        SetBuilder::new(number)
+            .name(&format!("set {}", number))
+            .file(FileBuilder::new("boot.ini").data(b"[boot loader]"))
+            .directory("Windows")
+            .file(FileBuilder::new("win.ini").data(b"[fonts]"))
+            .volume("D:")
+            .directory("data/2020")
+            .file(FileBuilder::new("report.doc").data(b"report"))
+    }
+
+    // (data set number, volume, path) of every file, from all blocks
+    fn scanned_files(parser: &mut MTFParser) -> Vec<(u16, Option<String>, PathBuf)> {
+        parser
+            .files()
+            .unwrap()
+            .map(|file| {
+                (
+                    file.handle.data_set_number.unwrap(),
+                    file.volume.as_deref().map(str::to_string),
+                    file.full_path,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn set_map() {
+        let medium = MediumBuilder::new()
+            .media_family_id(0x1234)
+            .set_map_catalog()
+            .set(set(1))
+            .set(set(2))
+            .set(SetBuilder::new(3))
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("set-map", &medium);
+
+        let set_map = parser.set_map().unwrap().unwrap();
+        assert_eq!(set_map.media_family_id, 0x1234);
+
+        let sets = parser.backup_sets().unwrap();
+        assert_eq!(set_map.entries.len(), sets.len());
+        for (entry, set) in set_map.entries.iter().zip(&sets) {
+            assert_eq!(entry.data_set_number, set.data_set_number);
+            assert_eq!(entry.data_set_name, set.name);
+            assert_eq!(parser.pba_offset(entry.sset_pba).unwrap(), set.offset);
+            assert_eq!(entry.media_sequence_number, 1);
+        }
+
+        let entry = &set_map.entries[1];
+        assert_eq!((entry.number_of_directories, entry.number_of_files), (2, 3));
+        assert_eq!(entry.data_set_size, 26);
+        let volumes: Vec<_> = entry
+            .volumes
+            .iter()
+            .map(|volume| volume.device_name.as_deref())
+            .collect();
+        assert_eq!(volumes, [Some("C:"), Some("D:")]);
+        assert!(set_map.entries[2].volumes.is_empty());
+
+        // the type 1 catalog has no set map
+        let medium = MediumBuilder::new().catalog().set(set(1)).build().unwrap();
+        let mut parser = fixture::open("set-map-type-1", &medium);
+        assert!(parser.set_map().unwrap().is_none());
+    }
+
+    #[test]
+    fn fdd() {
+        for (name, medium) in [
+            ("fdd-type-1", MediumBuilder::new().catalog()),
+            ("fdd-type-2", MediumBuilder::new().set_map_catalog()),
+        ] {
+            let medium = medium.set(set(1)).set(set(2)).build().unwrap();
+            let mut parser = fixture::open(name, &medium);
+            let scanned = scanned_files(&mut parser);
+
+            let fdds = parser.fdds().unwrap();
+            assert_eq!(
+                fdds.iter().map(|(number, _)| *number).collect::<Vec<_>>(),
+                [1, 2]
+            );
+
+            for (number, fdd) in &fdds {
+                let listed: Vec<_> = fdd.files().into_iter().map(|(path, _)| path).collect();
+                let expected: Vec<_> = scanned
+                    .iter()
+                    .filter(|(set, _, _)| set == number)
+                    .map(|(_, _, path)| path.clone())
+                    .collect();
+                assert_eq!(listed, expected, "{}", name);
+
+                // every entry points at the block it describes
+                for entry in &fdd.entries {
+                    let offset = parser.pba_offset(entry.format_logical_address).unwrap();
+                    let mut dblks = parser.dblks().unwrap();
+                    let dblk = dblks.find(|dblk| dblk.offset == offset).unwrap();
+                    match (&entry.body, &dblk.dblk.body) {
+                        (FddBody::VOLB { .. }, DBLKSpecific::VOLB { .. })
+                        | (FddBody::DIRB { .. }, DBLKSpecific::DIRB { .. }) => {}
+                        (
+                            FddBody::FILE { file_name, .. },
+                            DBLKSpecific::FILE {
+                                file_name: block_name,
+                                ..
+                            },
+                        ) => assert_eq!(file_name, block_name),
+                        (entry, block) => panic!("{:?} describes {:?}", entry, block),
+                    }
+                }
+            }
+
+            let files: Vec<_> = parser
+                .catalog_files(2)
+                .unwrap()
+                .unwrap()
+                .map(|file| {
+                    let file = file.unwrap();
+                    (
+                        file.handle.data_set_number.unwrap(),
+                        file.volume.as_deref().map(str::to_string),
+                        file.full_path,
+                    )
+                })
+                .collect();
+            let expected: Vec<_> = scanned.into_iter().filter(|file| file.0 == 2).collect();
+            assert_eq!(files, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn soft_filemarks() {
+        let medium = MediumBuilder::new()
+            .soft_filemarks()
+            .set(set(1))
+            .set(set(2))
+            .set(set(3))
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("soft-filemarks", &medium);
+
+        let sfmbs: Vec<_> = parser
+            .dblks()
+            .unwrap()
+            .filter(|dblk| matches!(dblk.dblk.body, DBLKSpecific::SFMB { .. }))
+            .map(|dblk| (dblk.offset, dblk.dblk))
+            .collect();
+        assert_eq!(sfmbs.len(), 3);
+
+        // the last one lists all of them, the most recent first
+        let (_, last) = sfmbs.last().unwrap();
+        let mut expected: Vec<_> = sfmbs.iter().map(|(offset, _)| *offset).collect();
+        expected.reverse();
+        assert_eq!(parser.soft_filemarks(last).unwrap(), expected);
+
+        let table = parser.soft_filemark_table(last).unwrap();
+        assert_eq!(table.number_of_entries, 113);
+        assert_eq!(table.entries.len(), 3);
+
+        let (_, first) = &sfmbs[0];
+        assert_eq!(parser.soft_filemarks(first).unwrap(), [sfmbs[0].0]);
+
+        let tape = parser.dblks().unwrap().next().unwrap().dblk;
+        assert!(parser.soft_filemarks(&tape).is_err());
+    }
+}
@@ -0,0 +1,370 @@
+use crate::{Result, UnsupportedFeature};
+use byteorder::{ByteOrder, LittleEndian};
+use failure::format_err;
+use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
+
+// MTF only defines 0 as no compression, every other value identifies a vendor specific algorithm
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompressionAlgorithm {
+    NONE,
+    VENDOR(u16),
+}
+
+impl CompressionAlgorithm {
+    pub(crate) fn parse(algorithm: u16) -> CompressionAlgorithm {
+        match algorithm {
+            0 => CompressionAlgorithm::NONE,
+            algorithm => CompressionAlgorithm::VENDOR(algorithm),
+        }
+    }
+
+    // Whether the data of streams compressed with this algorithm can be decoded
+    pub fn is_supported(&self) -> bool {
+        decompressor(*self).is_some()
+    }
+}
+
+// Decodes the frames of COMRESSED streams. Installed globally like Metrics, the first one that
+// supports the algorithm of a stream is used, Lzs for the algorithms none of them supports
+pub trait Decompressor: Send + Sync {
+    fn supports(&self, algorithm: CompressionAlgorithm) -> bool;
+
+    fn decompress(
+        &self,
+        algorithm: CompressionAlgorithm,
+        data: &[u8],
+        uncompressed_size: usize,
+    ) -> Result<Vec<u8>>;
+}
+
+static DECOMPRESSORS: RwLock<Vec<Arc<dyn Decompressor>>> = RwLock::new(Vec::new());
+
+pub fn register_decompressor(decompressor: Arc<dyn Decompressor>) {
+    DECOMPRESSORS.write().unwrap().push(decompressor);
+}
+
+fn decompressor(algorithm: CompressionAlgorithm) -> Option<Arc<dyn Decompressor>> {
+    let registered = DECOMPRESSORS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|decompressor| decompressor.supports(algorithm))
+        .cloned();
+
+    registered.or_else(|| match algorithm {
+        CompressionAlgorithm::NONE => None,
+        CompressionAlgorithm::VENDOR(algorithm) => Some(Arc::new(Lzs { algorithm })),
+    })
+}
+
+// Stac LZS (ANSI X3.241), the standard software compression of MTF. Writers record it under
+// different algorithm ids, so it is the default for every vendor algorithm. Registering it
+// explicitly is only needed to put it in front of other decompressors
+#[derive(Debug, Clone, Copy)]
+pub struct Lzs {
+    pub algorithm: u16,
+}
+
+impl Decompressor for Lzs {
+    fn supports(&self, algorithm: CompressionAlgorithm) -> bool {
+        algorithm == CompressionAlgorithm::VENDOR(self.algorithm)
+    }
+
+    fn decompress(
+        &self,
+        _algorithm: CompressionAlgorithm,
+        data: &[u8],
+        uncompressed_size: usize,
+    ) -> Result<Vec<u8>> {
+        lzs(data, uncompressed_size)
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    // in bits, most significant bit first
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn read(&mut self, bits: usize) -> Result<usize> {
+        let mut value = 0;
+
+        for _ in 0..bits {
+            let byte = self
+                .data
+                .get(self.position / 8)
+                .ok_or_else(|| format_err!("LZS data truncated"))?;
+
+            value = (value << 1) | ((byte >> (7 - self.position % 8)) & 1) as usize;
+            self.position += 1;
+        }
+
+        Ok(value)
+    }
+}
+
+// The uncompressed size comes from the frame header, a corrupt one must not make us reserve
+// gigabytes up front
+const MAX_RESERVATION: usize = 64 * 1024;
+
+fn lzs(data: &[u8], uncompressed_size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(uncompressed_size.min(MAX_RESERVATION));
+    let mut bits = BitReader { data, position: 0 };
+
+    let too_long = |len: usize| {
+        format_err!(
+            "LZS data decodes to more than the {} bytes of the frame (at least {})",
+            uncompressed_size,
+            len
+        )
+    };
+
+    while bits.position < data.len() * 8 {
+        if bits.read(1)? == 0 {
+            if out.len() == uncompressed_size {
+                return Err(too_long(out.len() + 1));
+            }
+            out.push(bits.read(8)? as u8);
+            continue;
+        }
+
+        let offset = if bits.read(1)? == 1 {
+            let offset = bits.read(7)?;
+
+            // the end marker, more compressed data can follow at the next byte
+            if offset == 0 {
+                bits.position = bits.position.div_ceil(8) * 8;
+                continue;
+            }
+
+            offset
+        } else {
+            bits.read(11)?
+        };
+
+        let length = match bits.read(2)? {
+            0 => 2,
+            1 => 3,
+            2 => 4,
+            _ => match bits.read(2)? {
+                0 => 5,
+                1 => 6,
+                2 => 7,
+                _ => {
+                    let mut length = 8;
+                    loop {
+                        let nibble = bits.read(4)?;
+                        length += nibble;
+                        if nibble != 15 {
+                            break length;
+                        }
+                    }
+                }
+            },
+        };
+
+        if offset == 0 || offset > out.len() {
+            return Err(format_err!(
+                "LZS offset {} outside of the {} decoded bytes",
+                offset,
+                out.len()
+            ));
+        }
+
+        if out.len() + length > uncompressed_size {
+            return Err(too_long(out.len() + length));
+        }
+
+        for _ in 0..length {
+            out.push(out[out.len() - offset]);
+        }
+    }
+
+    Ok(out)
+}
+
+const FRAME_HEADER_SIZE: usize = 22;
+
+// The data of a COMRESSED stream is a sequence of frames, each with a header:
+// "FM", remaining stream size (u64), uncompressed size (u32), compressed size (u32),
+// sequence number (u8), reserved (u8) and the XOR of the other words
+pub struct Frames<'a> {
+    data: Cow<'a, [u8]>,
+    position: usize,
+    algorithm: CompressionAlgorithm,
+    failed: bool,
+}
+
+impl<'a> Frames<'a> {
+    pub(crate) fn new(data: Cow<'a, [u8]>, algorithm: CompressionAlgorithm) -> Frames<'a> {
+        Frames {
+            data,
+            position: 0,
+            algorithm,
+            failed: false,
+        }
+    }
+
+    fn frame(&mut self) -> Result<Vec<u8>> {
+        let header = self
+            .data
+            .get(self.position..self.position + FRAME_HEADER_SIZE)
+            .ok_or_else(|| {
+                format_err!("compression frame header at {} truncated", self.position)
+            })?;
+
+        if &header[..2] != b"FM" {
+            return Err(format_err!(
+                "no compression frame header at {}, found {:x?}",
+                self.position,
+                &header[..2]
+            ));
+        }
+
+        let checksum = header[..FRAME_HEADER_SIZE - 2]
+            .chunks(2)
+            .fold(0, |checksum, word| checksum ^ LittleEndian::read_u16(word));
+        if checksum != LittleEndian::read_u16(&header[FRAME_HEADER_SIZE - 2..]) {
+            return Err(format_err!(
+                "wrong compression frame header checksum at {}",
+                self.position
+            ));
+        }
+
+        let uncompressed_size = LittleEndian::read_u32(&header[10..]) as usize;
+        let compressed_size = LittleEndian::read_u32(&header[14..]) as usize;
+
+        let start = self.position + FRAME_HEADER_SIZE;
+        let data = self
+            .data
+            .get(start..start + compressed_size)
+            .ok_or_else(|| format_err!("compression frame at {} truncated", self.position))?;
+        self.position = start + compressed_size;
+
+        // frames that would not get smaller are stored as is
+        if compressed_size == uncompressed_size {
+            return Ok(data.to_vec());
+        }
+
+        let decompressor = decompressor(self.algorithm).ok_or_else(|| {
+            UnsupportedFeature(format!(
+                "no decompressor registered for {:?}",
+                self.algorithm
+            ))
+        })?;
+        let chunk = decompressor.decompress(self.algorithm, data, uncompressed_size)?;
+
+        if chunk.len() != uncompressed_size {
+            return Err(format_err!(
+                "compression frame decompressed to {} bytes instead of {}",
+                chunk.len(),
+                uncompressed_size
+            ));
+        }
+
+        Ok(chunk)
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.position >= self.data.len() {
+            return None;
+        }
+
+        let frame = self.frame();
+        self.failed = frame.is_err();
+
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::frame;
+
+    // "abcabcabc": the literals a, b and c (0 and 8 bits each), a match with the 7 bit
+    // offset 3 and the length 6 (1, 1, 0000011, 1101) and the end marker (1, 1, 0000000)
+    const LZS: [u8; 7] = [0x30, 0x98, 0x8c, 0x78, 0x3d, 0xc0, 0x00];
+
+    fn frames(data: &[u8]) -> Result<Vec<u8>> {
+        Frames::new(data.into(), CompressionAlgorithm::VENDOR(0x4c5a))
+            .collect::<Result<Vec<_>>>()
+            .map(|chunks| chunks.concat())
+    }
+
+    #[test]
+    fn lzs_known_vector() {
+        assert_eq!(lzs(&LZS, 9).unwrap(), b"abcabcabc");
+
+        // the end marker is optional at the end of the data
+        assert_eq!(lzs(&LZS[..5], 9).unwrap(), b"abcabcabc");
+
+        let err = lzs(&LZS[..4], 9).unwrap_err();
+        assert!(err.to_string().contains("truncated"));
+
+        // more data than the frame claims
+        let err = lzs(&LZS, 5).unwrap_err();
+        assert!(err.to_string().contains("more than the 5 bytes"));
+        let err = lzs(&LZS, 2).unwrap_err();
+        assert!(err.to_string().contains("more than the 2 bytes"));
+
+        // a match before any data
+        let err = lzs(&[0xc1, 0xc0], 9).unwrap_err();
+        assert!(err.to_string().contains("outside of the 0 decoded bytes"));
+    }
+
+    #[test]
+    fn lzs_is_the_default() {
+        assert!(!CompressionAlgorithm::NONE.is_supported());
+        assert!(CompressionAlgorithm::VENDOR(0x4c5a).is_supported());
+        assert!(CompressionAlgorithm::VENDOR(1).is_supported());
+    }
+
+    #[test]
+    fn frame_round_trip() {
+        // a compressed frame followed by a stored one
+        let data = [frame(&LZS, 9), frame(b"def", 3)].concat();
+        assert_eq!(frames(&data).unwrap(), b"abcabcabcdef");
+        assert_eq!(frames(&[]).unwrap(), b"");
+    }
+
+    #[test]
+    fn frame_errors() {
+        let data = frame(&LZS, 9);
+
+        let err = frames(&data[..FRAME_HEADER_SIZE - 1]).unwrap_err();
+        assert!(err.to_string().contains("header at 0 truncated"));
+
+        let err = frames(&data[..data.len() - 1]).unwrap_err();
+        assert!(err.to_string().contains("frame at 0 truncated"));
+
+        // a second frame cut short
+        let err = frames(&[&data[..], &data[..10]].concat()).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("header at {} truncated", data.len())));
+
+        let mut magic = data.clone();
+        magic[..2].copy_from_slice(b"MF");
+        assert!(frames(&magic).is_err());
+
+        let mut checksum = data.clone();
+        checksum[10] ^= 1;
+        let err = frames(&checksum).unwrap_err();
+        assert!(err.to_string().contains("checksum"));
+
+        // the header claims fewer bytes than the data decodes to
+        let err = frames(&frame(&LZS, 6)).unwrap_err();
+        assert!(err.to_string().contains("more than the 6 bytes"));
+
+        // and more
+        let err = frames(&frame(&LZS, 12)).unwrap_err();
+        assert!(err.to_string().contains("9 bytes instead of 12"));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::mdf::{MTFPageProvider, SqlFileList};
+use crate::{MTFParser, Result};
+use failure::format_err;
+use mdf::PageProvider;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct ConvertedFile {
+    pub file_id: u16,
+    pub path: PathBuf,
+    pub pages: u32,
+}
+
+// Writes every database file of the provider below target_dir, in page order with the pages
+// missing from the backup zero filled, so the files can be attached to SQL Server or read with
+// other MDF tools. name gives the file name for a file id
+pub fn write_files<F: Fn(u16) -> String>(
+    provider: &MTFPageProvider,
+    target_dir: &Path,
+    name: F,
+) -> Result<Vec<ConvertedFile>> {
+    std::fs::create_dir_all(target_dir)?;
+
+    provider
+        .file_ids()
+        .into_iter()
+        .map(|file_id| {
+            let file_name = name(file_id);
+            if file_name.is_empty() || file_name.contains(['/', '\\', ':']) || file_name == ".." {
+                return Err(format_err!(
+                    "refusing to write file {} to unsafe name {:?}",
+                    file_id,
+                    file_name
+                ));
+            }
+
+            let path = target_dir.join(file_name);
+            provider.write_file(file_id, BufWriter::new(File::create(&path)?))?;
+
+            Ok(ConvertedFile {
+                file_id,
+                path,
+                pages: provider.num_pages(file_id),
+            })
+        })
+        .collect()
+}
+
+// The data files of a backup set, named like their physical name in the MQCI stream (see
+// mdf::SqlFileList), or after their file id without one: file_1.mdf for the primary file,
+// file_<id>.ndf for the others. The log is not part of the pages, attach the files with FOR
+// ATTACH_REBUILD_LOG
+pub fn convert_set(
+    parser: &mut MTFParser,
+    set_number: u16,
+    target_dir: &Path,
+) -> Result<Vec<ConvertedFile>> {
+    let file_list = SqlFileList::for_set(parser, set_number)?;
+    let provider = MTFPageProvider::for_set(parser, set_number)?;
+
+    write_files(&provider, target_dir, |file_id| {
+        let physical_name = file_list
+            .files
+            .iter()
+            .find(|file| file.file_id == file_id)
+            .and_then(|file| file.physical_name.as_deref())
+            .and_then(|name| name.rsplit(['\\', '/']).next());
+
+        match (physical_name, file_id) {
+            (Some(name), _) => name.to_string(),
+            (None, 1) => format!("file_{}.mdf", file_id),
+            (None, _) => format!("file_{}.ndf", file_id),
+        }
+    })
+}
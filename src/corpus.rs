@@ -0,0 +1,108 @@
+use crate::{MTFOptions, MTFParser, Result};
+use failure::format_err;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+// Everything parsed from the medium, as JSON. Block bodies are recorded with their Debug output,
+// so a change to any parsed field shows up
+pub fn snapshot(parser: &mut MTFParser) -> Value {
+    let mut dblks = match parser.dblks() {
+        Ok(dblks) => dblks,
+        Err(err) => return json!({ "error": err.to_string() }),
+    };
+    let mut blocks = Vec::new();
+
+    loop {
+        let offset = dblks.position;
+        let dblk = match dblks.next() {
+            Some(dblk) => dblk,
+            None => break,
+        };
+
+        let streams: Vec<Value> = dblk
+            .streams
+            .iter()
+            .map(|stream| {
+                json!({
+                    "id": stream.id(),
+                    "length": stream.len(),
+                    "checksum": stream.checksum(),
+                })
+            })
+            .collect();
+
+        blocks.push(json!({
+            "offset": offset,
+            "display_size": dblk.dblk.header.display_size,
+            "format_logical_address": dblk.dblk.header.format_logical_address,
+            "os_specific": format!("{:?}", dblk.dblk.header.os_specific),
+            "body": format!("{:?}", dblk.dblk.body),
+            "streams": streams,
+            "terminator": format!("{:?}", dblk.terminator),
+        }));
+    }
+
+    let summary = dblks.finish();
+
+    let files = match parser.files() {
+        Ok(files) => files,
+        Err(err) => return json!({ "error": err.to_string() }),
+    };
+    let files: Vec<Value> = files
+        .map(|file| {
+            json!({
+                "path": file.full_path.to_string_lossy(),
+                "size": file.size,
+                "attributes": file.attributes.bits(),
+            })
+        })
+        .collect();
+
+    json!({
+        "blocks": blocks,
+        "files": files,
+        "summary": {
+            "blocks": summary.blocks,
+            "bytes_consumed": summary.bytes_consumed,
+            "warnings": summary.warnings,
+            "termination": format!("{:?}", summary.termination),
+        },
+    })
+}
+
+// Golden file runner for a directory of sample media, to catch format regressions across
+// refactors. Compares every sample against the <sample>.json snapshot next to it and returns the
+// samples that differ. With update the snapshots are (re)written instead, missing ones always are
+pub fn run(dir: &Path, update: bool) -> Result<Vec<PathBuf>> {
+    let mut samples = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    samples.retain(|path| path.is_file() && path.extension().is_none_or(|ext| ext != "json"));
+    samples.sort();
+
+    let mut mismatches = Vec::new();
+    for sample in samples {
+        let name = sample
+            .to_str()
+            .ok_or_else(|| format_err!("sample path {:?} is not valid unicode", sample))?;
+
+        let mut parser = MTFParser::with_options(name, MTFOptions::default());
+        let mut snapshot = serde_json::to_string_pretty(&self::snapshot(&mut parser))?;
+        snapshot.push('\n');
+
+        let mut snapshot_path = sample.clone().into_os_string();
+        snapshot_path.push(".json");
+        let snapshot_path = PathBuf::from(snapshot_path);
+
+        match std::fs::read_to_string(&snapshot_path) {
+            Ok(expected) if !update => {
+                if expected != snapshot {
+                    mismatches.push(sample);
+                }
+            }
+            _ => std::fs::write(&snapshot_path, snapshot)?,
+        }
+    }
+
+    Ok(mismatches)
+}
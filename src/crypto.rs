@@ -0,0 +1,55 @@
+use crate::{Result, UnsupportedFeature};
+use std::sync::{Arc, RwLock};
+
+// MTF only defines 0 as no encryption, every other value is vendor specific
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EncryptionAlgorithm {
+    NONE,
+    VENDOR(u16),
+}
+
+impl EncryptionAlgorithm {
+    pub(crate) fn parse(algorithm: u16) -> EncryptionAlgorithm {
+        match algorithm {
+            0 => EncryptionAlgorithm::NONE,
+            algorithm => EncryptionAlgorithm::VENDOR(algorithm),
+        }
+    }
+
+    // Whether the data of streams encrypted with this algorithm can be decrypted
+    pub fn is_supported(&self) -> bool {
+        decryptor(*self).is_some()
+    }
+}
+
+// Decrypts the data of ENCRYPTED streams, for the ciphers of the vendor products.
+// Installed globally like Metrics, StreamWithData::decrypted uses the first one that supports
+// the algorithm of the stream
+pub trait Decryptor: Send + Sync {
+    fn supports(&self, algorithm: EncryptionAlgorithm) -> bool;
+
+    fn decrypt(&self, algorithm: EncryptionAlgorithm, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+static DECRYPTORS: RwLock<Vec<Arc<dyn Decryptor>>> = RwLock::new(Vec::new());
+
+pub fn register_decryptor(decryptor: Arc<dyn Decryptor>) {
+    DECRYPTORS.write().unwrap().push(decryptor);
+}
+
+fn decryptor(algorithm: EncryptionAlgorithm) -> Option<Arc<dyn Decryptor>> {
+    DECRYPTORS
+        .read()
+        .unwrap()
+        .iter()
+        .find(|decryptor| decryptor.supports(algorithm))
+        .cloned()
+}
+
+pub(crate) fn decrypt(algorithm: EncryptionAlgorithm, data: &[u8]) -> Result<Vec<u8>> {
+    let decryptor = decryptor(algorithm).ok_or_else(|| {
+        UnsupportedFeature(format!("no decryptor registered for {:?}", algorithm))
+    })?;
+
+    decryptor.decrypt(algorithm, data)
+}
@@ -0,0 +1,65 @@
+use crate::Result;
+use byteorder::{ByteOrder, LittleEndian};
+use failure::format_err;
+
+#[derive(Debug, Clone)]
+pub struct ExtendedAttribute<'a> {
+    pub name: String,
+    // 0x80 marks attributes the file can't be used correctly without
+    pub flags: u8,
+    pub value: &'a [u8],
+}
+
+// NTEA streams hold FILE_FULL_EA_INFORMATION entries, chained by the offset of the next one
+pub(crate) fn parse_nt(data: &[u8]) -> Result<Vec<ExtendedAttribute<'_>>> {
+    parse_entries(data)
+}
+
+// OS2A streams hold a FEA2LIST, the same entries with the size of the whole list in front
+pub(crate) fn parse_os2(data: &[u8]) -> Result<Vec<ExtendedAttribute<'_>>> {
+    let size = data
+        .get(..4)
+        .map(LittleEndian::read_u32)
+        .ok_or_else(|| format_err!("extended attribute list truncated"))? as usize;
+
+    parse_entries(data.get(4..size.min(data.len())).unwrap_or(&[]))
+}
+
+fn parse_entries(data: &[u8]) -> Result<Vec<ExtendedAttribute<'_>>> {
+    let mut attributes = Vec::new();
+    let mut position = 0;
+
+    while position < data.len() {
+        let header = data
+            .get(position..position + 8)
+            .ok_or_else(|| format_err!("extended attribute truncated at {}", position))?;
+
+        let next = LittleEndian::read_u32(header) as usize;
+        let flags = header[4];
+        let name_length = header[5] as usize;
+        let value_length = LittleEndian::read_u16(&header[6..]) as usize;
+
+        // the name is NUL terminated, the terminator is not part of the length
+        let name_start = position + 8;
+        let value_start = name_start + name_length + 1;
+        let name = data
+            .get(name_start..name_start + name_length)
+            .ok_or_else(|| format_err!("extended attribute name truncated at {}", position))?;
+        let value = data
+            .get(value_start..value_start + value_length)
+            .ok_or_else(|| format_err!("extended attribute value truncated at {}", position))?;
+
+        attributes.push(ExtendedAttribute {
+            name: String::from_utf8_lossy(name).into_owned(),
+            flags,
+            value,
+        });
+
+        if next == 0 {
+            break;
+        }
+        position += next;
+    }
+
+    Ok(attributes)
+}
@@ -0,0 +1,484 @@
+use crate::{
+    DBLKIterator, DBLKSpecific, DBLKWithStreams, MTFOptions, MTFParser, Result, StreamWithData,
+    DBLK,
+};
+use failure::format_err;
+use std::borrow::Cow;
+
+// The media of a media family, ordered by their media sequence number
+#[derive(Debug)]
+pub struct MediaFamily {
+    pub media_family_id: u32,
+    media: Vec<MTFParser>,
+}
+
+impl MediaFamily {
+    pub(crate) fn assemble(paths: &[&str], options: MTFOptions) -> Result<MediaFamily> {
+        let mut media = Vec::new();
+        let mut family_id = None;
+
+        for path in paths {
+            let mut parser = MTFParser::open(path, options.clone())?;
+
+            let (media_family_id, media_sequence_number) = match parser.tape()?.body {
+                DBLKSpecific::TAPE {
+                    media_family_id,
+                    media_sequence_number,
+                    ..
+                } => (media_family_id, media_sequence_number),
+                _ => unreachable!(),
+            };
+
+            match family_id {
+                Some(id) if id != media_family_id => {
+                    return Err(format_err!(
+                        "{} belongs to media family {:#x}, not {:#x}",
+                        path,
+                        media_family_id,
+                        id
+                    ))
+                }
+                _ => family_id = Some(media_family_id),
+            }
+
+            media.push((media_sequence_number, parser));
+        }
+
+        let media_family_id = family_id.ok_or_else(|| format_err!("need at least one medium"))?;
+
+        // sequence numbers start at one and every medium has to be there
+        media.sort_by_key(|(sequence_number, _)| *sequence_number);
+        for (expected, (sequence_number, _)) in (1..).zip(&media) {
+            if *sequence_number != expected {
+                return Err(format_err!(
+                    "expected medium {} of the media family, got {}",
+                    expected,
+                    sequence_number
+                ));
+            }
+        }
+
+        Ok(MediaFamily {
+            media_family_id,
+            media: media.into_iter().map(|(_, parser)| parser).collect(),
+        })
+    }
+
+    pub fn media(&mut self) -> &mut [MTFParser] {
+        &mut self.media
+    }
+
+    // The DBLKs of all media, with the media sequence number. Every medium after the first starts
+    // with its TAPE block and the continuation blocks of the interrupted set, they are yielded
+    // like the other ones
+    pub fn dblks(&mut self) -> FamilyIterator<'_> {
+        FamilyIterator {
+            media: self.media.iter_mut(),
+            current: None,
+            media_sequence_number: 0,
+        }
+    }
+}
+
+pub struct FamilyIterator<'a> {
+    media: std::slice::IterMut<'a, MTFParser>,
+    current: Option<DBLKIterator<'a>>,
+    media_sequence_number: u16,
+}
+
+impl<'a> Iterator for FamilyIterator<'a> {
+    type Item = Result<(u16, DBLKWithStreams<'a>)>;
+
+    // A medium whose blocks can not be read yields its error, the iteration goes on with the
+    // next one
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(dblk) = self.current.as_mut().and_then(Iterator::next) {
+                return Some(Ok((self.media_sequence_number, dblk)));
+            }
+
+            let medium = self.media.next()?;
+            self.media_sequence_number += 1;
+            match medium.dblks() {
+                Ok(dblks) => self.current = Some(dblks),
+                Err(err) => {
+                    self.current = None;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+// A stream that was split over media, its parts in order. Every part after the first has
+// MediaFormatAttributes::CONTINUE set. The parts of MQDA streams can be given to
+// MTFPageProvider::from_streams as they are
+#[derive(Debug)]
+pub struct LogicalStream<'a> {
+    pub parts: Vec<StreamWithData<'a>>,
+}
+
+impl<'a> LogicalStream<'a> {
+    pub fn id(&self) -> &str {
+        self.parts[0].id()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.parts.iter().map(|part| part.data.len() as u64).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Only copied if the stream actually is split
+    pub fn data(&self) -> Cow<'a, [u8]> {
+        match &self.parts[..] {
+            [part] => Cow::Borrowed(part.data),
+            parts => Cow::Owned(parts.iter().flat_map(|part| part.data).copied().collect()),
+        }
+    }
+}
+
+// A DBLK together with its continuation blocks on the following media
+#[derive(Debug)]
+pub struct StitchedDBLK<'a> {
+    // of the medium the DBLK starts on
+    pub media_sequence_number: u16,
+    // the first part, its streams are moved into streams
+    pub dblk: DBLKWithStreams<'a>,
+    pub streams: Vec<LogicalStream<'a>>,
+}
+
+impl MediaFamily {
+    // The DBLKs of all media, with the continuation blocks merged into the blocks they continue.
+    // A medium that was started in the middle of a FILE repeats its SSET, VOLB, DIRB and FILE as
+    // continuation blocks, all but the interrupted one (the last block of the previous medium) only
+    // give context and are dropped. The interrupted one holds the remaining streams, the first one
+    // flagged CONTINUE if it was split, they are added to the block they continue
+    pub fn stitched_dblks(&mut self) -> Result<Vec<StitchedDBLK<'_>>> {
+        let mut stitched: Vec<StitchedDBLK> = Vec::new();
+        // the block streams were last added to and the one cut off by the end of the medium
+        let mut last = None;
+        let mut interrupted = None;
+        let mut current_medium = 0;
+
+        for dblk in self.dblks() {
+            let (media_sequence_number, mut dblk) = dblk?;
+            if media_sequence_number != current_medium {
+                current_medium = media_sequence_number;
+                interrupted = last;
+            }
+
+            match dblk.dblk.body {
+                DBLKSpecific::TAPE { .. }
+                | DBLKSpecific::EOTM { .. }
+                | DBLKSpecific::SFMB { .. } => continue,
+                _ => {}
+            }
+
+            let streams = std::mem::take(&mut dblk.streams);
+
+            if !dblk.dblk.is_continuation() {
+                interrupted = None;
+                last = Some(stitched.len());
+                stitched.push(StitchedDBLK {
+                    media_sequence_number,
+                    dblk,
+                    streams: streams
+                        .into_iter()
+                        .map(|stream| LogicalStream {
+                            parts: vec![stream],
+                        })
+                        .collect(),
+                });
+                continue;
+            }
+
+            let idx = interrupted.ok_or_else(|| {
+                format_err!(
+                    "continuation block {:?} on medium {} does not follow an interrupted block",
+                    dblk.dblk.body,
+                    media_sequence_number
+                )
+            })?;
+            let block = &mut stitched[idx];
+
+            if std::mem::discriminant(&dblk.dblk.body)
+                != std::mem::discriminant(&block.dblk.dblk.body)
+            {
+                // a repeated SSET, VOLB or DIRB above the interrupted block
+                if let Some(stream) = streams.iter().find(|stream| stream.id() != "SPAD") {
+                    return Err(format_err!(
+                        "continuation block {:?} on medium {} has a {} stream, but {:?} was interrupted",
+                        dblk.dblk.body,
+                        media_sequence_number,
+                        stream.id(),
+                        block.dblk.dblk.body
+                    ));
+                }
+                continue;
+            }
+
+            if !continues(&block.dblk.dblk, &dblk.dblk) {
+                return Err(format_err!(
+                    "continuation block {:?} on medium {} does not continue the interrupted {:?}",
+                    dblk.dblk.body,
+                    media_sequence_number,
+                    block.dblk.dblk.body
+                ));
+            }
+
+            // the padding up to the end of the previous medium does not separate the parts
+            if streams.first().is_some_and(StreamWithData::is_continuation)
+                && block.streams.last().is_some_and(|last| last.id() == "SPAD")
+            {
+                block.streams.pop();
+            }
+
+            for stream in streams {
+                match block.streams.last_mut() {
+                    Some(last) if stream.is_continuation() && last.id() == stream.id() => {
+                        last.parts.push(stream)
+                    }
+                    _ => block.streams.push(LogicalStream {
+                        parts: vec![stream],
+                    }),
+                }
+            }
+        }
+
+        Ok(stitched)
+    }
+}
+
+// A continuation block repeats the block it continues, at the same format logical address
+fn continues(interrupted: &DBLK, continuation: &DBLK) -> bool {
+    use DBLKSpecific::*;
+
+    if interrupted.header.format_logical_address == continuation.header.format_logical_address {
+        return true;
+    }
+
+    match (&interrupted.body, &continuation.body) {
+        (
+            FILE {
+                directory_id,
+                file_id,
+                file_name,
+                ..
+            },
+            FILE {
+                directory_id: continued_directory_id,
+                file_id: continued_file_id,
+                file_name: continued_file_name,
+                ..
+            },
+        ) => {
+            directory_id == continued_directory_id
+                && file_id == continued_file_id
+                && file_name == continued_file_name
+        }
+        (
+            DIRB {
+                directory_id, path, ..
+            },
+            DIRB {
+                directory_id: continued_directory_id,
+                path: continued_path,
+                ..
+            },
+        ) => directory_id == continued_directory_id && path == continued_path,
+        (
+            SSET {
+                data_set_number, ..
+            },
+            SSET {
+                data_set_number: continued_data_set_number,
+                ..
+            },
+        ) => data_set_number == continued_data_set_number,
+        (
+            VOLB { device_name, .. },
+            VOLB {
+                device_name: continued_device_name,
+                ..
+            },
+        ) => device_name == continued_device_name,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{FileBuilder, MediumBuilder, SetBuilder, StreamBuilder};
+    use crate::MediaFormatAttributes;
+
+    // assemble opens the media by path, they are removed when dropped
+    struct Media(Vec<String>);
+
+    impl Media {
+        fn write(name: &str, media: &[MediumBuilder]) -> Media {
+            Media(
+                media
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, medium)| {
+                        let path = std::env::temp_dir().join(format!(
+                            "mtf-{}-{}-{}.bkf",
+                            name,
+                            idx,
+                            std::process::id()
+                        ));
+                        std::fs::write(&path, medium.build().unwrap()).unwrap();
+                        path.to_str().unwrap().to_string()
+                    })
+                    .collect(),
+            )
+        }
+
+        fn assemble(&self) -> Result<MediaFamily> {
+            let paths: Vec<&str> = self.0.iter().map(String::as_str).collect();
+            MTFParser::new_family(&paths)
+        }
+    }
+
+    impl Drop for Media {
+        fn drop(&mut self) {
+            for path in &self.0 {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    fn medium(media_family_id: u32, media_sequence_number: u16) -> MediumBuilder {
+        MediumBuilder::new()
+            .media_family_id(media_family_id)
+            .media_sequence_number(media_sequence_number)
+    }
+
+    fn media_sequence_numbers(family: &mut MediaFamily) -> Vec<u16> {
+        family
+            .media()
+            .iter_mut()
+            .map(|parser| match parser.tape().unwrap().body {
+                DBLKSpecific::TAPE {
+                    media_sequence_number,
+                    ..
+                } => media_sequence_number,
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn media_are_ordered() {
+        let media = Media::write("family-order", &[medium(7, 2), medium(7, 1), medium(7, 3)]);
+        let mut family = media.assemble().unwrap();
+        assert_eq!(family.media_family_id, 7);
+        assert_eq!(media_sequence_numbers(&mut family), [1, 2, 3]);
+
+        let media: Vec<_> = family.dblks().map(|dblk| dblk.unwrap().0).collect();
+        assert_eq!(media, [1, 2, 3]);
+    }
+
+    #[test]
+    fn wrong_family() {
+        let media = Media::write("family-wrong", &[medium(7, 1), medium(8, 2)]);
+        let err = media.assemble().unwrap_err().to_string();
+        assert!(err.contains("media family 0x8, not 0x7"), "{}", err);
+    }
+
+    #[test]
+    fn missing_medium() {
+        let media = Media::write("family-gap", &[medium(7, 1), medium(7, 3)]);
+        let err = media.assemble().unwrap_err().to_string();
+        assert!(err.contains("expected medium 2"), "{}", err);
+
+        let media = Media::write("family-no-first", &[medium(7, 2)]);
+        assert!(media.assemble().is_err());
+
+        let media = Media::write("family-twice", &[medium(7, 1), medium(7, 1)]);
+        assert!(media.assemble().is_err());
+
+        assert!(MTFParser::new_family(&[]).is_err());
+    }
+
+    #[test]
+    fn streams_split_over_media() {
+        let set = || SetBuilder::new(1).directory("docs");
+        let media = Media::write(
+            "family-split",
+            &[
+                medium(7, 1).set(
+                    set()
+                        .file(FileBuilder::new("a.txt").data(b"first"))
+                        .file(FileBuilder::new("b.txt").data(b"hello "))
+                        .interrupted(),
+                ),
+                medium(7, 2)
+                    .set(
+                        set().continuation().file(FileBuilder::new("a.txt")).file(
+                            FileBuilder::new("b.txt").stream(
+                                StreamBuilder::new(b"STAN", b"world")
+                                    .media_format_attrs(MediaFormatAttributes::CONTINUE),
+                            ),
+                        ),
+                    )
+                    .set(SetBuilder::new(2).file(FileBuilder::new("c.txt").data(b"last"))),
+            ],
+        );
+        let mut family = media.assemble().unwrap();
+        let stitched = family.stitched_dblks().unwrap();
+
+        let blocks: Vec<_> = stitched
+            .iter()
+            .map(|block| {
+                let name = match &block.dblk.dblk.body {
+                    DBLKSpecific::FILE { file_name, .. } => file_name.clone().unwrap_or_default(),
+                    body => format!("{:?}", std::mem::discriminant(body)),
+                };
+                let data: Vec<_> = block
+                    .streams
+                    .iter()
+                    .filter(|stream| stream.id() == "STAN")
+                    .map(|stream| (stream.parts.len(), stream.data().into_owned()))
+                    .collect();
+                (block.media_sequence_number, name, data)
+            })
+            .filter(|(_, name, _)| name.ends_with(".txt"))
+            .collect();
+
+        assert_eq!(
+            blocks,
+            [
+                (1, "a.txt".to_string(), vec![(1, b"first".to_vec())]),
+                (1, "b.txt".to_string(), vec![(2, b"hello world".to_vec())]),
+                (2, "c.txt".to_string(), vec![(1, b"last".to_vec())]),
+            ]
+        );
+        // the repeated SSET, VOLB and DIRB are dropped, the ESET of the first set is on medium 2
+        let sets: Vec<_> = stitched
+            .iter()
+            .filter_map(|block| match block.dblk.dblk.body {
+                DBLKSpecific::SSET {
+                    data_set_number, ..
+                } => Some((block.media_sequence_number, "SSET", data_set_number)),
+                DBLKSpecific::ESET {
+                    data_set_number, ..
+                } => Some((block.media_sequence_number, "ESET", data_set_number)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            sets,
+            [
+                (1, "SSET", 1),
+                (2, "ESET", 1),
+                (2, "SSET", 2),
+                (2, "ESET", 2)
+            ]
+        );
+    }
+}
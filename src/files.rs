@@ -0,0 +1,681 @@
+use crate::ea::{self, ExtendedAttribute};
+use crate::os_specific::OsSpecificData;
+use crate::security::{guid_to_string, SecurityDescriptor};
+use crate::{
+    split_path, DBLKIterator, DBLKSpecific, DBLKWithStreams, DateTime, FileAttrs,
+    FileSystemAttributes, MTFOptions, MediaFormatAttributes, Result, StreamWithData, StringDecoder,
+    DBLK,
+};
+use bitflags::bitflags;
+use byteorder::{ByteOrder, LittleEndian};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone)]
+pub struct FileDates {
+    pub last_modification: DateTime,
+    pub creation: DateTime,
+    pub backup: DateTime,
+    pub last_access: DateTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateKind {
+    LAST_MODIFICATION,
+    CREATION,
+    BACKUP,
+    LAST_ACCESS,
+}
+
+impl FileDates {
+    pub fn get(&self, kind: DateKind) -> &DateTime {
+        match kind {
+            DateKind::LAST_MODIFICATION => &self.last_modification,
+            DateKind::CREATION => &self.creation,
+            DateKind::BACKUP => &self.backup,
+            DateKind::LAST_ACCESS => &self.last_access,
+        }
+    }
+}
+
+// Selects files by one of their dates, as unix timestamps. start is inclusive, end exclusive,
+// a missing bound is unlimited. Files without a valid date of that kind never match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub kind: DateKind,
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, entry: &FileEntry) -> bool {
+        match entry.dates.get(self.kind).unix_timestamp() {
+            Some(timestamp) => {
+                self.start.is_none_or(|start| timestamp >= start)
+                    && self.end.is_none_or(|end| timestamp < end)
+            }
+            None => false,
+        }
+    }
+}
+
+// Selects files by their FILE attributes: every flag of required has to be set and none of
+// excluded, like skipping hidden and system files. contains also serves as (part of) the
+// selection of the Restorer, which is decided before any data is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttributeFilter {
+    pub required: FileAttrs,
+    pub excluded: FileAttrs,
+}
+
+impl AttributeFilter {
+    pub const SKIP_HIDDEN_SYSTEM: AttributeFilter = AttributeFilter {
+        required: FileAttrs::empty(),
+        excluded: FileAttrs::from_bits_truncate(
+            FileAttrs::HIDDEN.bits() | FileAttrs::SYSTEM.bits(),
+        ),
+    };
+
+    pub fn contains(&self, entry: &FileEntry) -> bool {
+        entry.attributes.contains(self.required) && !entry.attributes.intersects(self.excluded)
+    }
+}
+
+// A FILE block resolved against the VOLB and DIRB it belongs to
+#[derive(Debug)]
+pub struct FileEntry<'a> {
+    pub handle: EntryHandle,
+    // shared by all entries of the volume
+    pub volume: Option<Arc<str>>,
+    // relative to the volume, the last component is the file name
+    pub full_path: PathBuf,
+    pub size: u64,
+    pub dates: FileDates,
+    pub attributes: FileAttrs,
+    pub os_specific: OsSpecificData,
+    pub streams: Vec<StreamWithData<'a>>,
+}
+
+// Identifies an entry independent of any iterator, MTFParser::resolve parses it again. Besides the
+// set and the FILE block it records the VOLB and DIRB the path is resolved from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntryHandle {
+    pub data_set_number: Option<u16>,
+    pub offset: u64,
+    pub(crate) volume: Option<u64>,
+    pub(crate) directory: Option<u64>,
+}
+
+// How names are compared, backups from NTFS and FAT don't distinguish files by case
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MatchPolicy {
+    pub case_insensitive: bool,
+    // compare the NFC normalized names, composed and decomposed characters are equal then
+    pub unicode_normalized: bool,
+}
+
+impl MatchPolicy {
+    pub const EXACT: MatchPolicy = MatchPolicy {
+        case_insensitive: false,
+        unicode_normalized: false,
+    };
+
+    pub const WINDOWS: MatchPolicy = MatchPolicy {
+        case_insensitive: true,
+        unicode_normalized: true,
+    };
+
+    pub(crate) fn key(&self, name: &str) -> String {
+        let name: String = if self.unicode_normalized {
+            name.nfc().collect()
+        } else {
+            name.to_string()
+        };
+
+        if self.case_insensitive {
+            name.to_lowercase()
+        } else {
+            name
+        }
+    }
+
+    pub fn matches(&self, a: &str, b: &str) -> bool {
+        self.key(a) == self.key(b)
+    }
+}
+
+impl<'a> FileEntry<'a> {
+    // Compares against a path relative to the volume, with components separated by / or \
+    pub fn path_matches(&self, path: &str, policy: MatchPolicy) -> bool {
+        let mut expected = path.split(['/', '\\']).filter(|c| !c.is_empty());
+        let mut components = self.full_path.iter();
+
+        loop {
+            match (expected.next(), components.next()) {
+                (None, None) => return true,
+                (Some(expected), Some(component)) => {
+                    if !policy.matches(expected, &component.to_string_lossy()) {
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+
+bitflags! {
+    // Rolled up over the streams of a file, to flag files that won't extract cleanly
+    pub struct StreamStatusAttrs: u8 {
+        const COMPRESSED = 1 << 0;
+        const ENCRYPTED = 1 << 1;
+        const CHECKSUMMED = 1 << 2;
+        const TRUNCATED = 1 << 3;
+    }
+}
+
+// Data of a sparse file, at its offset into the file. Everything in between is a hole
+#[derive(Debug)]
+pub struct SparseBlock<'a> {
+    pub offset: u64,
+    pub data: &'a [u8],
+}
+
+impl<'a> SparseBlock<'a> {
+    // Every SPAR stream holds one block, starting with its u64 offset into the file
+    pub(crate) fn parse(data: &'a [u8]) -> Option<SparseBlock<'a>> {
+        let offset = LittleEndian::read_u64(data.get(..8)?);
+
+        Some(SparseBlock {
+            offset,
+            data: &data[8..],
+        })
+    }
+}
+
+// A named NTFS data stream of a file
+#[derive(Debug)]
+pub struct AlternateStream<'a> {
+    pub name: String,
+    pub data: &'a [u8],
+}
+
+impl<'a> AlternateStream<'a> {
+    // They are stored in ADAT streams, with the name (u32 size in bytes, then UTF-16) in front of the data
+    pub(crate) fn parse(data: &'a [u8]) -> Option<AlternateStream<'a>> {
+        let name_size = LittleEndian::read_u32(data.get(..4)?) as usize;
+        let name = utf16_to_string(data.get(4..4 + name_size)?)?;
+
+        // Win32 names them like :name:$DATA
+        let name = name.trim_start_matches(':').trim_end_matches(":$DATA");
+
+        Some(AlternateStream {
+            name: name.to_string(),
+            data: &data[4 + name_size..],
+        })
+    }
+}
+
+impl<'a> FileEntry<'a> {
+    // From the NACL stream, only present if the file was backed up with its security data
+    pub fn security(&self) -> Result<Option<SecurityDescriptor>> {
+        self.streams
+            .iter()
+            .find(|stream| stream.id() == "NACL")
+            .map(|stream| SecurityDescriptor::parse(stream.data))
+            .transpose()
+    }
+
+    // Of all streams except the SPAD and CSUM ones
+    pub fn stream_status(&self) -> StreamStatusAttrs {
+        let mut status = StreamStatusAttrs::empty();
+
+        for stream in &self.streams {
+            if stream.id() == "SPAD" || stream.id() == "CSUM" {
+                continue;
+            }
+
+            if stream.is_compressed() {
+                status |= StreamStatusAttrs::COMPRESSED;
+            }
+            if stream.is_encrypted() {
+                status |= StreamStatusAttrs::ENCRYPTED;
+            }
+            if stream
+                .media_format_attrs()
+                .contains(MediaFormatAttributes::CHECKSUMED)
+            {
+                status |= StreamStatusAttrs::CHECKSUMMED;
+            }
+            if stream.is_truncated() {
+                status |= StreamStatusAttrs::TRUNCATED;
+            }
+        }
+
+        status
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        self.streams.iter().any(|stream| {
+            stream.id() == "SPAR" || stream.fs_attrs().contains(FileSystemAttributes::IS_SPARSE)
+        })
+    }
+
+    pub fn sparse_blocks(&self) -> Vec<SparseBlock<'a>> {
+        self.streams
+            .iter()
+            .filter(|stream| stream.id() == "SPAR")
+            .filter_map(|stream| SparseBlock::parse(stream.data))
+            .collect()
+    }
+
+    // From the NTEA (NT) or OS2A (OS/2) streams
+    pub fn extended_attributes(&self) -> Result<Vec<ExtendedAttribute<'a>>> {
+        let mut attributes = Vec::new();
+
+        for stream in &self.streams {
+            match stream.id() {
+                "NTEA" => attributes.extend(ea::parse_nt(stream.data)?),
+                "OS2A" => attributes.extend(ea::parse_os2(stream.data)?),
+                _ => {}
+            }
+        }
+
+        Ok(attributes)
+    }
+
+    pub fn alternate_streams(&self) -> Vec<AlternateStream<'a>> {
+        self.streams
+            .iter()
+            .filter(|stream| stream.id() == "ADAT")
+            .filter_map(|stream| AlternateStream::parse(stream.data))
+            .collect()
+    }
+
+    // The NTLK stream names the earlier backed up file (relative to the volume) this is a hard link to
+    pub fn hard_link(&self) -> Option<PathBuf> {
+        let stream = self.streams.iter().find(|stream| stream.id() == "NTLK")?;
+        let target = utf16_to_string(stream.data)?;
+
+        Some(
+            target
+                .split(['\\', '/', '\0'])
+                .filter(|component| !component.is_empty())
+                .collect(),
+        )
+    }
+
+    pub fn object_id(&self) -> Option<ObjectId> {
+        let stream = self.streams.iter().find(|stream| stream.id() == "NTOI")?;
+        ObjectId::parse(stream.data)
+    }
+
+    // The REPARSE_DATA_BUFFER from the NTRP stream
+    pub fn reparse_point(&self) -> Option<ReparsePoint<'a>> {
+        let data = self
+            .streams
+            .iter()
+            .find(|stream| stream.id() == "NTRP")?
+            .data;
+        let tag = LittleEndian::read_u32(data.get(..4)?);
+
+        // symbolic links have an additional flags field before the names
+        let names_start = match tag {
+            ReparsePoint::TAG_MOUNT_POINT => 16,
+            ReparsePoint::TAG_SYMLINK => 20,
+            _ => {
+                return Some(ReparsePoint {
+                    tag,
+                    target: None,
+                    data,
+                })
+            }
+        };
+
+        let names = data.get(names_start..)?;
+        let name = |offset: usize| -> Option<String> {
+            let start = LittleEndian::read_u16(data.get(offset..offset + 2)?) as usize;
+            let length = LittleEndian::read_u16(data.get(offset + 2..offset + 4)?) as usize;
+            utf16_to_string(names.get(start..start + length)?).filter(|name| !name.is_empty())
+        };
+
+        // prefer the print name, the substitute name is in NT namespace (\??\C:\...)
+        let target = name(12).or_else(|| name(8));
+
+        Some(ReparsePoint { tag, target, data })
+    }
+}
+
+// The FILE_OBJECTID_BUFFER from the NTOI stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectId {
+    pub object_id: [u8; 16],
+    // the extended info, the birth ids say where the file was created
+    pub birth_volume_id: Option<[u8; 16]>,
+    pub birth_object_id: Option<[u8; 16]>,
+    pub domain_id: Option<[u8; 16]>,
+}
+
+impl ObjectId {
+    pub(crate) fn parse(data: &[u8]) -> Option<ObjectId> {
+        let guid = |idx: usize| -> Option<[u8; 16]> {
+            let mut guid = [0; 16];
+            guid.copy_from_slice(data.get(idx * 16..(idx + 1) * 16)?);
+            Some(guid)
+        };
+
+        Some(ObjectId {
+            object_id: guid(0)?,
+            birth_volume_id: guid(1),
+            birth_object_id: guid(2),
+            domain_id: guid(3),
+        })
+    }
+
+    pub fn to_guid_string(&self) -> String {
+        guid_to_string(&self.object_id)
+    }
+}
+
+#[derive(Debug)]
+pub struct ReparsePoint<'a> {
+    pub tag: u32,
+    // for mount points (junctions) and symbolic links
+    pub target: Option<String>,
+    pub data: &'a [u8],
+}
+
+impl<'a> ReparsePoint<'a> {
+    pub const TAG_MOUNT_POINT: u32 = 0xa000_0003;
+    pub const TAG_SYMLINK: u32 = 0xa000_000c;
+}
+
+fn utf16_to_string(data: &[u8]) -> Option<String> {
+    String::from_utf16(
+        &data
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<_>>(),
+    )
+    .ok()
+}
+
+pub struct FileIterator<'a> {
+    dblks: DBLKIterator<'a>,
+    resolver: PathResolver,
+}
+
+impl<'a> FileIterator<'a> {
+    pub(crate) fn new(dblks: DBLKIterator<'a>) -> Self {
+        Self {
+            dblks,
+            resolver: PathResolver::default(),
+        }
+    }
+}
+
+// Hands out one shared copy of every name. Large backups repeat the same volume and directory
+// names millions of times, the entries and directories of the sets only hold references to them
+#[derive(Debug, Default)]
+pub struct Interner {
+    names: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, name: &str) -> Arc<str> {
+        if let Some(name) = self.names.get(name) {
+            return name.clone();
+        }
+
+        let name: Arc<str> = name.into();
+        self.names.insert(name.clone());
+        name
+    }
+
+    // Number of distinct names
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+// Keeps track of the current VOLB and DIRB, to know where the following FILE blocks belong
+#[derive(Debug, Default)]
+pub(crate) struct PathResolver {
+    volume: Option<Arc<str>>,
+    directory: Vec<Arc<str>>,
+    names: Interner,
+    // offsets of the current VOLB and DIRB
+    volume_offset: Option<u64>,
+    directory_offset: Option<u64>,
+}
+
+// Names that don't fit into the DBLK are stored in a stream instead, using the string type of the DBLK
+fn stream_string(dblk: &DBLKWithStreams, id: &str, options: &MTFOptions) -> Option<String> {
+    let stream = dblk.streams.iter().find(|stream| stream.id() == id)?;
+
+    StringDecoder::new(&dblk.dblk.header, options)
+        .bytes_to_string(stream.data.to_vec())
+        .ok()
+}
+
+impl PathResolver {
+    // The path of the current directory, relative to the volume
+    pub(crate) fn directory(&self) -> &[Arc<str>] {
+        &self.directory
+    }
+
+    pub(crate) fn resolve<'a>(
+        &mut self,
+        dblk: DBLKWithStreams<'a>,
+        options: &MTFOptions,
+    ) -> Option<FileEntry<'a>> {
+        match &dblk.dblk.body {
+            DBLKSpecific::VOLB {
+                volume_name,
+                device_name,
+                ..
+            } => {
+                self.volume = volume_name
+                    .as_deref()
+                    .or(device_name.as_deref())
+                    .map(|name| self.names.intern(name));
+                self.directory.clear();
+                self.volume_offset = Some(dblk.offset);
+                self.directory_offset = None;
+                None
+            }
+            DBLKSpecific::DIRB { path, .. } => {
+                let path = match path {
+                    Some(path) => path.clone(),
+                    None => stream_string(&dblk, "PNAM", options)
+                        .map(|name| split_path(&name))
+                        .unwrap_or_default(),
+                };
+                self.directory = path.iter().map(|name| self.names.intern(name)).collect();
+                self.directory_offset = Some(dblk.offset);
+                None
+            }
+            DBLKSpecific::FILE {
+                attrs,
+                last_modification_date,
+                creation_date,
+                backup_date,
+                last_access_date,
+                file_name,
+                ..
+            } => {
+                let name = file_name
+                    .clone()
+                    .or_else(|| stream_string(&dblk, "FNAM", options))
+                    .unwrap_or_default();
+
+                let mut full_path: PathBuf = self.directory.iter().map(|name| &**name).collect();
+                full_path.push(name.trim_end_matches('\0'));
+
+                let dates = FileDates {
+                    last_modification: last_modification_date.clone(),
+                    creation: creation_date.clone(),
+                    backup: backup_date.clone(),
+                    last_access: last_access_date.clone(),
+                };
+
+                let data_set_number = match dblk.context.set.as_deref() {
+                    Some(DBLK {
+                        body:
+                            DBLKSpecific::SSET {
+                                data_set_number, ..
+                            },
+                        ..
+                    }) => Some(*data_set_number),
+                    _ => None,
+                };
+                let handle = EntryHandle {
+                    data_set_number,
+                    offset: dblk.offset,
+                    volume: self.volume_offset,
+                    directory: self.directory_offset,
+                };
+
+                Some(FileEntry {
+                    handle,
+                    volume: self.volume.clone(),
+                    full_path,
+                    size: dblk.dblk.header.display_size,
+                    dates,
+                    attributes: *attrs,
+                    os_specific: dblk.dblk.header.os_specific.clone(),
+                    streams: dblk.streams,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> FileIterator<'a> {
+    pub fn within(self, window: TimeWindow) -> impl Iterator<Item = FileEntry<'a>> {
+        self.filter(move |entry| window.contains(entry))
+    }
+
+    // Only the FILE block is looked at, the streams of the files left out are never read
+    pub fn with_attributes(self, filter: AttributeFilter) -> impl Iterator<Item = FileEntry<'a>> {
+        self.filter(move |entry| filter.contains(entry))
+    }
+}
+
+// A file with everything known about where it is, denormalized into one flat row for databases
+// and search indexes
+#[derive(Debug, Clone, Serialize)]
+pub struct FileRecord {
+    pub media_family_id: Option<u32>,
+    // the media id of the media label
+    pub media_id: Option<String>,
+    pub data_set_number: Option<u16>,
+    pub data_set_name: Option<String>,
+    pub volume: Option<String>,
+    pub path: String,
+    pub size: u64,
+    // unix timestamps
+    pub last_modification: Option<i64>,
+    pub creation: Option<i64>,
+    pub backup: Option<i64>,
+    pub last_access: Option<i64>,
+    pub attributes: u32,
+    // of the FILE, VOLB and DIRB
+    pub offset: u64,
+    pub volume_offset: Option<u64>,
+    pub directory_offset: Option<u64>,
+}
+
+pub struct FileRecords<'a> {
+    dblks: DBLKIterator<'a>,
+    resolver: PathResolver,
+}
+
+impl<'a> FileRecords<'a> {
+    pub(crate) fn new(dblks: DBLKIterator<'a>) -> Self {
+        Self {
+            dblks,
+            resolver: PathResolver::default(),
+        }
+    }
+}
+
+impl<'a> Iterator for FileRecords<'a> {
+    type Item = FileRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let dblk = self.dblks.next()?;
+            let context = dblk.context.clone();
+
+            let entry = match self.resolver.resolve(dblk, self.dblks.options) {
+                Some(entry) => entry,
+                None => continue,
+            };
+
+            let (media_family_id, media_id) = match context.tape.as_deref() {
+                Some(DBLK {
+                    body:
+                        DBLKSpecific::TAPE {
+                            media_family_id,
+                            media_label,
+                            ..
+                        },
+                    ..
+                }) => (
+                    Some(*media_family_id),
+                    media_label.as_ref().map(|label| label.media_id.clone()),
+                ),
+                _ => (None, None),
+            };
+
+            let data_set_name = match context.set.as_deref() {
+                Some(DBLK {
+                    body: DBLKSpecific::SSET { data_set_name, .. },
+                    ..
+                }) => data_set_name.clone(),
+                _ => None,
+            };
+
+            return Some(FileRecord {
+                media_family_id,
+                media_id,
+                data_set_number: entry.handle.data_set_number,
+                data_set_name,
+                volume: entry.volume.as_deref().map(str::to_string),
+                path: entry.full_path.to_string_lossy().into_owned(),
+                size: entry.size,
+                last_modification: entry.dates.last_modification.unix_timestamp(),
+                creation: entry.dates.creation.unix_timestamp(),
+                backup: entry.dates.backup.unix_timestamp(),
+                last_access: entry.dates.last_access.unix_timestamp(),
+                attributes: entry.attributes.bits(),
+                offset: entry.handle.offset,
+                volume_offset: entry.handle.volume,
+                directory_offset: entry.handle.directory,
+            });
+        }
+    }
+}
+
+impl<'a> Iterator for FileIterator<'a> {
+    type Item = FileEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let dblk = self.dblks.next()?;
+
+            if let Some(entry) = self.resolver.resolve(dblk, self.dblks.options) {
+                return Some(entry);
+            }
+        }
+    }
+}
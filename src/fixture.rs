@@ -0,0 +1,1651 @@
+use crate::{
+    CommonBlockAttrsTAPE, DateTime, FileAttrs, MediaFormatAttributes, Result, SSetAttrs, TapeAttrs,
+    VolbAttrs,
+};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use failure::format_err;
+use mdf::PAGE_SIZE;
+use std::io::Write;
+
+const FORMAT_LOGICAL_BLOCK_SIZE: usize = 1024;
+const SOFT_FILEMARK_BLOCK_SIZE: usize = 512;
+const HEADER_SIZE: usize = 52;
+const STREAM_HEADER_SIZE: usize = 22;
+const FRAME_HEADER_SIZE: usize = 22;
+// the part of an FDD entry in front of its body
+const FDD_HEADER_SIZE: usize = 36;
+
+// Writes a minimal medium (TAPE, SSET and ESET) that carries database pages in an MQDA stream of
+// the SSET, to test MTFPageProvider without shipping real backups
+#[derive(Debug, Clone, Default)]
+pub struct MQDABuilder {
+    pages: Vec<Vec<u8>>,
+    database_name: Option<String>,
+    // logical and physical name
+    files: Vec<(String, String)>,
+    attrs: u32,
+}
+
+impl MQDABuilder {
+    pub fn new() -> MQDABuilder {
+        MQDABuilder::default()
+    }
+
+    // Pages are stored in the order they are added and have to be PAGE_SIZE bytes
+    pub fn page(mut self, page: &[u8]) -> MQDABuilder {
+        self.pages.push(page.to_vec());
+        self
+    }
+
+    pub fn pages<'p, I: IntoIterator<Item = &'p [u8]>>(mut self, pages: I) -> MQDABuilder {
+        self.pages.extend(pages.into_iter().map(<[u8]>::to_vec));
+        self
+    }
+
+    // Written as UTF-16 into an MSCI stream in front of the MQDA, like SQL Server does, so
+    // MTFPageProvider::open_database_by_name finds the pages
+    pub fn database_name(mut self, name: &str) -> MQDABuilder {
+        self.database_name = Some(name.to_string());
+        self
+    }
+
+    // Of the SSET, which tell the backup type
+    pub fn attrs(mut self, attrs: SSetAttrs) -> MQDABuilder {
+        self.attrs = attrs.bits();
+        self
+    }
+
+    // Written into an MQCI stream in the order they are added, the data files first by file
+    // id and then the logs, like SQL Server does, so SqlFileList finds the names
+    pub fn file(mut self, logical_name: &str, physical_name: &str) -> MQDABuilder {
+        self.files
+            .push((logical_name.to_string(), physical_name.to_string()));
+        self
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>> {
+        if self.pages.is_empty() {
+            return Err(format_err!("need at least one page"));
+        }
+        if let Some(idx) = self.pages.iter().position(|page| page.len() != PAGE_SIZE) {
+            return Err(format_err!(
+                "page {} has {} bytes instead of {}",
+                idx,
+                self.pages[idx].len(),
+                PAGE_SIZE
+            ));
+        }
+
+        let mut out = Vec::new();
+        write_dblk(&mut out, b"TAPE", &tape()?, &[]);
+
+        let mut sset = Vec::new();
+        sset.write_u32::<LittleEndian>(self.attrs)?;
+        sset.write_u16::<LittleEndian>(0)?; // password encryption algorithm
+        sset.write_u16::<LittleEndian>(0)?; // software compression algorithm
+        sset.write_u16::<LittleEndian>(0)?; // software vendor id
+        sset.write_u16::<LittleEndian>(1)?; // data set number
+        sset.write_all(&[0; 16])?; // name, description, password and user name
+        sset.write_u64::<LittleEndian>(0)?; // physical block address
+        sset.write_all(&[0; 5])?; // write date
+        sset.write_all(&[0; 5])?; // software versions, time zone, minor and catalog version
+
+        let msci: Option<Vec<u8>> = self
+            .database_name
+            .as_ref()
+            .map(|name| name.encode_utf16().flat_map(u16::to_le_bytes).collect());
+
+        // every file as a small binary header and the names as NUL padded UTF-16 fields
+        let mut mqci = Vec::new();
+        for (idx, (logical_name, physical_name)) in self.files.iter().enumerate() {
+            mqci.write_u32::<LittleEndian>(idx as u32 + 1)?;
+            mqci.write_u32::<LittleEndian>(0)?;
+            for (name, units) in [(logical_name, 128), (physical_name, 260)] {
+                let mut field: Vec<u16> = name.encode_utf16().collect();
+                if field.len() >= units {
+                    return Err(format_err!("file name {:?} is too long", name));
+                }
+                field.resize(units, 0);
+                for unit in field {
+                    mqci.write_u16::<LittleEndian>(unit)?;
+                }
+            }
+        }
+
+        // the provider skips two bytes at the start of the stream
+        let mut mqda = vec![0; 2];
+        for page in &self.pages {
+            mqda.extend_from_slice(page);
+        }
+
+        let mut streams: Vec<(&[u8; 4], &[u8])> = Vec::new();
+        if let Some(msci) = &msci {
+            streams.push((b"MSCI", msci));
+        }
+        if !mqci.is_empty() {
+            streams.push((b"MQCI", &mqci));
+        }
+        streams.push((b"MQDA", &mqda));
+        write_dblk(&mut out, b"SSET", &sset, &streams);
+
+        let mut eset = Vec::new();
+        eset.write_u32::<LittleEndian>(0)?; // attributes
+        eset.write_u32::<LittleEndian>(0)?; // number of corrupt files
+        eset.write_u64::<LittleEndian>(0)?; // set map pba
+        eset.write_u64::<LittleEndian>(0)?; // fdd pba
+        eset.write_u16::<LittleEndian>(0)?; // fdd media sequence number
+        eset.write_u16::<LittleEndian>(1)?; // data set number
+        eset.write_all(&[0; 5])?; // media write date
+        write_dblk(&mut out, b"ESET", &eset, &[]);
+
+        Ok(out)
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> Result<()> {
+        out.write_all(&self.build()?)?;
+        Ok(())
+    }
+}
+
+// Writes a medium (TAPE, then an SSET, VOLB, DIRB and FILE blocks and an ESET for every set) with
+// files and their streams, to test reading and restoring files without shipping real backups
+#[derive(Debug, Clone)]
+pub struct MediumBuilder {
+    media_family_id: u32,
+    media_sequence_number: u16,
+    // the media based catalog type
+    catalog: u16,
+    soft_filemarks: bool,
+    sets: Vec<SetBuilder>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SetBuilder {
+    data_set_number: u16,
+    attrs: SSetAttrs,
+    name: Option<String>,
+    password: Option<String>,
+    compression_algorithm: u16,
+    continuation: bool,
+    interrupted: bool,
+    entries: Vec<SetEntry>,
+}
+
+#[derive(Debug, Clone)]
+enum SetEntry {
+    VOLUME(String),
+    DIRECTORY(String),
+    FILE(FileBuilder),
+}
+
+#[derive(Debug, Clone)]
+pub struct FileBuilder {
+    name: String,
+    attrs: FileAttrs,
+    modified: DateTime,
+    size: Option<u64>,
+    streams: Vec<StreamBuilder>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamBuilder {
+    id: [u8; 4],
+    media_format_attrs: MediaFormatAttributes,
+    encryption_algorithm: u16,
+    compression_algorithm: u16,
+    // written to the header instead of the length of data, to truncate the stream
+    length: Option<u64>,
+    data: Vec<u8>,
+}
+
+impl Default for MediumBuilder {
+    fn default() -> MediumBuilder {
+        MediumBuilder {
+            media_family_id: 1,
+            media_sequence_number: 1,
+            catalog: 0,
+            soft_filemarks: false,
+            sets: Vec::new(),
+        }
+    }
+}
+
+impl MediumBuilder {
+    pub fn new() -> MediumBuilder {
+        MediumBuilder::default()
+    }
+
+    pub fn media_family_id(mut self, media_family_id: u32) -> MediumBuilder {
+        self.media_family_id = media_family_id;
+        self
+    }
+
+    pub fn media_sequence_number(mut self, media_sequence_number: u16) -> MediumBuilder {
+        self.media_sequence_number = media_sequence_number;
+        self
+    }
+
+    // Writes a type 1 media based catalog, the TFDD of every set goes into its ESET
+    pub fn catalog(mut self) -> MediumBuilder {
+        self.catalog = 1;
+        self
+    }
+
+    // Writes a type 2 media based catalog. The FDD2 of every set and a MAP2 set map of all sets
+    // so far go into its ESET, the TAPE block has SET_MAP_EXISTS
+    pub fn set_map_catalog(mut self) -> MediumBuilder {
+        self.catalog = 2;
+        self
+    }
+
+    // Writes an SFMB after every set, which lists all of them so far, the most recent first
+    pub fn soft_filemarks(mut self) -> MediumBuilder {
+        self.soft_filemarks = true;
+        self
+    }
+
+    // Sets are written in the order they are added
+    pub fn set(mut self, set: SetBuilder) -> MediumBuilder {
+        self.sets.push(set);
+        self
+    }
+
+    pub fn build(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let attrs = if self.catalog == 2 {
+            CommonBlockAttrsTAPE::SET_MAP_EXISTS.bits()
+        } else {
+            0
+        };
+        write_block(
+            &mut out,
+            b"TAPE",
+            attrs,
+            0,
+            &tape_of(
+                self.media_family_id,
+                self.media_sequence_number,
+                self.catalog,
+                self.soft_filemarks,
+            )?,
+            &[],
+        );
+
+        let mut summaries = Vec::new();
+        let mut filemarks = Vec::new();
+        for set in &self.sets {
+            set.write_to(&mut out, self, &mut summaries)?;
+
+            if self.soft_filemarks {
+                filemarks.insert(0, (out.len() / SOFT_FILEMARK_BLOCK_SIZE) as u32);
+                write_sfmb(&mut out, &filemarks)?;
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn write<W: Write>(&self, mut out: W) -> Result<()> {
+        out.write_all(&self.build()?)?;
+        Ok(())
+    }
+}
+
+impl SetBuilder {
+    pub fn new(data_set_number: u16) -> SetBuilder {
+        SetBuilder {
+            data_set_number,
+            attrs: SSetAttrs::NORMAL,
+            name: None,
+            password: None,
+            compression_algorithm: 0,
+            continuation: false,
+            interrupted: false,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn attrs(mut self, attrs: SSetAttrs) -> SetBuilder {
+        self.attrs = attrs;
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> SetBuilder {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    pub fn password(mut self, password: &str) -> SetBuilder {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    // The software compression algorithm of the SSET, for streams that don't name one
+    pub fn compression_algorithm(mut self, algorithm: u16) -> SetBuilder {
+        self.compression_algorithm = algorithm;
+        self
+    }
+
+    // Writes all blocks but the ESET as continuation blocks, like at the start of a medium that
+    // continues the set. Only the last file was interrupted, the ones before it are skipped but
+    // keep their ids. Its streams are the rest of the interrupted one
+    pub fn continuation(mut self) -> SetBuilder {
+        self.continuation = true;
+        self
+    }
+
+    // Ends the set with an EOTM instead of an ESET, like at the end of a medium that the next
+    // one continues. It has to be the last set on the medium
+    pub fn interrupted(mut self) -> SetBuilder {
+        self.interrupted = true;
+        self
+    }
+
+    // Starts a volume with the given device name. Directories and files added before the first
+    // one go to a C: volume
+    pub fn volume(mut self, device_name: &str) -> SetBuilder {
+        self.entries.push(SetEntry::VOLUME(device_name.to_string()));
+        self
+    }
+
+    // Files added after this go into the directory, its components are separated by /. An empty
+    // path is the root directory of the volume
+    pub fn directory(mut self, path: &str) -> SetBuilder {
+        self.entries.push(SetEntry::DIRECTORY(path.to_string()));
+        self
+    }
+
+    pub fn file(mut self, file: FileBuilder) -> SetBuilder {
+        self.entries.push(SetEntry::FILE(file));
+        self
+    }
+
+    fn write_to(
+        &self,
+        out: &mut Vec<u8>,
+        medium: &MediumBuilder,
+        summaries: &mut Vec<SetSummary>,
+    ) -> Result<()> {
+        let sset_offset = out.len();
+        let mut strings = Strings::new(46);
+        let name = strings.add(self.name.as_deref());
+        let password = strings.add(self.password.as_deref());
+
+        let mut sset = Vec::new();
+        sset.write_u32::<LittleEndian>(self.attrs.bits())?;
+        sset.write_u16::<LittleEndian>(0)?; // password encryption algorithm
+        sset.write_u16::<LittleEndian>(self.compression_algorithm)?;
+        sset.write_u16::<LittleEndian>(0)?; // software vendor id
+        sset.write_u16::<LittleEndian>(self.data_set_number)?;
+        sset.write_u32::<LittleEndian>(name)?;
+        sset.write_u32::<LittleEndian>(0)?; // description
+        sset.write_u32::<LittleEndian>(password)?;
+        sset.write_u32::<LittleEndian>(0)?; // user name
+        sset.write_u64::<LittleEndian>(0)?; // physical block address
+        sset.write_all(&pack_date(&DEFAULT_DATE))?; // write date
+        sset.write_all(&[0; 5])?; // software versions, time zone, minor and catalog version
+        sset.write_all(&strings.data)?;
+        let attrs = self.continuation as u32;
+        write_block(out, b"SSET", attrs, 0, &sset, &[]);
+
+        let mut fdd = FddWriter {
+            media_sequence_number: medium.media_sequence_number,
+            data: Vec::new(),
+        };
+        let mut in_volume = false;
+        let mut directory_id = 0;
+        let mut file_id = 0;
+        let mut volumes = Vec::new();
+        let mut data_set_size = 0;
+        let last_file = self
+            .entries
+            .iter()
+            .rposition(|entry| matches!(entry, SetEntry::FILE(_)));
+
+        for (idx, entry) in self.entries.iter().enumerate() {
+            if !in_volume && !matches!(entry, SetEntry::VOLUME(_)) {
+                volumes.push("C:".to_string());
+                fdd.volb(out.len(), "C:")?;
+                write_volb(out, attrs, "C:")?;
+            }
+            in_volume = true;
+
+            match entry {
+                SetEntry::VOLUME(device_name) => {
+                    volumes.push(device_name.clone());
+                    fdd.volb(out.len(), device_name)?;
+                    write_volb(out, attrs, device_name)?;
+                }
+                SetEntry::DIRECTORY(path) => {
+                    directory_id += 1;
+                    fdd.dirb(out.len(), path)?;
+                    write_dirb(out, attrs, directory_id, path)?;
+                }
+                SetEntry::FILE(_) if self.continuation && Some(idx) != last_file => file_id += 1,
+                SetEntry::FILE(file) => {
+                    file_id += 1;
+                    data_set_size += file.display_size();
+                    fdd.file(out.len(), file)?;
+                    file.write_to(out, attrs, directory_id, file_id)?;
+                }
+            }
+        }
+
+        if self.interrupted {
+            // no ESET on this medium
+            let mut eotm = Vec::new();
+            eotm.write_u64::<LittleEndian>(0)?;
+            write_dblk(out, b"EOTM", &eotm, &[]);
+            return Ok(());
+        }
+
+        // the FDD and the set map are in the ESET
+        let eset_pba = (out.len() / FORMAT_LOGICAL_BLOCK_SIZE) as u64;
+        summaries.push(SetSummary {
+            data_set_number: self.data_set_number,
+            attrs: self.attrs,
+            name: self.name.clone(),
+            sset_pba: (sset_offset / FORMAT_LOGICAL_BLOCK_SIZE) as u64,
+            fdd_pba: if medium.catalog == 2 { eset_pba } else { 0 },
+            media_sequence_number: medium.media_sequence_number,
+            directories: directory_id,
+            files: file_id,
+            data_set_size,
+            volumes,
+        });
+
+        let mut streams = Vec::new();
+        match medium.catalog {
+            1 => streams.push(StreamBuilder::new(b"TFDD", &fdd.finish()?)),
+            2 => {
+                streams.push(StreamBuilder::new(b"FDD2", &fdd.finish()?));
+                streams.push(StreamBuilder::new(
+                    b"MAP2",
+                    &set_map(medium.media_family_id, summaries)?,
+                ));
+            }
+            _ => {}
+        }
+
+        let set_map_pba = if medium.catalog == 2 { eset_pba } else { 0 };
+        let mut eset = Vec::new();
+        eset.write_u32::<LittleEndian>(self.attrs.bits())?;
+        eset.write_u32::<LittleEndian>(0)?; // number of corrupt files
+        eset.write_u64::<LittleEndian>(set_map_pba)?;
+        eset.write_u64::<LittleEndian>(summaries.last().unwrap().fdd_pba)?;
+        eset.write_u16::<LittleEndian>(0)?; // fdd media sequence number
+        eset.write_u16::<LittleEndian>(self.data_set_number)?;
+        eset.write_all(&pack_date(&DEFAULT_DATE))?; // media write date
+        write_block(out, b"ESET", 0, 0, &eset, &streams);
+
+        Ok(())
+    }
+}
+
+// What the set map tells about a set
+struct SetSummary {
+    data_set_number: u16,
+    attrs: SSetAttrs,
+    name: Option<String>,
+    sset_pba: u64,
+    fdd_pba: u64,
+    media_sequence_number: u16,
+    directories: u32,
+    files: u32,
+    data_set_size: u64,
+    volumes: Vec<String>,
+}
+
+// Every entry is followed by its volume entries and then the strings of both, addressed from
+// the start of the entry or the volume entry
+fn set_map(media_family_id: u32, summaries: &[SetSummary]) -> Result<Vec<u8>> {
+    const ENTRY_SIZE: usize = 82;
+    const VOLUME_SIZE: usize = 25;
+
+    let mut map = Vec::new();
+    map.write_u32::<LittleEndian>(media_family_id)?;
+    map.write_u16::<LittleEndian>(summaries.len() as u16)?;
+    map.write_u16::<LittleEndian>(0)?;
+
+    for summary in summaries {
+        let volumes_size = VOLUME_SIZE * summary.volumes.len();
+        let mut strings = Strings::at(ENTRY_SIZE + volumes_size);
+        let name = strings.add(summary.name.as_deref());
+
+        let mut volumes = Vec::new();
+        for (idx, device_name) in summary.volumes.iter().enumerate() {
+            // strings.add addresses from the start of the entry
+            let address =
+                strings.add(Some(device_name)) - (((ENTRY_SIZE + VOLUME_SIZE * idx) as u32) << 16);
+
+            volumes.write_u16::<LittleEndian>(VOLUME_SIZE as u16)?;
+            volumes.write_u32::<LittleEndian>(VolbAttrs::DEV_DRIVE.bits())?;
+            volumes.write_u16::<LittleEndian>(summary.media_sequence_number)?;
+            volumes.write_u32::<LittleEndian>(address)?;
+            volumes.write_u32::<LittleEndian>(0)?; // volume name
+            volumes.write_u32::<LittleEndian>(0)?; // machine name
+            volumes.write_all(&pack_date(&DEFAULT_DATE))?;
+        }
+
+        let length = ENTRY_SIZE + volumes_size + strings.data.len();
+        map.write_u16::<LittleEndian>(length as u16)?;
+        map.write_u16::<LittleEndian>(summary.volumes.len() as u16)?;
+        map.write_u32::<LittleEndian>(summary.attrs.bits())?;
+        map.write_u16::<LittleEndian>(0)?; // password encryption algorithm
+        map.write_u16::<LittleEndian>(0)?; // software compression algorithm
+        map.write_u16::<LittleEndian>(0)?; // software vendor id
+        map.write_u16::<LittleEndian>(summary.data_set_number)?;
+        map.write_u32::<LittleEndian>(name)?;
+        map.write_all(&[0; 12])?; // description, password and user name
+        map.write_u64::<LittleEndian>(summary.sset_pba)?;
+        map.write_u64::<LittleEndian>(summary.fdd_pba)?;
+        map.write_u16::<LittleEndian>(summary.media_sequence_number)?; // of the fdd
+        map.write_u16::<LittleEndian>(summary.media_sequence_number)?;
+        map.write_u32::<LittleEndian>(summary.directories)?;
+        map.write_u32::<LittleEndian>(summary.files)?;
+        map.write_u32::<LittleEndian>(0)?; // number of corrupt files
+        map.write_u64::<LittleEndian>(summary.data_set_size)?;
+        map.write_all(&pack_date(&DEFAULT_DATE))?;
+        map.write_all(&[0; 5])?; // software versions, time zone, minor and catalog version
+        map.write_all(&volumes)?;
+        map.write_all(&strings.data)?;
+    }
+
+    Ok(map)
+}
+
+// An SFMB fills one soft filemark block, its entries are the physical block addresses of the
+// soft filemarks in soft filemark blocks
+fn write_sfmb(out: &mut Vec<u8>, filemarks: &[u32]) -> Result<()> {
+    let number_of_entries = (SOFT_FILEMARK_BLOCK_SIZE - HEADER_SIZE - 8) / 4;
+
+    let mut sfmb = Vec::new();
+    sfmb.write_u32::<LittleEndian>(number_of_entries as u32)?;
+    sfmb.write_u32::<LittleEndian>(filemarks.len() as u32)?;
+    for idx in 0..number_of_entries {
+        sfmb.write_u32::<LittleEndian>(filemarks.get(idx).copied().unwrap_or_default())?;
+    }
+    write_dblk(out, b"SFMB", &sfmb, &[]);
+
+    Ok(())
+}
+
+// The entries of a TFDD, with the format logical addresses of the blocks they describe
+struct FddWriter {
+    media_sequence_number: u16,
+    data: Vec<u8>,
+}
+
+impl FddWriter {
+    fn volb(&mut self, offset: usize, device_name: &str) -> Result<()> {
+        let mut strings = Strings::at(FDD_HEADER_SIZE + 29);
+        let device_name = strings.add(Some(device_name));
+
+        let mut volb = Vec::new();
+        volb.write_u32::<LittleEndian>(VolbAttrs::DEV_DRIVE.bits())?;
+        volb.write_u32::<LittleEndian>(device_name)?;
+        volb.write_u32::<LittleEndian>(0)?; // volume name
+        volb.write_u32::<LittleEndian>(0)?; // machine name
+        volb.write_u32::<LittleEndian>(0)?; // os specific data
+        volb.write_all(&pack_date(&DEFAULT_DATE))?; // write date
+        self.entry(b"VOLB", offset, 0, &volb, strings)
+    }
+
+    fn dirb(&mut self, offset: usize, path: &str) -> Result<()> {
+        let mut strings = Strings::at(FDD_HEADER_SIZE + 32);
+        let name = strings.add(Some(&dirb_name(path)));
+
+        let mut dirb = Vec::new();
+        dirb.write_u32::<LittleEndian>(0)?; // attributes
+        dirb.write_all(&pack_date(&DEFAULT_DATE))?; // modification date
+        dirb.write_all(&[0; 15])?; // creation, backup and access date
+        dirb.write_u32::<LittleEndian>(name)?;
+        dirb.write_u32::<LittleEndian>(0)?; // os specific data
+        self.entry(b"DIRB", offset, 0, &dirb, strings)
+    }
+
+    fn file(&mut self, offset: usize, file: &FileBuilder) -> Result<()> {
+        let mut strings = Strings::at(FDD_HEADER_SIZE + 32);
+        let name = strings.add(Some(&file.name));
+
+        let mut body = Vec::new();
+        body.write_u32::<LittleEndian>(file.attrs.bits())?;
+        body.write_all(&pack_date(&file.modified))?;
+        body.write_all(&[0; 15])?; // creation, backup and access date
+        body.write_u32::<LittleEndian>(name)?;
+        body.write_u32::<LittleEndian>(0)?; // os specific data
+        self.entry(b"FILE", offset, file.display_size(), &body, strings)
+    }
+
+    fn entry(
+        &mut self,
+        id: &[u8; 4],
+        offset: usize,
+        display_size: u64,
+        body: &[u8],
+        strings: Strings,
+    ) -> Result<()> {
+        let length = FDD_HEADER_SIZE + body.len() + strings.data.len();
+        self.data.write_u16::<LittleEndian>(length as u16)?;
+        self.data.write_all(id)?;
+        self.data
+            .write_u16::<LittleEndian>(self.media_sequence_number)?;
+        self.data.write_u32::<LittleEndian>(0)?; // common block attributes
+        self.data
+            .write_u64::<LittleEndian>((offset / FORMAT_LOGICAL_BLOCK_SIZE) as u64)?;
+        self.data.write_u64::<LittleEndian>(display_size)?;
+        self.data.write_u32::<LittleEndian>(0)?; // link
+        self.data.write_all(&[14, 1, 1, 0])?; // Windows NT, ANSI strings
+        self.data.write_all(body)?;
+        self.data.write_all(&strings.data)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>> {
+        self.data.write_u16::<LittleEndian>(0)?;
+        self.data.write_all(b"FEND")?;
+        Ok(self.data)
+    }
+}
+
+impl FileBuilder {
+    pub fn new(name: &str) -> FileBuilder {
+        FileBuilder {
+            name: name.to_string(),
+            attrs: FileAttrs::empty(),
+            modified: DEFAULT_DATE,
+            size: None,
+            streams: Vec::new(),
+        }
+    }
+
+    pub fn attrs(mut self, attrs: FileAttrs) -> FileBuilder {
+        self.attrs = attrs;
+        self
+    }
+
+    // The last modification date, the other dates are left unset
+    pub fn modified(mut self, date: DateTime) -> FileBuilder {
+        self.modified = date;
+        self
+    }
+
+    // The display size of the FILE, by default the length of all STAN streams
+    pub fn size(mut self, size: u64) -> FileBuilder {
+        self.size = Some(size);
+        self
+    }
+
+    // Adds a plain STAN stream
+    pub fn data(self, data: &[u8]) -> FileBuilder {
+        self.stream(StreamBuilder::new(b"STAN", data))
+    }
+
+    // Streams are written in the order they are added
+    pub fn stream(mut self, stream: StreamBuilder) -> FileBuilder {
+        self.streams.push(stream);
+        self
+    }
+
+    fn write_to(
+        &self,
+        out: &mut Vec<u8>,
+        attrs: u32,
+        directory_id: u32,
+        file_id: u32,
+    ) -> Result<()> {
+        let mut strings = Strings::new(36);
+        let name = strings.add(Some(&self.name));
+
+        let mut file = Vec::new();
+        file.write_u32::<LittleEndian>(self.attrs.bits())?;
+        file.write_all(&pack_date(&self.modified))?;
+        file.write_all(&[0; 15])?; // creation, backup and access date
+        file.write_u32::<LittleEndian>(directory_id)?;
+        file.write_u32::<LittleEndian>(file_id)?;
+        file.write_u32::<LittleEndian>(name)?;
+        file.write_all(&strings.data)?;
+
+        write_block(
+            out,
+            b"FILE",
+            attrs,
+            self.display_size(),
+            &file,
+            &self.streams,
+        );
+
+        Ok(())
+    }
+
+    fn display_size(&self) -> u64 {
+        self.size.unwrap_or_else(|| {
+            self.streams
+                .iter()
+                .filter(|stream| &stream.id == b"STAN")
+                .map(|stream| stream.data.len() as u64)
+                .sum()
+        })
+    }
+}
+
+impl StreamBuilder {
+    pub fn new(id: &[u8; 4], data: &[u8]) -> StreamBuilder {
+        StreamBuilder {
+            id: *id,
+            media_format_attrs: MediaFormatAttributes::empty(),
+            encryption_algorithm: 0,
+            compression_algorithm: 0,
+            length: None,
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn media_format_attrs(mut self, attrs: MediaFormatAttributes) -> StreamBuilder {
+        self.media_format_attrs |= attrs;
+        self
+    }
+
+    // The data has to be compressed already, in frames. 0 uses the algorithm of the set
+    pub fn compressed(mut self, algorithm: u16) -> StreamBuilder {
+        self.media_format_attrs |= MediaFormatAttributes::COMRESSED;
+        self.compression_algorithm = algorithm;
+        self
+    }
+
+    // The data has to be encrypted already
+    pub fn encrypted(mut self, algorithm: u16) -> StreamBuilder {
+        self.media_format_attrs |= MediaFormatAttributes::ENCRYPTED;
+        self.encryption_algorithm = algorithm;
+        self
+    }
+
+    // Claims the given length in the header, so a shorter stream reads as truncated. As there is
+    // no way to tell where such a stream ends it has to be the last thing on the medium
+    pub fn length(mut self, length: u64) -> StreamBuilder {
+        self.length = Some(length);
+        self
+    }
+
+    fn write_to(&self, out: &mut Vec<u8>) {
+        let mut header = [0; STREAM_HEADER_SIZE];
+        header[..4].copy_from_slice(&self.id);
+        LittleEndian::write_u16(&mut header[6..], self.media_format_attrs.bits());
+        LittleEndian::write_u64(
+            &mut header[8..],
+            self.length.unwrap_or(self.data.len() as u64),
+        );
+        LittleEndian::write_u16(&mut header[16..], self.encryption_algorithm);
+        LittleEndian::write_u16(&mut header[18..], self.compression_algorithm);
+        let header_checksum = checksum(&header[..STREAM_HEADER_SIZE - 2]);
+        LittleEndian::write_u16(&mut header[STREAM_HEADER_SIZE - 2..], header_checksum);
+
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&self.data);
+        out.resize(align(out.len(), 4), 0);
+    }
+}
+
+fn write_volb(out: &mut Vec<u8>, attrs: u32, device_name: &str) -> Result<()> {
+    let mut strings = Strings::new(21);
+    let device_name = strings.add(Some(device_name));
+
+    let mut volb = Vec::new();
+    volb.write_u32::<LittleEndian>(VolbAttrs::DEV_DRIVE.bits())?;
+    volb.write_u32::<LittleEndian>(device_name)?;
+    volb.write_u32::<LittleEndian>(0)?; // volume name
+    volb.write_u32::<LittleEndian>(0)?; // machine name
+    volb.write_all(&pack_date(&DEFAULT_DATE))?; // write date
+    volb.write_all(&strings.data)?;
+    write_block(out, b"VOLB", attrs, 0, &volb, &[]);
+
+    Ok(())
+}
+
+// The components are NUL terminated, the root directory is only the NUL
+fn dirb_name(path: &str) -> String {
+    let mut name: String = path
+        .split('/')
+        .filter(|component| !component.is_empty())
+        .map(|component| format!("{}\0", component))
+        .collect();
+    if name.is_empty() {
+        name.push('\0');
+    }
+    name
+}
+
+fn write_dirb(out: &mut Vec<u8>, attrs: u32, directory_id: u32, path: &str) -> Result<()> {
+    let mut strings = Strings::new(32);
+    let name = strings.add(Some(&dirb_name(path)));
+
+    let mut dirb = Vec::new();
+    dirb.write_u32::<LittleEndian>(0)?; // attributes
+    dirb.write_all(&pack_date(&DEFAULT_DATE))?; // modification date
+    dirb.write_all(&[0; 15])?; // creation, backup and access date
+    dirb.write_u32::<LittleEndian>(directory_id)?;
+    dirb.write_u32::<LittleEndian>(name)?;
+    dirb.write_all(&strings.data)?;
+    write_block(out, b"DIRB", attrs, 0, &dirb, &[]);
+
+    Ok(())
+}
+
+// The strings of a block body, they follow the fixed part of the body and are addressed from the
+// start of the block
+struct Strings {
+    offset: usize,
+    data: Vec<u8>,
+}
+
+impl Strings {
+    fn new(fixed_size: usize) -> Strings {
+        Strings::at(HEADER_SIZE + fixed_size)
+    }
+
+    // For strings that start at offset of what they are addressed from
+    fn at(offset: usize) -> Strings {
+        Strings {
+            offset,
+            data: Vec::new(),
+        }
+    }
+
+    // The tape address of the string, ANSI encoded
+    fn add(&mut self, string: Option<&str>) -> u32 {
+        match string {
+            Some(string) if !string.is_empty() => {
+                let address = string.len() as u32 | ((self.offset + self.data.len()) as u32) << 16;
+                self.data.extend_from_slice(string.as_bytes());
+                address
+            }
+            _ => 0,
+        }
+    }
+}
+
+// 2020-05-17 13:45:30
+const DEFAULT_DATE: DateTime = DateTime {
+    year: 2020,
+    month: 5,
+    day: 17,
+    hour: 13,
+    minute: 45,
+    second: 30,
+};
+
+// The packed 40 bit format of the blocks
+pub(crate) fn pack_date(date: &DateTime) -> [u8; 5] {
+    let packed = (date.year as u64) << 26
+        | (date.month as u64) << 22
+        | (date.day as u64) << 17
+        | (date.hour as u64) << 12
+        | (date.minute as u64) << 6
+        | date.second as u64;
+    let mut data = [0; 5];
+    data.copy_from_slice(&packed.to_be_bytes()[3..]);
+    data
+}
+
+fn tape() -> Result<Vec<u8>> {
+    tape_of(1, 1, 0, false)
+}
+
+fn tape_of(
+    media_family_id: u32,
+    media_sequence_number: u16,
+    media_based_catalog_type: u16,
+    soft_filemarks: bool,
+) -> Result<Vec<u8>> {
+    let (attrs, soft_filemark_block_size) = if soft_filemarks {
+        (TapeAttrs::SOFT_FILE_MARK, SOFT_FILEMARK_BLOCK_SIZE / 512)
+    } else {
+        (TapeAttrs::empty(), 0)
+    };
+
+    let mut tape = Vec::new();
+    tape.write_u32::<LittleEndian>(media_family_id)?;
+    tape.write_u32::<LittleEndian>(attrs.bits())?;
+    tape.write_u16::<LittleEndian>(media_sequence_number)?;
+    tape.write_u16::<LittleEndian>(0)?; // password encryption algorithm
+    tape.write_u16::<LittleEndian>(soft_filemark_block_size as u16)?;
+    tape.write_u16::<LittleEndian>(media_based_catalog_type)?;
+    tape.write_all(&[0; 16])?; // media name, description, password and software name
+    tape.write_u16::<LittleEndian>(FORMAT_LOGICAL_BLOCK_SIZE as u16)?;
+    tape.write_u16::<LittleEndian>(0)?; // software vendor id
+    tape.write_all(&[0; 5])?; // media date
+    tape.write_u8(1)?; // major version
+    Ok(tape)
+}
+
+// A frame of the data of a COMRESSED stream, data has to be compressed with the algorithm of the
+// stream already. Frames with as many bytes as they decompress to are stored uncompressed
+pub fn frame(data: &[u8], uncompressed_size: u32) -> Vec<u8> {
+    let mut frame = vec![0; FRAME_HEADER_SIZE];
+    frame[..2].copy_from_slice(b"FM");
+    // the remaining stream size is not checked
+    LittleEndian::write_u64(&mut frame[2..], data.len() as u64);
+    LittleEndian::write_u32(&mut frame[10..], uncompressed_size);
+    LittleEndian::write_u32(&mut frame[14..], data.len() as u32);
+    let header_checksum = checksum(&frame[..FRAME_HEADER_SIZE - 2]);
+    LittleEndian::write_u16(&mut frame[FRAME_HEADER_SIZE - 2..], header_checksum);
+    frame.extend_from_slice(data);
+    frame
+}
+
+// The parser maps its file, so tests write the medium to a temporary one first
+#[cfg(test)]
+pub(crate) fn open(name: &str, medium: &[u8]) -> crate::MTFParser {
+    let path = std::env::temp_dir().join(format!("mtf-{}-{}.bkf", name, std::process::id()));
+    std::fs::write(&path, medium).unwrap();
+    let parser = crate::MTFParser::new(path.to_str().unwrap());
+    // still readable through the open handle
+    std::fs::remove_file(&path).unwrap();
+    parser
+}
+
+fn align(position: usize, alignment: usize) -> usize {
+    position.div_ceil(alignment) * alignment
+}
+
+fn checksum(data: &[u8]) -> u16 {
+    data.chunks(2)
+        .fold(0, |checksum, word| checksum ^ LittleEndian::read_u16(word))
+}
+
+// Every DBLK starts at a format logical block, its streams end with a SPAD up to the next one
+fn write_dblk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8], streams: &[(&[u8; 4], &[u8])]) {
+    let streams: Vec<_> = streams
+        .iter()
+        .map(|(id, data)| StreamBuilder::new(id, data))
+        .collect();
+    write_block(out, id, 0, 0, body, &streams);
+}
+
+// With the common block attributes and the display size of the header
+pub(crate) fn write_block(
+    out: &mut Vec<u8>,
+    id: &[u8; 4],
+    attrs: u32,
+    display_size: u64,
+    body: &[u8],
+    streams: &[StreamBuilder],
+) {
+    let start = out.len();
+    let offset_to_first_event = align(HEADER_SIZE + body.len(), 4);
+
+    let mut header = [0; HEADER_SIZE];
+    header[..4].copy_from_slice(id);
+    LittleEndian::write_u32(&mut header[4..], attrs);
+    LittleEndian::write_u16(&mut header[8..], offset_to_first_event as u16);
+    header[10] = 14; // Windows NT
+    LittleEndian::write_u64(&mut header[12..], display_size);
+    LittleEndian::write_u64(
+        &mut header[20..],
+        (start / FORMAT_LOGICAL_BLOCK_SIZE) as u64,
+    );
+    header[48] = 1; // ANSI strings
+    let header_checksum = checksum(&header[..HEADER_SIZE - 2]);
+    LittleEndian::write_u16(&mut header[HEADER_SIZE - 2..], header_checksum);
+
+    out.extend_from_slice(&header);
+    out.extend_from_slice(body);
+    out.resize(start + offset_to_first_event, 0);
+
+    for stream in streams {
+        stream.write_to(out);
+    }
+
+    let spad_start = out.len() + STREAM_HEADER_SIZE;
+    let spad = align(spad_start, FORMAT_LOGICAL_BLOCK_SIZE) - spad_start;
+    write_stream(out, b"SPAD", &vec![0; spad]);
+}
+
+pub(crate) fn write_stream(out: &mut Vec<u8>, id: &[u8; 4], data: &[u8]) {
+    StreamBuilder::new(id, data).write_to(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::os_specific::{NtFileAttrs, NtVersion, OsSpecificData};
+    use crate::{
+        CfilAttrs, DBLKSets, DBLKSpecific, DBLKWithStreams, DirbAttrs, MTFOptions, SSetAttrs,
+        StreamTerminator, TerminationReason,
+    };
+    use std::io::Cursor;
+
+    fn parse(data: &[u8]) -> DBLKWithStreams<'_> {
+        DBLKWithStreams::parse(
+            &mut Cursor::new(data),
+            &mut DBLKSets::default(),
+            &MTFOptions::default(),
+            data,
+        )
+        .unwrap()
+    }
+
+    fn tape_address(size: usize, offset: usize) -> u32 {
+        size as u32 | (offset as u32) << 16
+    }
+
+    // Appends the OS specific data to the body and points the header at it
+    fn write_dblk_os(
+        out: &mut Vec<u8>,
+        id: &[u8; 4],
+        osver: u8,
+        body: &[u8],
+        os_specific: &[u8],
+        streams: &[(&[u8; 4], &[u8])],
+    ) {
+        let start = out.len();
+        write_dblk(out, id, &[body, os_specific].concat(), streams);
+
+        let header = &mut out[start..start + HEADER_SIZE];
+        header[11] = osver;
+        LittleEndian::write_u32(
+            &mut header[44..],
+            tape_address(os_specific.len(), HEADER_SIZE + body.len()),
+        );
+        let header_checksum = checksum(&header[..HEADER_SIZE - 2]);
+        LittleEndian::write_u16(&mut header[HEADER_SIZE - 2..], header_checksum);
+    }
+
+    // The NT structure of the given size followed by the short name, for a block with body
+    fn nt_os_specific(body: &[u8], attrs: NtFileAttrs, size: usize, short_name: &str) -> Vec<u8> {
+        let mut os_specific = Vec::new();
+        os_specific.write_u32::<LittleEndian>(attrs.bits()).unwrap();
+        if size >= 8 {
+            let offset = HEADER_SIZE + body.len() + size;
+            os_specific
+                .write_u32::<LittleEndian>(tape_address(short_name.len(), offset))
+                .unwrap();
+        }
+        if size >= 10 {
+            os_specific.write_u16::<LittleEndian>(1).unwrap(); // link
+        }
+        os_specific.write_all(short_name.as_bytes()).unwrap();
+        os_specific
+    }
+
+    // 2020-05-17 13:45:30 in the packed 40 bit format
+    const DATE: [u8; 5] = [31, 145, 98, 219, 94];
+
+    fn dirb(directory_id: u32, name: &str) -> Vec<u8> {
+        let mut dirb = Vec::new();
+        dirb.write_u32::<LittleEndian>(DirbAttrs::HIDDEN.bits())
+            .unwrap();
+        dirb.write_all(&DATE).unwrap(); // modification date
+        dirb.write_all(&[0; 15]).unwrap(); // creation, backup and access date
+        dirb.write_u32::<LittleEndian>(directory_id).unwrap();
+        let offset = HEADER_SIZE + dirb.len() + 4;
+        dirb.write_u32::<LittleEndian>(tape_address(name.len(), offset))
+            .unwrap();
+        dirb.write_all(name.as_bytes()).unwrap();
+        dirb
+    }
+
+    fn file(directory_id: u32, file_id: u32, name: &str) -> Vec<u8> {
+        let mut file = Vec::new();
+        file.write_u32::<LittleEndian>(0).unwrap(); // attributes
+        file.write_all(&DATE).unwrap(); // modification date
+        file.write_all(&[0; 15]).unwrap(); // creation, backup and access date
+        file.write_u32::<LittleEndian>(directory_id).unwrap();
+        file.write_u32::<LittleEndian>(file_id).unwrap();
+        let offset = HEADER_SIZE + file.len() + 4;
+        file.write_u32::<LittleEndian>(tape_address(name.len(), offset))
+            .unwrap();
+        file.write_all(name.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn zero_length_streams() {
+        let mut data = Vec::new();
+        write_dblk(
+            &mut data,
+            b"FILE",
+            &file(1, 2, "empty.txt"),
+            &[(b"STAN", &[]), (b"CSUM", &[0; 4])],
+        );
+        write_dblk(&mut data, b"ESPB", &[], &[]);
+
+        let dblk = parse(&data);
+        let ids: Vec<_> = dblk.streams.iter().map(|stream| stream.id()).collect();
+        assert_eq!(ids, ["STAN", "CSUM", "SPAD"]);
+        assert!(dblk.streams[0].data.is_empty());
+        assert_eq!(dblk.terminator, StreamTerminator::SPAD);
+        match dblk.dblk.body {
+            DBLKSpecific::FILE {
+                file_id, file_name, ..
+            } => {
+                assert_eq!(file_id, 2);
+                assert_eq!(file_name.as_deref(), Some("empty.txt"));
+            }
+            body => panic!("expected a FILE, got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        // the XOR of "hell", "o wo" and "rld\0"
+        let checksum = 0x037f2975u32;
+        let checksumed = |name: &str, csum: &[u8]| {
+            FileBuilder::new(name)
+                .stream(
+                    StreamBuilder::new(b"STAN", b"hello world")
+                        .media_format_attrs(MediaFormatAttributes::CHECKSUMED),
+                )
+                .stream(StreamBuilder::new(b"CSUM", csum))
+        };
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .file(checksumed("good.txt", &checksum.to_le_bytes()))
+                    .file(checksumed("corrupt.txt", &(checksum ^ 1).to_le_bytes()))
+                    .file(checksumed("short.txt", &[0; 2])),
+            )
+            .build()
+            .unwrap();
+        let mut parser = open("checksums", &medium);
+
+        let files: Vec<_> = parser
+            .dblks()
+            .unwrap()
+            .filter(|dblk| matches!(dblk.dblk.body, DBLKSpecific::FILE { .. }))
+            .collect();
+        assert_eq!(files.len(), 3);
+
+        let stan = &files[0].streams[0];
+        assert_eq!(stan.checksum(), checksum);
+        assert!(stan.verify_checksum(&files[0].streams[1]).unwrap());
+        assert!(stan.verify_checksum(stan).is_err());
+
+        let results: Vec<_> = files
+            .iter()
+            .map(|file| {
+                let results = file.verify_checksums();
+                assert_eq!(results.len(), 1);
+                let (idx, result) = results.into_iter().next().unwrap();
+                assert_eq!(idx, 0);
+                result.map_err(|err| err.to_string())
+            })
+            .collect();
+        assert_eq!(
+            results,
+            [
+                Ok(true),
+                Ok(false),
+                Err("CSUM stream truncated".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn dirb_body() {
+        let mut data = Vec::new();
+        write_dblk(&mut data, b"DIRB", &dirb(7, "Windows\0System32\0"), &[]);
+
+        match parse(&data).dblk.body {
+            DBLKSpecific::DIRB {
+                attrs,
+                last_modification_date,
+                directory_id,
+                path,
+                ..
+            } => {
+                assert_eq!(attrs, DirbAttrs::HIDDEN);
+                assert_eq!(last_modification_date.year, 2020);
+                assert_eq!(last_modification_date.month, 5);
+                assert_eq!(last_modification_date.day, 17);
+                assert_eq!(last_modification_date.hour, 13);
+                assert_eq!(last_modification_date.minute, 45);
+                assert_eq!(last_modification_date.second, 30);
+                assert_eq!(directory_id, 7);
+                assert_eq!(path, Some(vec!["Windows".into(), "System32".into()]));
+            }
+            body => panic!("expected a DIRB, got {:?}", body),
+        }
+
+        // the root directory is only the terminating NUL
+        let mut data = Vec::new();
+        write_dblk(&mut data, b"DIRB", &dirb(1, "\0"), &[]);
+        match parse(&data).dblk.body {
+            DBLKSpecific::DIRB { path, .. } => assert_eq!(path, Some(Vec::new())),
+            body => panic!("expected a DIRB, got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn file_body() {
+        let mut data = Vec::new();
+        write_dblk(
+            &mut data,
+            b"FILE",
+            &file(7, 42, "notepad.exe"),
+            &[(b"STAN", b"MZ")],
+        );
+
+        let dblk = parse(&data);
+        assert_eq!(dblk.streams[0].data, b"MZ");
+        match dblk.dblk.body {
+            DBLKSpecific::FILE {
+                last_modification_date,
+                creation_date,
+                directory_id,
+                file_id,
+                file_name,
+                ..
+            } => {
+                assert_eq!(last_modification_date.unix_timestamp(), Some(1589723130));
+                assert_eq!(creation_date.unix_timestamp(), None);
+                assert_eq!(directory_id, 7);
+                assert_eq!(file_id, 42);
+                assert_eq!(file_name.as_deref(), Some("notepad.exe"));
+            }
+            body => panic!("expected a FILE, got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn cfil_body() {
+        let mut cfil = Vec::new();
+        cfil.write_u32::<LittleEndian>(CfilAttrs::UNREADABLE_BLK.bits())
+            .unwrap();
+        cfil.write_all(&[0; 8]).unwrap(); // reserved
+        cfil.write_u64::<LittleEndian>(0x1_0000).unwrap(); // stream offset
+        cfil.write_u16::<LittleEndian>(1).unwrap(); // corrupt stream number
+
+        let mut data = Vec::new();
+        write_dblk(&mut data, b"CFIL", &cfil, &[]);
+
+        match parse(&data).dblk.body {
+            DBLKSpecific::CFIL {
+                attrs,
+                stream_offset,
+                corrupt_stream_number,
+            } => {
+                assert_eq!(attrs, CfilAttrs::UNREADABLE_BLK);
+                assert_eq!(stream_offset, 0x1_0000);
+                assert_eq!(corrupt_stream_number, 1);
+            }
+            body => panic!("expected a CFIL, got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn eset_body() {
+        let mut eset = Vec::new();
+        eset.write_u32::<LittleEndian>(SSetAttrs::NORMAL.bits())
+            .unwrap();
+        eset.write_u32::<LittleEndian>(3).unwrap(); // number of corrupt files
+        eset.write_u64::<LittleEndian>(12).unwrap(); // set map pba
+        eset.write_u64::<LittleEndian>(10).unwrap(); // fdd pba
+        eset.write_u16::<LittleEndian>(1).unwrap(); // fdd media sequence number
+        eset.write_u16::<LittleEndian>(2).unwrap(); // data set number
+        eset.write_all(&DATE).unwrap(); // media write date
+
+        let mut data = Vec::new();
+        write_dblk(&mut data, b"ESET", &eset, &[]);
+
+        match parse(&data).dblk.body {
+            DBLKSpecific::ESET {
+                attrs,
+                number_of_corrupt_files,
+                set_map_pba,
+                fdd_pba,
+                fdd_media_sequence_number,
+                data_set_number,
+                media_write_date,
+            } => {
+                assert_eq!(attrs, SSetAttrs::NORMAL);
+                assert_eq!(number_of_corrupt_files, 3);
+                assert_eq!(set_map_pba, 12);
+                assert_eq!(fdd_pba, 10);
+                assert_eq!(fdd_media_sequence_number, 1);
+                assert_eq!(data_set_number, 2);
+                assert_eq!(media_write_date.unix_timestamp(), Some(1589723130));
+            }
+            body => panic!("expected an ESET, got {:?}", body),
+        }
+    }
+
+    #[test]
+    fn espb_padding() {
+        let mut medium = Vec::new();
+        write_dblk(&mut medium, b"TAPE", &tape().unwrap(), &[]);
+        write_dblk(&mut medium, b"ESPB", &[], &[]);
+        // where the hardware filemark was
+        medium.resize(medium.len() + 2 * FORMAT_LOGICAL_BLOCK_SIZE, 0);
+        let eset_offset = medium.len() as u64;
+        let mut eset = vec![0; 33];
+        eset[26] = 1; // data set number
+        write_dblk(&mut medium, b"ESET", &eset, &[]);
+
+        let mut parser = open("espb-padding", &medium);
+        let mut dblks = parser.dblks().unwrap();
+        let blocks: Vec<_> = dblks
+            .by_ref()
+            .map(|dblk| (dblk.offset, dblk.dblk.body))
+            .collect();
+        assert_eq!(blocks.len(), 3);
+        assert!(matches!(blocks[0].1, DBLKSpecific::TAPE { .. }));
+        assert!(matches!(blocks[1].1, DBLKSpecific::ESPB));
+        assert_eq!(blocks[2].0, eset_offset);
+        assert!(matches!(
+            blocks[2].1,
+            DBLKSpecific::ESET {
+                data_set_number: 1,
+                ..
+            }
+        ));
+        assert_eq!(
+            dblks.finish().termination,
+            Some(TerminationReason::END_OF_DATA)
+        );
+    }
+
+    #[test]
+    fn mqda_medium() {
+        let page = vec![0; PAGE_SIZE];
+        assert!(MQDABuilder::new().build().is_err());
+        assert!(MQDABuilder::new().page(&page[1..]).build().is_err());
+
+        let medium = MQDABuilder::new().page(&page).build().unwrap();
+        let mut parser = open("mqda-medium", &medium);
+        let sets = parser.backup_sets().unwrap();
+        assert_eq!(sets.len(), 1);
+        assert_eq!(sets[0].data_set_number, 1);
+
+        let mqda: Vec<_> = parser
+            .set(1)
+            .unwrap()
+            .flat_map(|dblk| dblk.streams)
+            .filter(|stream| stream.id() == "MQDA")
+            .map(|stream| stream.data.len())
+            .collect();
+        assert_eq!(mqda, [2 + PAGE_SIZE]);
+    }
+
+    #[test]
+    fn nt3_file_os_specific() {
+        let body = file(1, 2, "Program Files");
+        let attrs = NtFileAttrs::HIDDEN | NtFileAttrs::ARCHIVE;
+        // the short name directly follows the 8 bytes of the NT3 layout, it must not be taken for
+        // the link flag
+        let os_specific = nt_os_specific(&body, attrs, 8, "\x01\x00PROGR~1");
+        let mut data = Vec::new();
+        write_dblk_os(&mut data, b"FILE", 0, &body, &os_specific, &[]);
+
+        match parse(&data).dblk.header.os_specific {
+            OsSpecificData::NT_FILE {
+                version,
+                attrs: parsed,
+                short_name,
+                link,
+            } => {
+                assert_eq!(version, NtVersion::NT3);
+                assert_eq!(parsed, attrs);
+                assert_eq!(short_name.as_deref(), Some("\x01\x00PROGR~1"));
+                assert!(!link);
+            }
+            os_specific => panic!("expected NT_FILE, got {:?}", os_specific),
+        }
+    }
+
+    #[test]
+    fn nt5_file_os_specific() {
+        let body = file(1, 2, "Program Files");
+        let os_specific = nt_os_specific(&body, NtFileAttrs::ARCHIVE, 10, "PROGRA~1");
+        let mut data = Vec::new();
+        write_dblk_os(&mut data, b"FILE", 1, &body, &os_specific, &[]);
+
+        match parse(&data).dblk.header.os_specific {
+            OsSpecificData::NT_FILE {
+                version,
+                attrs,
+                short_name,
+                link,
+            } => {
+                assert_eq!(version, NtVersion::NT5);
+                assert_eq!(attrs, NtFileAttrs::ARCHIVE);
+                assert_eq!(short_name.as_deref(), Some("PROGRA~1"));
+                assert!(link);
+            }
+            os_specific => panic!("expected NT_FILE, got {:?}", os_specific),
+        }
+
+        // too short for the NT5 layout
+        let os_specific = nt_os_specific(&body, NtFileAttrs::ARCHIVE, 8, "");
+        let mut data = Vec::new();
+        write_dblk_os(&mut data, b"FILE", 1, &body, &os_specific, &[]);
+        assert!(matches!(
+            parse(&data).dblk.header.os_specific,
+            OsSpecificData::UNKNOWN(_)
+        ));
+    }
+
+    #[test]
+    fn nt3_dirb_os_specific() {
+        let body = dirb(1, "Documents and Settings\0");
+        let os_specific = nt_os_specific(&body, NtFileAttrs::DIRECTORY, 4, "");
+        let mut data = Vec::new();
+        write_dblk_os(&mut data, b"DIRB", 0, &body, &os_specific, &[]);
+
+        match parse(&data).dblk.header.os_specific {
+            OsSpecificData::NT_DIRB {
+                version,
+                attrs,
+                short_name,
+            } => {
+                assert_eq!(version, NtVersion::NT3);
+                assert_eq!(attrs, NtFileAttrs::DIRECTORY);
+                assert_eq!(short_name, None);
+            }
+            os_specific => panic!("expected NT_DIRB, got {:?}", os_specific),
+        }
+    }
+
+    #[test]
+    fn nt5_dirb_os_specific() {
+        let body = dirb(1, "Documents and Settings\0");
+        let os_specific = nt_os_specific(&body, NtFileAttrs::DIRECTORY, 8, "DOCUME~1");
+        let mut data = Vec::new();
+        write_dblk_os(&mut data, b"DIRB", 1, &body, &os_specific, &[]);
+
+        match parse(&data).dblk.header.os_specific {
+            OsSpecificData::NT_DIRB {
+                version,
+                attrs,
+                short_name,
+            } => {
+                assert_eq!(version, NtVersion::NT5);
+                assert_eq!(attrs, NtFileAttrs::DIRECTORY);
+                assert_eq!(short_name.as_deref(), Some("DOCUME~1"));
+            }
+            os_specific => panic!("expected NT_DIRB, got {:?}", os_specific),
+        }
+
+        // the NT3 layout with a newer osver
+        let os_specific = nt_os_specific(&body, NtFileAttrs::DIRECTORY, 4, "");
+        let mut data = Vec::new();
+        write_dblk_os(&mut data, b"DIRB", 2, &body, &os_specific, &[]);
+        assert!(matches!(
+            parse(&data).dblk.header.os_specific,
+            OsSpecificData::UNKNOWN(_)
+        ));
+    }
+
+    #[test]
+    fn error_classes() {
+        use crate::crypto::{self, EncryptionAlgorithm};
+        use crate::{ErrorClass, UnsupportedFeature};
+
+        let missing = crate::backup::Backup::open("/nonexistent/mtf-error-classes.bkf");
+        assert_eq!(ErrorClass::of(&missing.unwrap_err()), ErrorClass::IO);
+
+        // reading past the end of the data is not an I/O error
+        let medium = MediumBuilder::new().build().unwrap();
+        let err = DBLKWithStreams::parse(
+            &mut Cursor::new(&medium[..40]),
+            &mut DBLKSets::default(),
+            &MTFOptions::default(),
+            &medium[..40],
+        )
+        .unwrap_err();
+        assert_eq!(ErrorClass::of(&err), ErrorClass::CORRUPT_INPUT);
+
+        let mut header = medium.clone();
+        header[0] = b'X';
+        let err = DBLKWithStreams::parse(
+            &mut Cursor::new(&header),
+            &mut DBLKSets::default(),
+            &MTFOptions::default(),
+            &header,
+        )
+        .unwrap_err();
+        assert_eq!(ErrorClass::of(&err), ErrorClass::CORRUPT_INPUT);
+
+        let err = crypto::decrypt(EncryptionAlgorithm::VENDOR(0xfffe), b"").unwrap_err();
+        assert!(err.downcast_ref::<UnsupportedFeature>().is_some());
+        assert_eq!(ErrorClass::of(&err), ErrorClass::UNSUPPORTED_FEATURE);
+    }
+
+    #[test]
+    fn entry_filters() {
+        use crate::files::{AttributeFilter, DateKind, TimeWindow};
+
+        let later = DateTime {
+            year: 2021,
+            ..DEFAULT_DATE
+        };
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .file(FileBuilder::new("plain.txt").data(b"plain"))
+                    .file(FileBuilder::new("hidden.txt").attrs(FileAttrs::HIDDEN))
+                    .file(
+                        FileBuilder::new("system.txt")
+                            .attrs(FileAttrs::SYSTEM | FileAttrs::READ_ONLY)
+                            .modified(later.clone()),
+                    )
+                    .file(FileBuilder::new("new.txt").modified(later.clone())),
+            )
+            .build()
+            .unwrap();
+        let mut parser = open("entry-filters", &medium);
+
+        fn names<'a>(files: impl Iterator<Item = crate::files::FileEntry<'a>>) -> Vec<String> {
+            files
+                .map(|file| file.full_path.to_string_lossy().into_owned())
+                .collect()
+        }
+
+        let visible = parser
+            .files()
+            .unwrap()
+            .with_attributes(AttributeFilter::SKIP_HIDDEN_SYSTEM);
+        assert_eq!(names(visible), ["plain.txt", "new.txt"]);
+
+        let read_only = AttributeFilter {
+            required: FileAttrs::READ_ONLY,
+            excluded: FileAttrs::empty(),
+        };
+        let read_only = parser.files().unwrap().with_attributes(read_only);
+        assert_eq!(names(read_only), ["system.txt"]);
+
+        let window = TimeWindow {
+            kind: DateKind::LAST_MODIFICATION,
+            start: later.unix_timestamp(),
+            end: None,
+        };
+        let modified = parser.files().unwrap().within(window);
+        assert_eq!(names(modified), ["system.txt", "new.txt"]);
+    }
+
+    #[test]
+    fn catalog_files() {
+        let set = |number: u16| {
+            SetBuilder::new(number)
+                .file(FileBuilder::new("boot.ini").data(b"[boot loader]"))
+                .directory("Windows")
+                .file(FileBuilder::new("win.ini").data(b"[fonts]"))
+                .file(FileBuilder::new("system.ini"))
+                .volume("D:")
+                .directory("data/2020")
+                .file(FileBuilder::new("report.doc").data(b"report"))
+        };
+        let medium = MediumBuilder::new()
+            .catalog()
+            .set(set(1))
+            .set(set(2))
+            .build()
+            .unwrap();
+        let mut parser = open("catalog-files", &medium);
+
+        let scanned: Vec<_> = parser
+            .files()
+            .unwrap()
+            .filter(|file| file.handle.data_set_number == Some(2))
+            .map(|file| (file.handle, file.volume, file.full_path, file.size))
+            .collect();
+        assert_eq!(scanned.len(), 4);
+
+        let files = parser.catalog_files(2).unwrap().unwrap();
+        assert_eq!(files.len(), 4);
+        assert_eq!(files.handles().len(), 4);
+        let listed: Vec<_> = files
+            .map(|file| file.unwrap())
+            .map(|file| (file.handle, file.volume, file.full_path, file.size))
+            .collect();
+        assert_eq!(listed, scanned);
+
+        // pages of two
+        let mut files = parser.catalog_files(2).unwrap().unwrap();
+        let third = files.nth(2).unwrap().unwrap();
+        assert_eq!(third.full_path, scanned[2].2);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files.next().unwrap().unwrap().full_path, scanned[3].2);
+        assert!(files.next().is_none());
+
+        assert!(parser.catalog_files(3).unwrap().is_none());
+
+        let medium = MediumBuilder::new().set(set(1)).build().unwrap();
+        let mut parser = open("catalog-files-none", &medium);
+        assert!(parser.catalog_files(1).unwrap().is_none());
+    }
+
+    #[test]
+    fn names_are_shared() {
+        use std::sync::Arc;
+
+        let set = |number: u16| {
+            SetBuilder::new(number)
+                .directory("Windows/System32")
+                .file(FileBuilder::new("a.dll"))
+                .file(FileBuilder::new("b.dll"))
+        };
+        let medium = MediumBuilder::new()
+            .set(set(1))
+            .set(set(2))
+            .build()
+            .unwrap();
+        let mut parser = open("names-are-shared", &medium);
+        let sets = parser.sets().unwrap();
+
+        let windows: Vec<_> = sets
+            .iter()
+            .map(|set| set.volumes[0].root.directory(&["Windows"]).unwrap())
+            .collect();
+        assert!(Arc::ptr_eq(&windows[0].name, &windows[1].name));
+
+        let files: Vec<_> = sets
+            .iter()
+            .flat_map(|set| set.files())
+            .map(|file| file.entry.volume.clone().unwrap())
+            .collect();
+        assert_eq!(files.len(), 4);
+        assert_eq!(&*files[0], "C:");
+        assert!(files.iter().all(|volume| Arc::ptr_eq(volume, &files[0])));
+    }
+}
@@ -4,11 +4,49 @@
 use bitflags::*;
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
 use failure::*;
+use log::warn;
 use memmap::Mmap;
+use rayon::prelude::*;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{Cursor, Read};
-
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub mod audit;
+pub mod backup;
+pub mod catalog;
+pub mod codepage;
+pub mod compression;
+pub mod convert;
+#[doc(hidden)]
+pub mod corpus;
+pub mod crypto;
+pub mod ea;
+pub mod family;
+pub mod files;
+pub mod fixture;
 pub mod mdf;
+pub mod metrics;
+pub mod os_specific;
+pub mod prelude;
+pub mod restore;
+pub mod scan;
+pub mod schema;
+pub mod security;
+pub mod sets;
+pub mod streams;
+
+use audit::AuditEvent;
+use catalog::{CatalogFiles, Fdd, FddBody, SetMap};
+use codepage::Codepage;
+use compression::CompressionAlgorithm;
+use crypto::EncryptionAlgorithm;
+use family::MediaFamily;
+use files::{EntryHandle, FileEntry, FileIterator, FileRecords, PathResolver};
+use os_specific::OsSpecificData;
+use sets::BackupSet;
 
 type Result<T> = std::result::Result<T, failure::Error>;
 
@@ -20,6 +58,43 @@ bitflags! {
     }
 }
 
+// With TapeAttrs::MEDIA_LABEL the media name holds these fields, separated by '|'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaLabel {
+    // "MTF Media Label"
+    pub format: String,
+    pub version: String,
+    pub vendor: String,
+    pub cartridge_label: String,
+    pub side: String,
+    pub media_id: String,
+    pub domain: String,
+    // whatever the vendor added after the standard fields
+    pub vendor_specific: Vec<String>,
+}
+
+impl MediaLabel {
+    fn parse(name: &str) -> Option<MediaLabel> {
+        if !name.contains('|') {
+            return None;
+        }
+
+        let mut fields = name.trim_end_matches('\0').split('|').map(String::from);
+        let mut next = || fields.next().unwrap_or_default();
+
+        Some(MediaLabel {
+            format: next(),
+            version: next(),
+            vendor: next(),
+            cartridge_label: next(),
+            side: next(),
+            media_id: next(),
+            domain: next(),
+            vendor_specific: fields.collect(),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SoftFileMarkBlockSize {
     blocks: u16,
@@ -68,6 +143,38 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct DirbAttrs: u32 {
+        const READ_ONLY = 1 << 8;
+        const HIDDEN = 1 << 9;
+        const SYSTEM = 1 << 10;
+        const MODIFIED = 1 << 11;
+        const EMPTY = 1 << 16;
+        const PATH_IN_STREAM = 1 << 17;
+        const CORRUPT = 1 << 18;
+    }
+}
+
+bitflags! {
+    pub struct FileAttrs: u32 {
+        const READ_ONLY = 1 << 8;
+        const HIDDEN = 1 << 9;
+        const SYSTEM = 1 << 10;
+        const MODIFIED = 1 << 11;
+        const IN_USE = 1 << 16;
+        const NAME_IN_STREAM = 1 << 17;
+        const CORRUPT = 1 << 18;
+    }
+}
+
+bitflags! {
+    pub struct CfilAttrs: u32 {
+        const LENGTH_CHANGE = 1 << 16;
+        const UNREADABLE_BLK = 1 << 17;
+        const DEADLOCK = 1 << 18;
+    }
+}
+
 bitflags! {
     pub struct VolbAttrs: u32 {
         const NO_REDIRECT_RESTORE = 1 << 0;
@@ -88,7 +195,9 @@ pub enum DBLKSpecific {
         password_encryption_algorithm: u16,
         soft_filemark_block_size: SoftFileMarkBlockSize,
         media_based_catalog_type: MediaBasedCatalogType,
+        // None if the name is a media label
         media_name: Option<String>,
+        media_label: Option<MediaLabel>,
         media_description: Option<String>,
         media_password: Option<String>,
         software_name: Option<String>,
@@ -100,7 +209,8 @@ pub enum DBLKSpecific {
     SSET {
         attrs: SSetAttrs,
         password_encryption_algorithm: u16,
-        software_compression_algorithm: u16,
+        // the algorithm the COMRESSED streams of the set use
+        software_compression_algorithm: CompressionAlgorithm,
         software_vendor_id: u16,
         data_set_number: u16,
         data_set_name: Option<String>,
@@ -111,7 +221,7 @@ pub enum DBLKSpecific {
         write_date: DateTime,
         software_major_version: u8,
         software_minor_version: u8,
-        timezone: i8,
+        timezone: TimeZone,
         minor_version: u8,
         media_catalog_version: u8,
     },
@@ -122,12 +232,50 @@ pub enum DBLKSpecific {
         machine_name: Option<String>,
         write_date: DateTime,
     },
-    DIRB,
-    FILE,
-    CFIL,
+    DIRB {
+        attrs: DirbAttrs,
+        last_modification_date: DateTime,
+        creation_date: DateTime,
+        backup_date: DateTime,
+        last_access_date: DateTime,
+        directory_id: u32,
+        // The components of the path, relative to the volume; empty for the root directory.
+        // None if PATH_IN_STREAM is set, the path is in the PNAM stream then
+        path: Option<Vec<String>>,
+    },
+    FILE {
+        attrs: FileAttrs,
+        last_modification_date: DateTime,
+        creation_date: DateTime,
+        backup_date: DateTime,
+        last_access_date: DateTime,
+        directory_id: u32,
+        file_id: u32,
+        // None if NAME_IN_STREAM is set, the name is in the FNAM stream then
+        file_name: Option<String>,
+    },
+    CFIL {
+        attrs: CfilAttrs,
+        // offset into the corrupt stream at which the corruption starts
+        stream_offset: u64,
+        // which stream of the preceding FILE/DIRB is corrupt
+        corrupt_stream_number: u16,
+    },
     ESPB,
-    ESET,
-    EOTM,
+    ESET {
+        // same bits as for the SSET
+        attrs: SSetAttrs,
+        number_of_corrupt_files: u32,
+        set_map_pba: u64,
+        fdd_pba: u64,
+        fdd_media_sequence_number: u16,
+        data_set_number: u16,
+        media_write_date: DateTime,
+    },
+    EOTM {
+        // of the last ESET on the medium, 0 if there is none
+        last_eset_pba: u64,
+    },
     SFMB {
         number_of_entries: u32,
         used_entries: u32,
@@ -138,8 +286,8 @@ pub enum DBLKSpecific {
 
 #[derive(Debug, Clone)]
 pub struct DBLK {
-    header: CommonBlockHeader,
-    body: DBLKSpecific,
+    pub header: CommonBlockHeader,
+    pub body: DBLKSpecific,
 }
 
 #[derive(Debug, Clone)]
@@ -208,193 +356,1777 @@ impl DBLKType {
             }
         }
     }
-}
+}
+
+#[derive(Debug, Clone)]
+pub enum StringType {
+    NO_STRINGS,
+    ANSI_STR,
+    UNICODE_STR,
+}
+
+impl StringType {
+    fn parse(ty: u8) -> Result<StringType> {
+        use StringType::*;
+
+        match ty {
+            0 => Ok(NO_STRINGS),
+            1 => Ok(ANSI_STR),
+            2 => Ok(UNICODE_STR),
+            _ => Err(format_err!("invalid string type {}", ty)),
+        }
+    }
+
+    fn bytes_to_string(&self, data: Vec<u8>) -> Result<String> {
+        use StringType::*;
+
+        match self {
+            NO_STRINGS => Err(format_err!(
+                "string type was set to NO_STRINGS, but wanted to convert {:#?} to a string",
+                data
+            )),
+            ANSI_STR => Ok(String::from_utf8(data)?),
+            UNICODE_STR => Ok(String::from_utf16(
+                &data
+                    .chunks_exact(2)
+                    .into_iter()
+                    .map(|a| u16::from_le_bytes([a[0], a[1]]))
+                    .collect::<Vec<_>>(),
+            )?),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct StringDecoder {
+    ty: StringType,
+    // DOS agents write their ANSI strings in the OEM codepage of the machine
+    oem_codepage: Option<Codepage>,
+}
+
+impl StringDecoder {
+    fn new(header: &CommonBlockHeader, options: &MTFOptions) -> StringDecoder {
+        let ty = match (&header.string_type, &options.assumed_tape) {
+            (StringType::NO_STRINGS, Some(assumed)) => assumed.string_type.clone(),
+            (ty, _) => ty.clone(),
+        };
+
+        StringDecoder::for_os(&header.osid, ty, options)
+    }
+
+    fn for_os(osid: &OS, ty: StringType, options: &MTFOptions) -> StringDecoder {
+        let oem_codepage = match osid {
+            OS::DOS_Windows3_X => Some(options.oem_codepage),
+            _ => None,
+        };
+
+        StringDecoder { ty, oem_codepage }
+    }
+
+    fn decode(&self, ty: &StringType, data: Vec<u8>) -> Result<String> {
+        match (ty, self.oem_codepage) {
+            (StringType::ANSI_STR, Some(codepage)) => Ok(codepage.decode(&data)),
+            (ty, _) => ty.bytes_to_string(data),
+        }
+    }
+
+    // Some writers set the string type of the header inconsistently with the actual strings,
+    // so if the result looks wrong, retry with the other encoding
+    fn bytes_to_string(&self, data: Vec<u8>) -> Result<String> {
+        let other = match self.ty {
+            StringType::ANSI_STR => StringType::UNICODE_STR,
+            StringType::UNICODE_STR => StringType::ANSI_STR,
+            StringType::NO_STRINGS => return self.decode(&self.ty, data),
+        };
+
+        let decoded = self.decode(&self.ty, data.clone());
+        if decoded
+            .as_ref()
+            .is_ok_and(|decoded| !Self::suspicious(&self.ty, &data, decoded))
+        {
+            return decoded;
+        }
+
+        match self.decode(&other, data.clone()) {
+            Ok(repaired) if !Self::suspicious(&other, &data, &repaired) => {
+                warn!(
+                    "string type {:?} does not match the string data, decoded {:?} as {:?} instead",
+                    self.ty, repaired, other
+                );
+                Ok(repaired)
+            }
+            _ => decoded,
+        }
+    }
+
+    fn suspicious(ty: &StringType, data: &[u8], decoded: &str) -> bool {
+        // NULs are fine, they separate the components of DIRB names
+        if decoded.chars().any(|c| c != '\0' && c.is_control()) {
+            return true;
+        }
+
+        match ty {
+            // UTF-16 read as single bytes leaves every other char NUL
+            StringType::ANSI_STR => {
+                data.len() >= 4 && data.iter().skip(1).step_by(2).all(|&b| b == 0)
+            }
+            // single bytes read as UTF-16 give no ASCII at all, even though every byte is ASCII
+            StringType::UNICODE_STR => {
+                data.len() % 2 == 1
+                    || (data.iter().all(|&b| b == 0 || (0x20..0x7f).contains(&b))
+                        && decoded.chars().all(|c| c == '\0' || !c.is_ascii()))
+            }
+            StringType::NO_STRINGS => false,
+        }
+    }
+}
+
+// Catalogs and filemarks address blocks with 32 bit (or 64 bit) numbers, which easily exceed
+// 32 bits once scaled to bytes. Offsets that can't be mapped on this platform are errors as well
+fn block_offset(start: u64, address: u64, block_size: u64) -> Result<u64> {
+    let offset = address
+        .checked_mul(block_size)
+        .and_then(|offset| offset.checked_add(start))
+        .ok_or_else(|| {
+            format_err!(
+                "block address {} with block size {} is out of range",
+                address,
+                block_size
+            )
+        })?;
+
+    if offset > usize::MAX as u64 {
+        return Err(format_err!(
+            "offset {} of block address {} can't be addressed on this platform",
+            offset,
+            address
+        ));
+    }
+
+    Ok(offset)
+}
+
+// Parses the VOLB, DIRB and FILE blocks of a handle with an iterator over the whole data
+pub(crate) fn resolve_with<'a>(
+    dblks: &mut DBLKIterator<'a>,
+    handle: EntryHandle,
+) -> Result<FileEntry<'a>> {
+    let mut resolver = PathResolver::default();
+
+    let offsets = [handle.volume, handle.directory, Some(handle.offset)];
+    for offset in offsets.iter().flatten() {
+        if *offset >= dblks.end {
+            return Err(format_err!(
+                "block {} of {:?} is beyond the end of the data",
+                offset,
+                handle
+            ));
+        }
+
+        dblks.position = *offset;
+        let dblk = dblks
+            .next()
+            .ok_or_else(|| format_err!("could not parse block {} of {:?}", offset, handle))?;
+
+        if let Some(mut entry) = resolver.resolve(dblk, dblks.options) {
+            entry.handle = handle;
+            return Ok(entry);
+        }
+    }
+
+    Err(format_err!("{:?} does not point to a FILE block", handle))
+}
+
+// Directory names are separated (and terminated) by NULs instead of a path separator
+fn split_path(name: &str) -> Vec<String> {
+    name.split('\0')
+        .filter(|component| !component.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MTFOptions {
+    pub oem_codepage: Codepage,
+    // collect the SPAD contents and the space skipped between blocks into DBLKWithStreams::slack,
+    // for carving remnants of earlier data
+    pub retain_slack: bool,
+    // used in place of the TAPE block if the data doesn't start with one
+    pub assumed_tape: Option<AssumedTape>,
+}
+
+// Parameters normally taken from the TAPE block, for images that start in the middle of a tape
+// (partial dd captures). The data then starts at the first DBLK that is found
+#[derive(Debug, Clone)]
+pub struct AssumedTape {
+    // in 512 byte blocks, like in the TAPE block
+    pub soft_filemark_block_size: u16,
+    pub format_logical_block_size: u16,
+    // for blocks whose header claims they have no strings
+    pub string_type: StringType,
+}
+
+impl Default for AssumedTape {
+    // what NTBackup writes
+    fn default() -> AssumedTape {
+        AssumedTape {
+            soft_filemark_block_size: 1,
+            format_logical_block_size: 1024,
+            string_type: StringType::UNICODE_STR,
+        }
+    }
+}
+
+impl AssumedTape {
+    fn tape(&self) -> DBLK {
+        let tape_attrs = if self.soft_filemark_block_size > 0 {
+            TapeAttrs::SOFT_FILE_MARK
+        } else {
+            TapeAttrs::empty()
+        };
+
+        DBLK {
+            header: CommonBlockHeader {
+                attrs: CommonBlockAttrs::TAPE(CommonBlockAttrsTAPE::empty()),
+                offset_to_first_event: 0,
+                osid: OS::WindowsNT,
+                osver: 0,
+                display_size: 0,
+                format_logical_address: 0,
+                control_block_id: 0,
+                os_specific_data: TapeAddress {
+                    size: 0,
+                    offset: 0,
+                    base: 0,
+                },
+                os_specific: OsSpecificData::NONE,
+                string_type: self.string_type.clone(),
+                header_checksum: 0,
+            },
+            body: DBLKSpecific::TAPE {
+                media_family_id: 0,
+                tape_attrs,
+                media_sequence_number: 0,
+                password_encryption_algorithm: 0,
+                soft_filemark_block_size: SoftFileMarkBlockSize::parse(
+                    self.soft_filemark_block_size,
+                ),
+                media_based_catalog_type: MediaBasedCatalogType::NONE,
+                media_name: None,
+                media_label: None,
+                media_description: None,
+                media_password: None,
+                software_name: None,
+                format_logical_block_size: self.format_logical_block_size,
+                software_vendor_id: 0,
+                media_date: DateTime::parse([0; 5]),
+                major_version: 1,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DBLKSets {
+    pub tape: Option<DBLK>,
+    pub set: Option<DBLK>,
+    pub vol: Option<DBLK>,
+    pub dir: Option<DBLK>,
+    pub file: Option<DBLK>,
+    pub soft_mark: Option<DBLK>,
+}
+
+impl DBLKSets {
+    fn update(&mut self, dblk: DBLK) {
+        use DBLKSpecific::*;
+
+        match dblk.body {
+            TAPE { .. } => self.tape = Some(dblk),
+            SSET { .. } => self.set = Some(dblk),
+            VOLB { .. } => self.vol = Some(dblk),
+            DIRB { .. } => self.dir = Some(dblk),
+            FILE { .. } => self.file = Some(dblk),
+            SFMB { .. } => self.soft_mark = Some(dblk),
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct MTFParser {
+    file: File,
+    sets: DBLKSets,
+    options: MTFOptions,
+    mmap: Option<Mmap>,
+    start: Option<MediaStart>,
+}
+
+// Where the MTF data actually starts, some images have a media label or other junk in front of the TAPE block
+#[derive(Debug, Clone)]
+pub struct MediaStart {
+    pub offset: u64,
+    pub label: Option<String>,
+}
+
+impl MediaStart {
+    // TAPE blocks start on a 512 byte boundary, so only look there
+    const ALIGNMENT: usize = 512;
+    const PROBE_LIMIT: usize = 64 * 1024;
+
+    // Without a TAPE block, the first block of any kind
+    fn probe_mid_tape(data: &[u8]) -> Result<MediaStart> {
+        let offset = (0..data.len())
+            .step_by(Self::ALIGNMENT)
+            .find(|&offset| Self::is_dblk_header(&data[offset..]))
+            .ok_or_else(|| format_err!("not an MTF file, no DBLK found"))?;
+
+        Ok(MediaStart {
+            offset: offset as u64,
+            label: None,
+        })
+    }
+
+    fn probe(data: &[u8]) -> Result<MediaStart> {
+        let offset = (0..Self::PROBE_LIMIT.min(data.len()))
+            .step_by(Self::ALIGNMENT)
+            .find(|&offset| Self::is_tape_header(&data[offset..]))
+            .ok_or_else(|| {
+                let magic = &data[..data.len().min(4)];
+                format_err!(
+                    "not an MTF file, no TAPE block found (starts with {:x?} / {:?})",
+                    magic,
+                    String::from_utf8_lossy(magic)
+                )
+            })?;
+
+        let label = String::from_utf8_lossy(&data[..offset]);
+        let label = label.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        let label = if label.is_empty() {
+            None
+        } else {
+            Some(label.to_string())
+        };
+
+        Ok(MediaStart {
+            offset: offset as u64,
+            label,
+        })
+    }
+
+    fn is_tape_header(data: &[u8]) -> bool {
+        data.starts_with(b"TAPE") && Self::is_dblk_header(data)
+    }
+
+    fn is_dblk_header(data: &[u8]) -> bool {
+        if data.len() < 52 {
+            return false;
+        }
+        if let DBLKType::UNKNOWN = DBLKType::parse(LittleEndian::read_u32(data)) {
+            return false;
+        }
+
+        let mut words = [0u16; 26];
+        LittleEndian::read_u16_into(&data[..52], &mut words);
+
+        words[..25].iter().fold(0, |checksum, word| checksum ^ word) == words[25]
+    }
+}
+
+#[derive(Debug)]
+pub struct DBLKWithStreams<'a> {
+    // of the DBLK in the file
+    pub offset: u64,
+    pub dblk: DBLK,
+    pub streams: Vec<StreamWithData<'a>>,
+    pub terminator: StreamTerminator,
+    pub context: BlockContext,
+    // only filled with MTFOptions::retain_slack
+    pub slack: Vec<Slack<'a>>,
+}
+
+// Bytes that are not part of any block or stream content, at their offset in the file
+#[derive(Debug)]
+pub struct Slack<'a> {
+    pub offset: u64,
+    pub data: &'a [u8],
+}
+
+// The blocks a DBLK belongs to, as they were when it was parsed. Unlike DBLKSets this
+// can't change anymore while the iterator goes on
+#[derive(Debug, Clone, Default)]
+pub struct BlockContext {
+    pub tape: Option<Arc<DBLK>>,
+    pub set: Option<Arc<DBLK>>,
+    pub vol: Option<Arc<DBLK>>,
+    pub dir: Option<Arc<DBLK>>,
+}
+
+impl BlockContext {
+    fn update(&mut self, dblk: &DBLK) {
+        use DBLKSpecific::*;
+
+        match dblk.body {
+            TAPE { .. } => self.tape = Some(Arc::new(dblk.clone())),
+            SSET { .. } => {
+                self.set = Some(Arc::new(dblk.clone()));
+                self.vol = None;
+                self.dir = None;
+            }
+            VOLB { .. } => {
+                self.vol = Some(Arc::new(dblk.clone()));
+                self.dir = None;
+            }
+            DIRB { .. } => self.dir = Some(Arc::new(dblk.clone())),
+            _ => {}
+        }
+    }
+}
+
+impl<'a> DBLKWithStreams<'a> {
+    // Verifies every stream that is followed by a CSUM stream, by its index into streams
+    pub fn verify_checksums(&self) -> Vec<(usize, Result<bool>)> {
+        self.streams
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| pair[1].id() == "CSUM")
+            .map(|(idx, pair)| (idx, pair[0].verify_checksum(&pair[1])))
+            .collect()
+    }
+
+    fn parse<C: AsRef<[u8]>>(
+        cursor: &mut Cursor<C>,
+        sets: &mut DBLKSets,
+        options: &MTFOptions,
+        data: &'a [u8],
+    ) -> Result<Self> {
+        let dblk_position = cursor.position();
+        let dblock = DBLK::parse(cursor, sets, options)
+            .map_err(|e| e.context(ParseContext::new(dblk_position, dblk_position, sets, data)))?;
+        sets.update(dblock.clone());
+
+        // all dblck's have atleast the SPAD stream
+        cursor.set_position(dblk_position + (dblock.header.offset_to_first_event as u64));
+
+        let streams_position = cursor.position();
+        let (streams, terminator) = StreamWithData::parse_all(cursor, data).map_err(|e| {
+            e.context(ParseContext::new(
+                dblk_position,
+                streams_position,
+                sets,
+                data,
+            ))
+        })?;
+
+        Ok(Self {
+            offset: dblk_position,
+            dblk: dblock,
+            streams,
+            terminator,
+            context: BlockContext::default(),
+            slack: Vec::new(),
+        })
+    }
+}
+
+// Where parsing failed, attached to every error coming out of the DBLK iterator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseContext {
+    pub offset: u64,
+    pub block_offset: u64,
+    pub block_id: String,
+    pub data_set_number: Option<u16>,
+    // a few bytes starting at offset
+    pub bytes: Vec<u8>,
+}
+
+impl ParseContext {
+    const MAX_BYTES: usize = 64;
+
+    fn new(block_offset: u64, offset: u64, sets: &DBLKSets, data: &[u8]) -> ParseContext {
+        let bytes_at = |offset: u64, len: usize| {
+            let start = (offset as usize).min(data.len());
+            &data[start..(start + len).min(data.len())]
+        };
+
+        let data_set_number = match &sets.set {
+            Some(DBLK {
+                body: DBLKSpecific::SSET {
+                    data_set_number, ..
+                },
+                ..
+            }) => Some(*data_set_number),
+            _ => None,
+        };
+
+        ParseContext {
+            offset,
+            block_offset,
+            block_id: String::from_utf8_lossy(bytes_at(block_offset, 4)).to_string(),
+            data_set_number,
+            bytes: bytes_at(offset, Self::MAX_BYTES).to_vec(),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "error at offset {:#x} in {:?} block at {:#x} (data set {:?}), data: {:02x?}",
+            self.offset, self.block_id, self.block_offset, self.data_set_number, self.bytes
+        )
+    }
+}
+
+// Something this crate knows of but can't handle, like an encryption algorithm no decryptor is
+// registered for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedFeature(pub String);
+
+impl std::fmt::Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedFeature {}
+
+// What an error of this crate is about, for tools that process many media and have to tell
+// broken media from missing features and from problems of the machine they run on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    CORRUPT_INPUT,
+    UNSUPPORTED_FEATURE,
+    IO,
+}
+
+impl ErrorClass {
+    // Reading past the end of the data is corrupt input, every other I/O error comes from the
+    // file system. Errors without a type of their own are parse errors
+    pub fn of(err: &Error) -> ErrorClass {
+        for cause in err.iter_chain() {
+            if cause.downcast_ref::<UnsupportedFeature>().is_some()
+                || cause
+                    .downcast_ref::<crate::mdf::CompressedBackupUnsupported>()
+                    .is_some()
+            {
+                return ErrorClass::UNSUPPORTED_FEATURE;
+            }
+
+            if let Some(err) = cause.downcast_ref::<std::io::Error>() {
+                if err.kind() != std::io::ErrorKind::UnexpectedEof {
+                    return ErrorClass::IO;
+                }
+            }
+        }
+
+        ErrorClass::CORRUPT_INPUT
+    }
+}
+
+#[derive(Debug)]
+pub struct StreamWithData<'a> {
+    pub stream: Stream,
+    pub data: &'a [u8],
+}
+
+impl<'a> StreamWithData<'a> {
+    fn parse_all<C: AsRef<[u8]>>(
+        cursor: &mut Cursor<C>,
+        data: &'a [u8],
+    ) -> Result<(Vec<Self>, StreamTerminator)> {
+        let (streams, terminator) = Stream::parse_all(cursor)?;
+
+        let streams = streams
+            .into_iter()
+            .map(|stream| StreamWithData::from_stream(stream, data))
+            .collect();
+
+        Ok((streams, terminator))
+    }
+
+    fn from_stream(stream: Stream, data: &'a [u8]) -> Self {
+        StreamWithData {
+            data: stream.data(data),
+            stream,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.stream.header.id
+    }
+
+    // Length as recorded in the stream header, data can be shorter if the file is truncated
+    pub fn len(&self) -> u64 {
+        self.stream.header.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The data ended before the length of the stream header
+    pub fn is_truncated(&self) -> bool {
+        (self.data.len() as u64) < self.len()
+    }
+
+    pub fn fs_attrs(&self) -> FileSystemAttributes {
+        self.stream.header.file_system_attributes
+    }
+
+    pub fn media_format_attrs(&self) -> MediaFormatAttributes {
+        self.stream.header.media_format_attributes
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.media_format_attrs()
+            .contains(MediaFormatAttributes::COMRESSED)
+    }
+
+    pub fn compression_algorithm(&self) -> CompressionAlgorithm {
+        self.stream.header.compression_algorithm
+    }
+
+    // The decompressed data of a COMRESSED stream, frame by frame. The algorithm of the stream
+    // header is used, if that is NONE the one of the set (SSET software_compression_algorithm).
+    // Data is compressed before it is encrypted, so an encrypted stream is decrypted first
+    pub fn decompressed_chunks(
+        &self,
+        set_algorithm: CompressionAlgorithm,
+    ) -> Result<compression::Frames<'a>> {
+        let algorithm = match self.compression_algorithm() {
+            CompressionAlgorithm::NONE => set_algorithm,
+            algorithm => algorithm,
+        };
+
+        Ok(compression::Frames::new(self.decrypted()?, algorithm))
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.media_format_attrs()
+            .contains(MediaFormatAttributes::ENCRYPTED)
+    }
+
+    pub fn encryption_algorithm(&self) -> EncryptionAlgorithm {
+        self.stream.header.encryption_algorithm
+    }
+
+    // The data of encrypted streams is decrypted with a registered crypto::Decryptor
+    pub fn decrypted(&self) -> Result<Cow<'a, [u8]>> {
+        if self.is_encrypted() {
+            crypto::decrypt(self.encryption_algorithm(), self.data).map(Cow::Owned)
+        } else {
+            Ok(Cow::Borrowed(self.data))
+        }
+    }
+
+    pub fn is_continuation(&self) -> bool {
+        self.media_format_attrs()
+            .contains(MediaFormatAttributes::CONTINUE)
+    }
+
+    // XOR of all (little endian) u32 words of the data, the last one zero padded
+    pub fn checksum(&self) -> u32 {
+        self.data.chunks(4).fold(0, |checksum, word| {
+            let mut padded = [0; 4];
+            padded[..word.len()].copy_from_slice(word);
+            checksum ^ u32::from_le_bytes(padded)
+        })
+    }
+
+    // A stream with CHECKSUMED set is followed by a CSUM stream holding its checksum
+    pub fn verify_checksum(&self, csum: &StreamWithData) -> Result<bool> {
+        if csum.id() != "CSUM" {
+            return Err(format_err!("expected a CSUM stream, got {}", csum.id()));
+        }
+
+        if self.is_truncated() {
+            return Err(format_err!(
+                "{} stream is truncated, can't verify its checksum",
+                self.id()
+            ));
+        }
+
+        let expected = csum
+            .data
+            .get(..4)
+            .map(LittleEndian::read_u32)
+            .ok_or_else(|| format_err!("CSUM stream truncated"))?;
+
+        Ok(self.checksum() == expected)
+    }
+}
+
+// Raw access to the underlying data, together with the blocks parsed so far
+#[derive(Debug)]
+pub struct BlockCursor<'a> {
+    pub sets: &'a DBLKSets,
+    pub cursor: Cursor<&'a [u8]>,
+}
+
+impl<'a> Read for BlockCursor<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.cursor.read(buf)
+    }
+}
+
+pub struct DBLKIterator<'a> {
+    sets: &'a mut DBLKSets,
+    options: &'a MTFOptions,
+    mmap: &'a Mmap,
+    start: u64,
+    position: u64,
+    end: u64,
+    summary: IterationSummary,
+    started: Instant,
+    context: BlockContext,
+    budget: ScanBudget,
+}
+
+// Limits for triage of large media, once one is reached the iteration stops early
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanBudget {
+    pub time: Option<Duration>,
+    pub bytes: Option<u64>,
+}
+
+impl ScanBudget {
+    fn exhausted(&self, elapsed: Duration, bytes: u64) -> bool {
+        self.time.is_some_and(|time| elapsed >= time) || self.bytes.is_some_and(|max| bytes >= max)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminationReason {
+    // reached the end of the data (or of the set)
+    END_OF_DATA,
+    // the next block could not be parsed without leaving the data
+    NO_PROGRESS,
+    // the ScanBudget was used up before the end of the data
+    BUDGET_EXHAUSTED,
+    // the next block is broken, context is None for errors outside of the block parser
+    PARSE_ERROR {
+        message: String,
+        context: Option<ParseContext>,
+    },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IterationSummary {
+    // number of blocks by their four character id
+    pub blocks: BTreeMap<String, u64>,
+    pub bytes_consumed: u64,
+    // from the start of the iteration to the end of the data
+    pub bytes_total: u64,
+    // blocks whose streams were cut off by the end of the data
+    pub warnings: u64,
+    // None if the iterator was not run to completion
+    pub termination: Option<TerminationReason>,
+}
+
+impl IterationSummary {
+    // The part of the data that was covered, below 1 if the iteration stopped early
+    pub fn coverage(&self) -> f64 {
+        if self.bytes_total == 0 {
+            1.0
+        } else {
+            (self.bytes_consumed as f64 / self.bytes_total as f64).min(1.0)
+        }
+    }
+}
+
+// An SSET of the medium, SQL Server appends one for every backup written to the same file
+#[derive(Debug, Clone)]
+pub struct SetInfo {
+    pub data_set_number: u16,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub write_date: DateTime,
+    // the data is still readable, see BackupSet::is_password_protected
+    pub password_protected: bool,
+    // of the SSET
+    pub offset: u64,
+}
+
+// The soft filemarks an SFMB lists. Entries are physical block addresses, a physical block being
+// one soft filemark block
+#[derive(Debug, Clone)]
+pub struct SoftFilemarkTable {
+    // room the SFMB has for entries
+    pub number_of_entries: u32,
+    // the used entries in the order they are stored, most recent filemark first
+    pub entries: Vec<SoftFilemark>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoftFilemark {
+    pub pba: u32,
+    // absolute byte offset in the file
+    pub offset: u64,
+}
+
+// How much a set shrank on the medium and how fast it was written
+#[derive(Debug, Clone)]
+pub struct SetStatistics {
+    pub data_set_number: u16,
+    // offset of the SSET
+    pub offset: u64,
+    // from the SSET up to the end of the ESET (or of the data)
+    pub bytes_consumed: u64,
+    // sum of the display sizes of all files of the set
+    pub original_bytes: u64,
+    pub files: u64,
+    // unix timestamps of the SSET and ESET, if they are set
+    pub started: Option<i64>,
+    pub finished: Option<i64>,
+}
+
+impl SetStatistics {
+    // original size per byte on the medium, above 1 if the set was compressed
+    pub fn compression_ratio(&self) -> Option<f64> {
+        if self.bytes_consumed == 0 {
+            None
+        } else {
+            Some(self.original_bytes as f64 / self.bytes_consumed as f64)
+        }
+    }
+
+    // original bytes per second while the backup was taken, the dates only have second resolution
+    pub fn throughput(&self) -> Option<f64> {
+        let seconds = self.finished? - self.started?;
+
+        if seconds > 0 {
+            Some(self.original_bytes as f64 / seconds as f64)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> DBLKIterator<'a> {
+    // After an ESPB there can be zero filled blocks (where the hardware filemark was on tape),
+    // the next DBLK starts at the first format logical block that is not zero
+    fn skip_padding(&mut self) {
+        let block_size = match &self.sets.tape {
+            Some(DBLK {
+                body:
+                    DBLKSpecific::TAPE {
+                        format_logical_block_size,
+                        ..
+                    },
+                ..
+            }) if *format_logical_block_size > 0 => *format_logical_block_size as u64,
+            _ => return,
+        };
+
+        let mut position = self.position.div_ceil(block_size) * block_size;
+
+        while let Some(magic) = self.mmap.get(position as usize..position as usize + 4) {
+            if magic != [0; 4] {
+                break;
+            }
+            position += block_size;
+        }
+
+        self.position = position;
+    }
+
+    fn new(
+        sets: &'a mut DBLKSets,
+        options: &'a MTFOptions,
+        mmap: &'a Mmap,
+        start: u64,
+        end: u64,
+    ) -> Self {
+        // iterating a single set starts after the TAPE block
+        let context = BlockContext {
+            tape: sets.tape.clone().map(Arc::new),
+            ..Default::default()
+        };
+
+        Self {
+            sets,
+            options,
+            mmap,
+            start,
+            position: start,
+            end,
+            summary: IterationSummary {
+                bytes_total: end.saturating_sub(start),
+                ..Default::default()
+            },
+            started: Instant::now(),
+            context,
+            budget: ScanBudget::default(),
+        }
+    }
+
+    // The budget counts from the creation of the iterator
+    pub fn with_budget(mut self, budget: ScanBudget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    fn summary(&self) -> IterationSummary {
+        IterationSummary {
+            bytes_consumed: self.position - self.start,
+            ..self.summary.clone()
+        }
+    }
+
+    fn terminate(&mut self, reason: TerminationReason) {
+        if self.summary.termination.is_none() {
+            self.summary.termination = Some(reason);
+
+            let summary = self.summary();
+            metrics::report(|metrics| metrics.iteration_finished(&summary, self.started.elapsed()));
+            audit::record(AuditEvent::SCANNED {
+                start: self.start,
+                end: self.position,
+                blocks: summary.blocks.values().sum(),
+            });
+        }
+    }
+
+    pub fn finish(self) -> IterationSummary {
+        self.summary()
+    }
+}
+
+impl<'a> Iterator for DBLKIterator<'a> {
+    type Item = DBLKWithStreams<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.summary.termination.is_some() {
+            return None;
+        }
+
+        if self.position >= self.end {
+            self.terminate(TerminationReason::END_OF_DATA);
+            return None;
+        }
+
+        if self
+            .budget
+            .exhausted(self.started.elapsed(), self.position - self.start)
+        {
+            self.terminate(TerminationReason::BUDGET_EXHAUSTED);
+            return None;
+        }
+
+        let mut cursor = Cursor::new(self.mmap);
+        // We would like to save the cursor, but self referential stuff is hard...
+        // So we just save the position and then recreate the Cursor...
+        cursor.set_position(self.position);
+
+        // stop at the first broken block, finish() tells why
+        let mut dblk = match DBLKWithStreams::parse(&mut cursor, self.sets, self.options, self.mmap)
+        {
+            Ok(dblk) => dblk,
+            Err(err) => {
+                let context = err
+                    .downcast_ref::<failure::Context<ParseContext>>()
+                    .map(|context| context.get_context().clone());
+                self.terminate(TerminationReason::PARSE_ERROR {
+                    message: err.find_root_cause().to_string(),
+                    context,
+                });
+                return None;
+            }
+        };
+        self.context.update(&dblk.dblk);
+        dblk.context = self.context.clone();
+
+        // we don't really have proper detection when the file ends, so for now try to parse the next block
+        // and the cursor will prevent going further than the bounds, so just look if we did not move
+        if self.position != cursor.position() {
+            let id = &self.mmap[self.position as usize..][..4];
+            *self
+                .summary
+                .blocks
+                .entry(String::from_utf8_lossy(id).into_owned())
+                .or_default() += 1;
+
+            if let StreamTerminator::EOF = dblk.terminator {
+                self.summary.warnings += 1;
+            }
+
+            self.position = cursor.position();
+
+            if self.options.retain_slack {
+                dblk.slack.extend(
+                    dblk.streams
+                        .iter()
+                        .filter(|stream| stream.id() == "SPAD")
+                        .map(|stream| Slack {
+                            offset: stream.stream.base,
+                            data: stream.data,
+                        }),
+                );
+            }
+
+            if let DBLKSpecific::ESPB = dblk.dblk.body {
+                let before = self.position;
+                self.skip_padding();
+
+                // only the first bytes of the skipped blocks are known to be zero
+                let len = self.mmap.len() as u64;
+                if self.options.retain_slack && self.position.min(len) > before {
+                    dblk.slack.push(Slack {
+                        offset: before,
+                        data: &self.mmap[before as usize..self.position.min(len) as usize],
+                    });
+                }
+            }
+
+            Some(dblk)
+        } else {
+            self.terminate(TerminationReason::NO_PROGRESS);
+            None
+        }
+    }
+}
+
+// What can be learned about a medium from its TAPE block alone
+#[derive(Debug, Clone)]
+pub struct MediaSummary {
+    pub format_family: FormatFamily,
+    pub label: Option<String>,
+    pub major_version: u8,
+    pub media_name: Option<String>,
+    pub media_label: Option<MediaLabel>,
+    pub software_name: Option<String>,
+    pub software_vendor_id: u16,
+    pub media_date: DateTime,
+}
+
+// The product that wrote the medium. Microsoft uses the same software vendor id for NTBackup and
+// SQL Server, so this goes by the software name of the TAPE block and the vendor of the media label
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatFamily {
+    NTBACKUP,
+    SQL_SERVER,
+    BACKUP_EXEC,
+    UNKNOWN,
+}
+
+impl FormatFamily {
+    fn detect(software_name: Option<&str>, media_label: Option<&MediaLabel>) -> FormatFamily {
+        let names = [
+            software_name,
+            media_label.map(|label| label.vendor.as_str()),
+        ];
+
+        for name in names.iter().flatten().map(|name| name.to_lowercase()) {
+            if name.contains("sql server") {
+                return FormatFamily::SQL_SERVER;
+            }
+            if name.contains("ntbackup") || name.contains("windows nt backup") {
+                return FormatFamily::NTBACKUP;
+            }
+            // sold by Seagate Software, VERITAS and Symantec over the years
+            if ["backup exec", "veritas", "seagate", "symantec"]
+                .iter()
+                .any(|vendor| name.contains(vendor))
+            {
+                return FormatFamily::BACKUP_EXEC;
+            }
+        }
+
+        FormatFamily::UNKNOWN
+    }
+}
+
+// Reads only the first blocks of the input, without mapping the file or building any index
+pub fn identify<R: Read>(reader: R) -> Option<MediaSummary> {
+    // leave room for the TAPE block itself after the furthest probed offset
+    let mut data = Vec::new();
+    reader
+        .take(2 * MediaStart::PROBE_LIMIT as u64)
+        .read_to_end(&mut data)
+        .ok()?;
+
+    let start = MediaStart::probe(&data).ok()?;
+
+    let mut cursor = Cursor::new(&data);
+    cursor.set_position(start.offset);
+
+    let dblk = DBLK::parse(&mut cursor, &DBLKSets::default(), &MTFOptions::default()).ok()?;
+
+    match dblk.body {
+        DBLKSpecific::TAPE {
+            media_name,
+            media_label,
+            software_name,
+            software_vendor_id,
+            media_date,
+            major_version,
+            ..
+        } => Some(MediaSummary {
+            format_family: FormatFamily::detect(software_name.as_deref(), media_label.as_ref()),
+            label: start.label,
+            major_version,
+            media_name,
+            media_label,
+            software_name,
+            software_vendor_id,
+            media_date,
+        }),
+        _ => None,
+    }
+}
+
+impl MTFParser {
+    pub fn new(filename: &str) -> MTFParser {
+        MTFParser::with_options(filename, MTFOptions::default())
+    }
+
+    // The image files of a media family, in any order
+    pub fn new_family(filenames: &[&str]) -> Result<MediaFamily> {
+        MediaFamily::assemble(filenames, MTFOptions::default())
+    }
+
+    pub fn new_family_with_options(filenames: &[&str], options: MTFOptions) -> Result<MediaFamily> {
+        MediaFamily::assemble(filenames, options)
+    }
+
+    pub fn with_options(filename: &str, options: MTFOptions) -> MTFParser {
+        MTFParser::open(filename, options).unwrap()
+    }
+
+    fn open(filename: &str, options: MTFOptions) -> Result<MTFParser> {
+        let file = File::open(filename).map_err(|err| {
+            audit::record(AuditEvent::ERROR {
+                operation: "open",
+                message: format!("{}: {}", filename, err),
+            });
+            err
+        })?;
+        audit::record(AuditEvent::OPENED { path: filename });
+
+        Ok(MTFParser {
+            file,
+            mmap: None,
+            start: None,
+            options,
+            sets: DBLKSets::default(),
+        })
+    }
+
+    // Unmaps the file, for services that keep many parsers around. It is mapped again on the next
+    // use, the file handle, the probed start and the options are kept so that is cheap.
+    pub fn release(&mut self) {
+        self.mmap = None;
+    }
+
+    fn map(&mut self) {
+        if self.mmap.is_none() {
+            self.mmap = Some(unsafe { Mmap::map(&self.file).unwrap() });
+        }
+    }
+
+    pub fn media_start(&mut self) -> Result<MediaStart> {
+        if self.start.is_none() {
+            self.map();
+            let data = self.mmap.as_ref().unwrap();
+
+            self.start = Some(
+                match (MediaStart::probe(data), &self.options.assumed_tape) {
+                    (Ok(start), _) => start,
+                    (Err(_), Some(assumed)) => {
+                        let start = MediaStart::probe_mid_tape(data)?;
+                        self.sets.tape = Some(assumed.tape());
+                        start
+                    }
+                    (Err(err), None) => return Err(err),
+                },
+            );
+        }
+
+        Ok(self.start.clone().unwrap())
+    }
+
+    // Fails if the file is no MTF medium
+    pub fn dblks(&mut self) -> Result<DBLKIterator<'_>> {
+        let start = self.media_start()?;
+        let mmap = self.mmap.as_ref().unwrap();
+        Ok(DBLKIterator::new(
+            &mut self.sets,
+            &self.options,
+            mmap,
+            start.offset,
+            mmap.len() as u64,
+        ))
+    }
+
+    // Offsets of all SSET blocks on the medium
+    pub fn set_offsets(&mut self) -> Result<Vec<u64>> {
+        Ok(self.backup_sets()?.iter().map(|set| set.offset).collect())
+    }
+
+    // The TAPE block, parsed again if necessary. The assumed one for media without one
+    fn tape(&mut self) -> Result<DBLK> {
+        match self.dblks()?.next().map(|dblk| dblk.dblk) {
+            Some(
+                tape @ DBLK {
+                    body: DBLKSpecific::TAPE { .. },
+                    ..
+                },
+            ) => Ok(tape),
+            _ => match &self.options.assumed_tape {
+                Some(assumed) => Ok(assumed.tape()),
+                None => Err(format_err!("medium does not start with a TAPE block")),
+            },
+        }
+    }
+
+    pub fn catalog_type(&mut self) -> Result<MediaBasedCatalogType> {
+        match self.tape()?.body {
+            DBLKSpecific::TAPE {
+                media_based_catalog_type,
+                ..
+            } => Ok(media_based_catalog_type),
+            _ => unreachable!(),
+        }
+    }
+
+    // The set map of the catalog, None if the medium has none. Type 1 catalogs keep it in a TSMP,
+    // type 2 catalogs in a MAP2 stream of the ESETs. The last ESET with a set map is searched for
+    // from the end of the data, so this doesn't need a pass over all DBLKs
+    pub fn set_map(&mut self) -> Result<Option<SetMap>> {
+        // the TAPE block tells whether there is a set map and the block size to search with
+        let tape = self.tape()?;
+        let (block_size, ids): (u64, &[&str]) = match &tape.body {
+            DBLKSpecific::TAPE {
+                format_logical_block_size,
+                media_based_catalog_type,
+                ..
+            } => (
+                *format_logical_block_size as u64,
+                match media_based_catalog_type {
+                    MediaBasedCatalogType::TYPE_1 => &["TSMP"],
+                    // some writers use the type 1 stream for type 2 catalogs as well
+                    MediaBasedCatalogType::TYPE_2 => &["MAP2", "TSMP"],
+                    _ => return Ok(None),
+                },
+            ),
+            _ => unreachable!(),
+        };
+        let has_set_map = matches!(
+            &tape.header.attrs,
+            CommonBlockAttrs::TAPE(attrs) if attrs.contains(CommonBlockAttrsTAPE::SET_MAP_EXISTS)
+        );
+
+        if !has_set_map {
+            return Ok(None);
+        }
+        if block_size < 4 {
+            return Err(format_err!(
+                "invalid format logical block size {}",
+                block_size
+            ));
+        }
+
+        let start = self.media_start()?.offset;
+        let mmap = self.mmap.as_ref().unwrap();
+        let end = mmap.len() as u64;
+
+        let mut offset = start + (end - start) / block_size * block_size;
+        while offset > start {
+            offset -= block_size;
+
+            if &mmap[offset as usize..offset as usize + 4] != b"ESET" {
+                continue;
+            }
+
+            let eset = DBLKIterator::new(&mut self.sets, &self.options, mmap, offset, end).next();
+            if let Some(eset) = eset {
+                let set_map = eset
+                    .streams
+                    .iter()
+                    .find(|stream| ids.contains(&stream.id()));
+
+                if let Some(stream) = set_map {
+                    let strings = StringDecoder::new(&eset.dblk.header, &self.options);
+                    return SetMap::parse(stream.data, &strings).map(Some);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    // Byte offset of a physical block address, as used by the catalogs. On disk images a physical
+    // block is one format logical block
+    pub fn pba_offset(&mut self, pba: u64) -> Result<u64> {
+        let block_size = match self.tape()?.body {
+            DBLKSpecific::TAPE {
+                format_logical_block_size,
+                ..
+            } => format_logical_block_size as u64,
+            _ => unreachable!(),
+        };
+
+        block_offset(self.media_start()?.offset, pba, block_size)
+    }
+
+    // Byte offsets of the soft filemarks an SFMB lists, in the order they are stored
+    pub fn soft_filemarks(&mut self, sfmb: &DBLK) -> Result<Vec<u64>> {
+        Ok(self
+            .soft_filemark_table(sfmb)?
+            .entries
+            .iter()
+            .map(|filemark| filemark.offset)
+            .collect())
+    }
+
+    // Every used entry of an SFMB with its byte offset. A soft filemark is itself an SFMB, so
+    // every entry has to point at one, on a format logical block boundary inside the medium
+    pub fn soft_filemark_table(&mut self, sfmb: &DBLK) -> Result<SoftFilemarkTable> {
+        let (number_of_entries, used_entries, entries) = match &sfmb.body {
+            DBLKSpecific::SFMB {
+                number_of_entries,
+                used_entries,
+                entries,
+            } => (*number_of_entries, *used_entries as usize, entries),
+            _ => return Err(format_err!("not an SFMB block: {:?}", sfmb.body)),
+        };
+
+        let entries = entries.get(..used_entries).ok_or_else(|| {
+            format_err!(
+                "SFMB claims {} used entries, but only has room for {}",
+                used_entries,
+                entries.len()
+            )
+        })?;
+
+        let (soft_filemark_block_size, format_logical_block_size) = match self.tape()?.body {
+            DBLKSpecific::TAPE {
+                soft_filemark_block_size,
+                format_logical_block_size,
+                ..
+            } => (
+                soft_filemark_block_size.bytes(),
+                format_logical_block_size as u64,
+            ),
+            _ => unreachable!(),
+        };
+        let start = self.media_start()?.offset;
+        let mmap = self.mmap.as_ref().unwrap();
+
+        let entries = entries
+            .iter()
+            .enumerate()
+            .map(|(idx, &pba)| {
+                let offset = block_offset(start, pba as u64, soft_filemark_block_size)?;
+
+                if (offset - start) % format_logical_block_size.max(1) != 0 {
+                    return Err(format_err!(
+                        "soft filemark {} at {} is not on a format logical block boundary",
+                        idx,
+                        offset
+                    ));
+                }
+
+                match mmap.get(offset as usize..offset as usize + 4) {
+                    Some(b"SFMB") => Ok(SoftFilemark { pba, offset }),
+                    Some(id) => Err(format_err!(
+                        "soft filemark {} at {} points at {:?} instead of an SFMB",
+                        idx,
+                        offset,
+                        String::from_utf8_lossy(id)
+                    )),
+                    None => Err(format_err!(
+                        "soft filemark {} at {} is past the end of the medium",
+                        idx,
+                        offset
+                    )),
+                }
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(SoftFilemarkTable {
+            number_of_entries,
+            entries,
+        })
+    }
+
+    // The file/directory detail of every set, by data set number. Sets without one are left out.
+    // With a set map only the DBLKs holding the catalogs are read. Type 1 catalogs can also come
+    // without one, their FDDs follow the ESET of every set, so all DBLKs are read then
+    pub fn fdds(&mut self) -> Result<Vec<(u16, Fdd)>> {
+        let catalog_type = self.catalog_type()?;
+        let ids: &[&str] = match catalog_type {
+            MediaBasedCatalogType::TYPE_1 => &["TFDD"],
+            MediaBasedCatalogType::TYPE_2 => &["FDD2", "TFDD"],
+            _ => return Ok(Vec::new()),
+        };
+
+        let set_map = match self.set_map()? {
+            Some(set_map) => set_map,
+            None if matches!(catalog_type, MediaBasedCatalogType::TYPE_1) => {
+                return self.interleaved_fdds(ids)
+            }
+            None => return Ok(Vec::new()),
+        };
+
+        let mut fdds = Vec::new();
+        for entry in set_map.entries {
+            if entry.fdd_pba == 0 {
+                continue;
+            }
+
+            let offset = self.pba_offset(entry.fdd_pba)?;
+            let end = self.mmap.as_ref().unwrap().len() as u64;
+            if offset >= end {
+                return Err(format_err!(
+                    "fdd of set {} at {} is beyond the end of the data",
+                    entry.data_set_number,
+                    offset
+                ));
+            }
+
+            let mmap = self.mmap.as_ref().unwrap();
+            let dblk = DBLKIterator::new(&mut self.sets, &self.options, mmap, offset, end).next();
+            let stream = dblk.as_ref().and_then(|dblk| {
+                dblk.streams
+                    .iter()
+                    .find(|stream| ids.contains(&stream.id()))
+            });
+
+            match stream {
+                Some(stream) => fdds.push((
+                    entry.data_set_number,
+                    Fdd::parse(stream.data, &self.options)?,
+                )),
+                None => {
+                    return Err(format_err!(
+                        "no fdd at {} for set {}",
+                        offset,
+                        entry.data_set_number
+                    ))
+                }
+            }
+        }
+
+        Ok(fdds)
+    }
+
+    // The files of a set from its FDD, None if there is none. Only the files on this medium of
+    // the family are listed
+    pub fn catalog_files(&mut self, set_number: u16) -> Result<Option<CatalogFiles<'_>>> {
+        let fdd = match self
+            .fdds()?
+            .into_iter()
+            .find(|(number, _)| *number == set_number)
+        {
+            Some((_, fdd)) => fdd,
+            None => return Ok(None),
+        };
+        let media_sequence_number = match self.tape()?.body {
+            DBLKSpecific::TAPE {
+                media_sequence_number,
+                ..
+            } => media_sequence_number,
+            _ => unreachable!(),
+        };
+
+        let mut handles = Vec::new();
+        let (mut volume, mut directory) = (None, None);
+        for entry in &fdd.entries {
+            if entry.media_sequence_number != media_sequence_number {
+                continue;
+            }
+
+            let offset = self.pba_offset(entry.format_logical_address)?;
+            match entry.body {
+                FddBody::VOLB { .. } => {
+                    volume = Some(offset);
+                    directory = None;
+                }
+                FddBody::DIRB { .. } => directory = Some(offset),
+                FddBody::FILE { .. } => handles.push(EntryHandle {
+                    data_set_number: Some(set_number),
+                    offset,
+                    volume,
+                    directory,
+                }),
+                FddBody::UNKNOWN(_) => {}
+            }
+        }
+
+        let start = self.media_start()?.offset;
+        let mmap = self.mmap.as_ref().unwrap();
+        let dblks = DBLKIterator::new(
+            &mut self.sets,
+            &self.options,
+            mmap,
+            start,
+            mmap.len() as u64,
+        );
+        Ok(Some(CatalogFiles::new(dblks, handles)))
+    }
+
+    fn interleaved_fdds(&mut self, ids: &[&str]) -> Result<Vec<(u16, Fdd)>> {
+        let options = self.options.clone();
+        let mut fdds = Vec::new();
+
+        for dblk in self.dblks()? {
+            if let DBLKSpecific::ESET {
+                data_set_number, ..
+            } = dblk.dblk.body
+            {
+                if let Some(stream) = dblk
+                    .streams
+                    .iter()
+                    .find(|stream| ids.contains(&stream.id()))
+                {
+                    fdds.push((data_set_number, Fdd::parse(stream.data, &options)?));
+                }
+            }
+        }
+
+        Ok(fdds)
+    }
 
-#[derive(Debug, Clone)]
-pub enum StringType {
-    NO_STRINGS,
-    ANSI_STR,
-    UNICODE_STR,
-}
+    // Parses the entry of a handle again, from the VOLB, DIRB and FILE blocks it was resolved from
+    pub fn resolve(&mut self, handle: EntryHandle) -> Result<FileEntry<'_>> {
+        // the TAPE block has to be known to parse the other blocks
+        self.tape()?;
 
-impl StringType {
-    fn parse(ty: u8) -> Result<StringType> {
-        use StringType::*;
+        let mmap = self.mmap.as_ref().unwrap();
+        let end = mmap.len() as u64;
+        let mut dblks = DBLKIterator::new(&mut self.sets, &self.options, mmap, handle.offset, end);
 
-        match ty {
-            0 => Ok(NO_STRINGS),
-            1 => Ok(ANSI_STR),
-            2 => Ok(UNICODE_STR),
-            _ => Err(format_err!("invalid string type {}", ty)),
-        }
+        resolve_with(&mut dblks, handle)
     }
 
-    fn bytes_to_string(&self, data: Vec<u8>) -> Result<String> {
-        use StringType::*;
+    // Statistics of every set, in media order. This needs one pass over all DBLKs
+    pub fn set_statistics(&mut self) -> Result<Vec<SetStatistics>> {
+        let mut dblks = self.dblks()?;
+        let mut statistics: Vec<SetStatistics> = Vec::new();
+        let mut in_set = false;
 
-        match self {
-            NO_STRINGS => Err(format_err!(
-                "string type was set to NO_STRINGS, but wanted to convert {:#?} to a string",
-                data
-            )),
-            ANSI_STR => Ok(String::from_utf8(data)?),
-            UNICODE_STR => Ok(String::from_utf16(
-                &data
-                    .chunks_exact(2)
-                    .into_iter()
-                    .map(|a| u16::from_le_bytes([a[0], a[1]]))
-                    .collect::<Vec<_>>(),
-            )?),
+        loop {
+            let position = dblks.position;
+            let dblk = match dblks.next() {
+                Some(dblk) => dblk.dblk,
+                None => break Ok(statistics),
+            };
+
+            match &dblk.body {
+                DBLKSpecific::SSET {
+                    data_set_number,
+                    write_date,
+                    ..
+                } => {
+                    in_set = true;
+                    statistics.push(SetStatistics {
+                        data_set_number: *data_set_number,
+                        offset: position,
+                        bytes_consumed: 0,
+                        original_bytes: 0,
+                        files: 0,
+                        started: write_date.unix_timestamp(),
+                        finished: None,
+                    })
+                }
+                DBLKSpecific::FILE { .. } => {
+                    if let Some(set) = statistics.last_mut() {
+                        set.original_bytes += dblk.header.display_size;
+                        set.files += 1;
+                    }
+                }
+                DBLKSpecific::ESET {
+                    media_write_date, ..
+                } => {
+                    if let Some(set) = statistics.last_mut() {
+                        set.finished = media_write_date.unix_timestamp();
+                    }
+                }
+                _ => {}
+            }
+
+            // blocks after the ESET (EOTM, SFMB, ...) don't belong to the set anymore
+            if let Some(set) = statistics.last_mut().filter(|_| in_set) {
+                set.bytes_consumed = dblks.position - set.offset;
+            }
+            if let DBLKSpecific::ESET { .. } = dblk.body {
+                in_set = false;
+            }
         }
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct DBLKSets {
-    tape: Option<DBLK>,
-    set: Option<DBLK>,
-    vol: Option<DBLK>,
-    dir: Option<DBLK>,
-    file: Option<DBLK>,
-    soft_mark: Option<DBLK>,
-}
+    pub fn backup_sets(&mut self) -> Result<Vec<SetInfo>> {
+        let mut dblks = self.dblks()?;
+        let mut sets = Vec::new();
 
-impl DBLKSets {
-    fn update(&mut self, dblk: DBLK) {
-        use DBLKSpecific::*;
+        loop {
+            let position = dblks.position;
 
-        match dblk.body {
-            TAPE { .. } => self.tape = Some(dblk),
-            SSET { .. } => self.set = Some(dblk),
-            VOLB { .. } => self.vol = Some(dblk),
-            DIRB { .. } => self.dir = Some(dblk),
-            FILE { .. } => self.file = Some(dblk),
-            SFMB { .. } => self.soft_mark = Some(dblk),
-            _ => {}
+            match dblks.next().map(|dblk| dblk.dblk.body) {
+                Some(DBLKSpecific::SSET {
+                    data_set_number,
+                    data_set_name,
+                    data_set_description,
+                    data_set_password,
+                    write_date,
+                    ..
+                }) => sets.push(SetInfo {
+                    data_set_number,
+                    name: data_set_name,
+                    description: data_set_description,
+                    write_date,
+                    password_protected: data_set_password
+                        .is_some_and(|password| !password.is_empty()),
+                    offset: position,
+                }),
+                Some(_) => {}
+                None => break Ok(sets),
+            }
         }
     }
-}
-
-#[derive(Debug)]
-pub struct MTFParser {
-    file: File,
-    sets: DBLKSets,
-    mmap: Option<Mmap>,
-}
 
-#[derive(Debug)]
-pub struct DBLKWithStreams<'a> {
-    pub dblk: DBLK,
-    pub streams: Vec<StreamWithData<'a>>,
-}
+    // Only the DBLKs of the set with the given data set number, from its SSET up to the next one,
+    // like FILE = n of RESTORE in SQL Server
+    pub fn set(&mut self, data_set_number: u16) -> Result<DBLKIterator<'_>> {
+        let sets = self.backup_sets()?;
+        let idx = sets
+            .iter()
+            .position(|set| set.data_set_number == data_set_number)
+            .ok_or_else(|| format_err!("no backup set {}", data_set_number))?;
 
-impl<'a> DBLKWithStreams<'a> {
-    fn parse<C: AsRef<[u8]>>(cursor: &mut Cursor<C>, sets: &mut DBLKSets, data: &'a [u8]) -> Self {
-        let dblk_position = cursor.position();
-        let dblock = DBLK::parse(cursor, sets).unwrap();
-        sets.update(dblock.clone());
+        let mmap = self.mmap.as_ref().unwrap();
+        let start = sets[idx].offset;
+        let end = sets
+            .get(idx + 1)
+            .map_or(mmap.len() as u64, |next| next.offset);
+
+        self.sets = DBLKSets {
+            tape: self.sets.tape.take(),
+            ..Default::default()
+        };
 
-        // all dblck's have atleast the SPAD stream
-        cursor.set_position(dblk_position + (dblock.header.offset_to_first_event as u64));
+        Ok(DBLKIterator::new(
+            &mut self.sets,
+            &self.options,
+            mmap,
+            start,
+            end,
+        ))
+    }
 
-        let streams = StreamWithData::parse_all(cursor, data);
+    // Hands every set to f on its own thread, each with its own DBLKSets and an iterator
+    // that stops at the next set. This needs one pass over all DBLKs first to find the sets.
+    pub fn sets_par<F, T>(&mut self, f: F) -> Result<Vec<T>>
+    where
+        F: Fn(DBLKIterator) -> T + Sync + Send,
+        T: Send,
+    {
+        let starts = self.set_offsets()?;
+        let tape = self.sets.tape.clone();
+        let options = &self.options;
+        let mmap = self.mmap.as_ref().unwrap();
 
-        Self {
-            dblk: dblock,
-            streams,
-        }
+        let ends = starts
+            .iter()
+            .skip(1)
+            .cloned()
+            .chain(std::iter::once(mmap.len() as u64));
+        let ranges: Vec<(u64, u64)> = starts.iter().cloned().zip(ends).collect();
+
+        let results = ranges
+            .into_par_iter()
+            .map(|(start, end)| {
+                let mut sets = DBLKSets {
+                    tape: tape.clone(),
+                    ..Default::default()
+                };
+                f(DBLKIterator::new(&mut sets, options, mmap, start, end))
+            })
+            .collect();
+
+        Ok(results)
     }
-}
 
-#[derive(Debug)]
-pub struct StreamWithData<'a> {
-    pub stream: Stream,
-    pub data: &'a [u8],
-}
+    pub fn files(&mut self) -> Result<FileIterator<'_>> {
+        Ok(FileIterator::new(self.dblks()?))
+    }
 
-impl<'a> StreamWithData<'a> {
-    fn parse_all<C: AsRef<[u8]>>(cursor: &mut Cursor<C>, data: &'a [u8]) -> Vec<Self> {
-        Stream::parse_all(cursor)
-            .unwrap()
-            .into_iter()
-            .map(|stream| StreamWithData::from_stream(stream, data))
-            .collect()
+    pub fn file_records(&mut self) -> Result<FileRecords<'_>> {
+        Ok(FileRecords::new(self.dblks()?))
     }
 
-    fn from_stream(stream: Stream, data: &'a [u8]) -> Self {
-        StreamWithData {
-            data: stream.data(data),
-            stream,
-        }
+    pub fn sets(&mut self) -> Result<Vec<BackupSet<'_>>> {
+        Ok(BackupSet::build(&mut self.dblks()?))
     }
-}
 
-pub struct DBLKIterator<'a> {
-    sets: &'a mut DBLKSets,
-    mmap: &'a Mmap,
-    position: u64,
-}
+    // Whatever sets could be built within the budget, the summary tells how much of the medium
+    // they cover
+    pub fn partial_sets(
+        &mut self,
+        budget: ScanBudget,
+    ) -> Result<(Vec<BackupSet<'_>>, IterationSummary)> {
+        let mut dblks = self.dblks()?.with_budget(budget);
+        let sets = BackupSet::build(&mut dblks);
 
-impl<'a> DBLKIterator<'a> {
-    fn new(sets: &'a mut DBLKSets, mmap: &'a Mmap) -> Self {
-        Self {
-            sets,
-            mmap,
-            position: 0,
-        }
+        Ok((sets, dblks.finish()))
     }
-}
 
-impl<'a> Iterator for DBLKIterator<'a> {
-    type Item = DBLKWithStreams<'a>;
+    // See sets::content_hash
+    pub fn catalog_hash(&mut self) -> Result<[u8; 32]> {
+        Ok(sets::content_hash(&self.sets()?))
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut cursor = Cursor::new(self.mmap);
-        // We would like to save the cursor, but self referential stuff is hard...
-        // So we just save the position and then recreate the Cursor...
-        cursor.set_position(self.position);
+    // Writes the data of all streams with the given id of one set in media order. A stream that is
+    // split over multiple DBLKs continues in the next stream with the same id, so this merges the
+    // fragments. Only the one set, the MQDA streams of appended backups are different databases
+    pub fn write_streams<W: Write>(
+        &mut self,
+        data_set_number: u16,
+        id: &str,
+        mut out: W,
+    ) -> Result<u64> {
+        let mut written = 0;
+
+        for dblk in self.set(data_set_number)? {
+            for stream in dblk.streams.iter().filter(|stream| stream.id() == id) {
+                out.write_all(stream.data)?;
+                written += stream.data.len() as u64;
+            }
+        }
 
-        let dblk = DBLKWithStreams::parse(&mut cursor, self.sets, self.mmap);
+        Ok(written)
+    }
 
-        // we don't really have proper detection when the file ends, so for now try to parse the next block
-        // and the cursor will prevent going further than the bounds, so just look if we did not move
-        if self.position != cursor.position() {
-            self.position = cursor.position();
+    pub fn cursor_at(&mut self, offset: u64) -> Option<BlockCursor<'_>> {
+        self.map();
+        let data: &[u8] = self.mmap.as_ref().unwrap();
 
-            Some(dblk)
-        } else {
-            None
+        if offset > data.len() as u64 {
+            return None;
         }
-    }
-}
 
-impl MTFParser {
-    pub fn new(filename: &str) -> MTFParser {
-        MTFParser {
-            file: File::open(filename).unwrap(),
-            mmap: None,
-            sets: DBLKSets {
-                tape: None,
-                set: None,
-                vol: None,
-                dir: None,
-                file: None,
-                soft_mark: None,
-            },
-        }
-    }
+        let mut cursor = Cursor::new(data);
+        cursor.set_position(offset);
 
-    pub fn dblks(&mut self) -> impl Iterator<Item = DBLKWithStreams> {
-        if self.mmap.is_none() {
-            self.mmap = Some(unsafe { Mmap::map(&self.file).unwrap() });
-        }
-        let mmap = self.mmap.as_ref().unwrap();
-        DBLKIterator::new(&mut self.sets, &mmap)
+        Some(BlockCursor {
+            sets: &self.sets,
+            cursor,
+        })
     }
 }
 
 impl DBLK {
-    fn parse<T: AsRef<[u8]>>(data: &mut Cursor<T>, sets: &DBLKSets) -> Result<DBLK> {
+    pub fn is_continuation(&self) -> bool {
+        self.header.attrs.is_continuation()
+    }
+
+    fn parse<T: AsRef<[u8]>>(
+        data: &mut Cursor<T>,
+        sets: &DBLKSets,
+        options: &MTFOptions,
+    ) -> Result<DBLK> {
         let base = data.position();
 
         let mut header_data = [0; 52];
@@ -441,13 +2173,15 @@ impl DBLK {
 
         let header_checksum = header_data.read_u16::<LittleEndian>()?;
 
-        assert_eq!(
-            header_checksum, checksum,
-            "got checksum {:#b}, calculated checksum {:#b}",
-            header_checksum, checksum
-        );
+        if header_checksum != checksum {
+            return Err(format_err!(
+                "got checksum {:#b}, calculated checksum {:#b}",
+                header_checksum,
+                checksum
+            ));
+        }
 
-        let header = CommonBlockHeader {
+        let mut header = CommonBlockHeader {
             attrs,
             offset_to_first_event,
             osid,
@@ -456,23 +2190,37 @@ impl DBLK {
             format_logical_address,
             control_block_id,
             os_specific_data,
+            os_specific: OsSpecificData::NONE,
             string_type,
             header_checksum,
         };
 
+        let strings = StringDecoder::new(&header, options);
+
+        header.os_specific = OsSpecificData::parse(
+            &header.osid,
+            header.osver,
+            &ty,
+            header.os_specific_data.clone(),
+            &strings,
+            data,
+        )?;
+
         let body = match ty {
             DBLKType::TAPE => {
-                assert_eq!(
-                    format_logical_address, 0,
-                    "format_logical_address has to be set to zero for TAPE DBLK, not {}",
-                    format_logical_address
-                );
+                if format_logical_address != 0 {
+                    return Err(format_err!(
+                        "format_logical_address has to be set to zero for TAPE DBLK, not {}",
+                        format_logical_address
+                    ));
+                }
 
-                assert_eq!(
-                    control_block_id, 0,
-                    "control_block_id has to be set to zero for TAPE DBLK, not {}",
-                    control_block_id
-                );
+                if control_block_id != 0 {
+                    return Err(format_err!(
+                        "control_block_id has to be set to zero for TAPE DBLK, not {}",
+                        control_block_id
+                    ));
+                }
 
                 let media_family_id = data.read_u32::<LittleEndian>()?;
                 let tape_attrs = data.read_u32::<LittleEndian>()?;
@@ -486,13 +2234,18 @@ impl DBLK {
                 let media_based_catalog_type =
                     MediaBasedCatalogType::parse(data.read_u16::<LittleEndian>()?)?;
                 let media_name = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
+                let media_label = media_name
+                    .as_deref()
+                    .filter(|_| tape_attrs.contains(TapeAttrs::MEDIA_LABEL))
+                    .and_then(MediaLabel::parse);
+                let media_name = media_name.filter(|_| media_label.is_none());
                 let media_description = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
                 let media_password = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
                 let software_name = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
                 let format_logical_block_size = data.read_u16::<LittleEndian>()?;
                 let software_vendor_id = data.read_u16::<LittleEndian>()?;
 
@@ -510,6 +2263,7 @@ impl DBLK {
                     soft_filemark_block_size,
                     media_based_catalog_type,
                     media_name,
+                    media_label,
                     media_description,
                     media_password,
                     software_name,
@@ -526,18 +2280,19 @@ impl DBLK {
                 })?;
 
                 let password_encryption_algorithm = data.read_u16::<LittleEndian>()?;
-                let software_compression_algorithm = data.read_u16::<LittleEndian>()?;
+                let software_compression_algorithm =
+                    CompressionAlgorithm::parse(data.read_u16::<LittleEndian>()?);
                 let software_vendor_id = data.read_u16::<LittleEndian>()?;
                 let data_set_number = data.read_u16::<LittleEndian>()?;
                 let data_set_name = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
                 let data_set_description =
                     TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                        .read_str(&header.string_type, data)?;
+                        .read_str(&strings, data)?;
                 let data_set_password = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
                 let username = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
                 let physical_block_address = data.read_u64::<LittleEndian>()?;
 
                 let mut write_date = [0; 5];
@@ -546,7 +2301,7 @@ impl DBLK {
 
                 let software_major_version = data.read_u8()?;
                 let software_minor_version = data.read_u8()?;
-                let timezone = data.read_i8()?;
+                let timezone = TimeZone::parse(data.read_i8()?);
                 let minor_version = data.read_u8()?;
                 let media_catalog_version = data.read_u8()?;
 
@@ -576,11 +2331,11 @@ impl DBLK {
                 })?;
 
                 let device_name = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
                 let volume_name = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
                 let machine_name = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
-                    .read_str(&header.string_type, data)?;
+                    .read_str(&strings, data)?;
 
                 let mut write_date = [0; 5];
                 data.read_exact(&mut write_date)?;
@@ -595,28 +2350,106 @@ impl DBLK {
                 }
             }
             DBLKType::DIRB => {
-                // DIRB
-                unimplemented!()
+                let attrs = data.read_u32::<LittleEndian>()?;
+                let attrs = DirbAttrs::from_bits(attrs).ok_or_else(|| {
+                    format_err!("could not parse dirb attributes from {:#b}", attrs)
+                })?;
+
+                let last_modification_date = DateTime::read(data)?;
+                let creation_date = DateTime::read(data)?;
+                let backup_date = DateTime::read(data)?;
+                let last_access_date = DateTime::read(data)?;
+                let directory_id = data.read_u32::<LittleEndian>()?;
+                let directory_name = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
+                    .read_str(&strings, data)?;
+
+                let path = directory_name.map(|name| split_path(&name));
+
+                DBLKSpecific::DIRB {
+                    attrs,
+                    last_modification_date,
+                    creation_date,
+                    backup_date,
+                    last_access_date,
+                    directory_id,
+                    path,
+                }
             }
             DBLKType::FILE => {
-                // FILE
-                unimplemented!()
+                let attrs = data.read_u32::<LittleEndian>()?;
+                let attrs = FileAttrs::from_bits(attrs).ok_or_else(|| {
+                    format_err!("could not parse file attributes from {:#b}", attrs)
+                })?;
+
+                let last_modification_date = DateTime::read(data)?;
+                let creation_date = DateTime::read(data)?;
+                let backup_date = DateTime::read(data)?;
+                let last_access_date = DateTime::read(data)?;
+                let directory_id = data.read_u32::<LittleEndian>()?;
+                let file_id = data.read_u32::<LittleEndian>()?;
+                let file_name = TapeAddress::parse(data.read_u32::<LittleEndian>()?, base)?
+                    .read_str(&strings, data)?;
+
+                DBLKSpecific::FILE {
+                    attrs,
+                    last_modification_date,
+                    creation_date,
+                    backup_date,
+                    last_access_date,
+                    directory_id,
+                    file_id,
+                    file_name,
+                }
             }
             DBLKType::CFIL => {
-                // CFIL
-                unimplemented!()
+                let attrs = data.read_u32::<LittleEndian>()?;
+                let attrs = CfilAttrs::from_bits(attrs).ok_or_else(|| {
+                    format_err!("could not parse cfil attributes from {:#b}", attrs)
+                })?;
+
+                let mut reserved = [0; 8];
+                data.read_exact(&mut reserved)?;
+
+                let stream_offset = data.read_u64::<LittleEndian>()?;
+                let corrupt_stream_number = data.read_u16::<LittleEndian>()?;
+
+                DBLKSpecific::CFIL {
+                    attrs,
+                    stream_offset,
+                    corrupt_stream_number,
+                }
             }
             DBLKType::ESPB => {
-                // ESPB
-                unimplemented!()
+                // only padding, the SPAD stream fills up to the next physical block
+                DBLKSpecific::ESPB
             }
             DBLKType::ESET => {
-                // ESET
-                unimplemented!()
+                let attrs = data.read_u32::<LittleEndian>()?;
+                let attrs = SSetAttrs::from_bits(attrs).ok_or_else(|| {
+                    format_err!("could not parse eset attributes from {:#b}", attrs)
+                })?;
+
+                let number_of_corrupt_files = data.read_u32::<LittleEndian>()?;
+                let set_map_pba = data.read_u64::<LittleEndian>()?;
+                let fdd_pba = data.read_u64::<LittleEndian>()?;
+                let fdd_media_sequence_number = data.read_u16::<LittleEndian>()?;
+                let data_set_number = data.read_u16::<LittleEndian>()?;
+                let media_write_date = DateTime::read(data)?;
+
+                DBLKSpecific::ESET {
+                    attrs,
+                    number_of_corrupt_files,
+                    set_map_pba,
+                    fdd_pba,
+                    fdd_media_sequence_number,
+                    data_set_number,
+                    media_write_date,
+                }
             }
             DBLKType::EOTM => {
-                // EOTM
-                unimplemented!()
+                let last_eset_pba = data.read_u64::<LittleEndian>()?;
+
+                DBLKSpecific::EOTM { last_eset_pba }
             }
             DBLKType::SFMB => {
                 let number_of_entries = data.read_u32::<LittleEndian>()?;
@@ -634,14 +2467,22 @@ impl DBLK {
                         }
                     },
                     None => {
-                        Err(format_err!("need to have parsed tape dblk to parse sfmb dblk (for soft_filemark_block_size)"))
+                        Err(format_err!("need to have parsed tape dblk to parse sfmb dblk (for soft_filemark_block_size), set MTFOptions::assumed_tape if the medium has none"))
                     }
                 }?;
 
                 // 60 = sizeof(common header = 52) + 2 * u32
-                let mut entries_data = vec![0u8; (soft_filemark_block_size.bytes() - 60) as usize];
-                let mut entries =
-                    vec![0u32; ((soft_filemark_block_size.bytes() - 60) / 4) as usize];
+                let entries_size = soft_filemark_block_size
+                    .bytes()
+                    .checked_sub(60)
+                    .ok_or_else(|| {
+                        format_err!(
+                            "soft filemark block size of {} bytes is too small for an SFMB",
+                            soft_filemark_block_size.bytes()
+                        )
+                    })?;
+                let mut entries_data = vec![0u8; entries_size as usize];
+                let mut entries = vec![0u32; (entries_size / 4) as usize];
 
                 data.read_exact(&mut entries_data)?;
 
@@ -674,6 +2515,17 @@ pub enum CommonBlockAttrs {
 }
 
 impl CommonBlockAttrs {
+    // The block repeats one of the previous medium, its streams continue the ones there
+    pub fn is_continuation(&self) -> bool {
+        match self {
+            CommonBlockAttrs::ANY(attrs) => attrs.contains(CommonBlockAttrsAny::CONTINUATION),
+            CommonBlockAttrs::TAPE(attrs) => attrs.contains(CommonBlockAttrsTAPE::CONTINUATION),
+            CommonBlockAttrs::SSET(attrs) => attrs.contains(CommonBlockAttrsSSET::CONTINUATION),
+            CommonBlockAttrs::ESET(attrs) => attrs.contains(CommonBlockAttrsESET::CONTINUATION),
+            CommonBlockAttrs::EOTM(attrs) => attrs.contains(CommonBlockAttrsEOTM::CONTINUATION),
+        }
+    }
+
     fn parse(attrs: u32, ty: &DBLKType) -> Result<CommonBlockAttrs> {
         use DBLKType::*;
 
@@ -828,38 +2680,100 @@ impl TapeAddress {
         Ok(TapeAddress { size, offset, base })
     }
 
-    fn read_str<T: AsRef<[u8]>>(
-        self,
-        ty: &StringType,
-        data: &mut Cursor<T>,
-    ) -> Result<Option<String>> {
+    fn read_bytes<T: AsRef<[u8]>>(&self, data: &mut Cursor<T>) -> Result<Option<Vec<u8>>> {
         if self.size > 0 {
             let old_position = data.position();
             data.set_position(self.base + (self.offset as u64));
 
-            let mut str_data = vec![0; self.size as usize];
-            data.read_exact(&mut str_data)?;
+            let mut bytes = vec![0; self.size as usize];
+            data.read_exact(&mut bytes)?;
 
             data.set_position(old_position);
 
-            ty.bytes_to_string(str_data).map(Option::Some)
+            Ok(Some(bytes))
         } else {
             Ok(None)
         }
     }
+
+    fn read_str<T: AsRef<[u8]>>(
+        self,
+        strings: &StringDecoder,
+        data: &mut Cursor<T>,
+    ) -> Result<Option<String>> {
+        match self.read_bytes(data)? {
+            Some(str_data) => strings.bytes_to_string(str_data).map(Option::Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeZone {
+    // minutes east of GMT
+    Offset(i16),
+    // written as local time, without knowing the offset
+    Unknown,
+}
+
+impl TimeZone {
+    fn parse(tz: i8) -> TimeZone {
+        // the offset is stored in 15 minute steps, 127 is local time,
+        // anything else outside of +-12h is treated as unknown as well
+        match tz {
+            -48..=48 => TimeZone::Offset(tz as i16 * 15),
+            _ => TimeZone::Unknown,
+        }
+    }
+
+    pub fn offset_minutes(&self) -> Option<i16> {
+        match self {
+            TimeZone::Offset(minutes) => Some(*minutes),
+            TimeZone::Unknown => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct DateTime {
-    year: u16,
-    month: u16,
-    day: u16,
-    hour: u16,
-    minute: u16,
-    second: u16,
+    pub year: u16,
+    pub month: u16,
+    pub day: u16,
+    pub hour: u16,
+    pub minute: u16,
+    pub second: u16,
 }
 
 impl DateTime {
+    fn read<T: AsRef<[u8]>>(data: &mut Cursor<T>) -> Result<DateTime> {
+        let mut date = [0; 5];
+        data.read_exact(&mut date)?;
+        Ok(DateTime::parse(date))
+    }
+
+    // Seconds since the unix epoch, taking the date as UTC. None for unset (all zero) or invalid dates
+    pub fn unix_timestamp(&self) -> Option<i64> {
+        if !(1..=12).contains(&self.month)
+            || !(1..=31).contains(&self.day)
+            || self.hour > 23
+            || self.minute > 59
+            || self.second > 59
+        {
+            return None;
+        }
+
+        // days_from_civil from http://howardhinnant.github.io/date_algorithms.html
+        let month = self.month as i64;
+        let year = self.year as i64 - if month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        Some(days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60 + self.second as i64)
+    }
+
     fn parse(data: [u8; 5]) -> DateTime {
         // 40 bits packed format
 
@@ -899,6 +2813,7 @@ pub struct CommonBlockHeader {
     format_logical_address: u64,
     control_block_id: u32,
     os_specific_data: TapeAddress,
+    pub os_specific: OsSpecificData,
     string_type: StringType,
     header_checksum: u16,
 }
@@ -942,8 +2857,21 @@ pub struct StreamHeader {
     file_system_attributes: FileSystemAttributes,
     media_format_attributes: MediaFormatAttributes,
     length: u64,
-    encryption_algorithm: u16,
-    compression_algorithm: u16,
+    encryption_algorithm: EncryptionAlgorithm,
+    compression_algorithm: CompressionAlgorithm,
+}
+
+// What ended the list of streams of a DBLK
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamTerminator {
+    // the regular case, the SPAD stream is the last one
+    SPAD,
+    // the next DBLK followed directly, as written by old writers
+    DBLK,
+    // zero filled space, for example where a filemark was on tape
+    ZERO_FILL,
+    // the data ended, possibly in the middle of the last stream
+    EOF,
 }
 
 #[derive(Debug, Clone)]
@@ -980,9 +2908,10 @@ impl Stream {
         )?;
 
         // support deprecated mtf's where not all segments have a stream
+        // zero filled space also has a valid checksum and would otherwise parse as endless empty streams
         match &*id {
             "TAPE" | "SSET" | "VOLB" | "DIRB" | "FILE" | "CFIL" | "ESPB" | "ESET" | "EOTM"
-            | "SFMB" => {
+            | "SFMB" | "\0\0\0\0" => {
                 data.set_position(orig);
                 return Ok(None);
             }
@@ -1009,8 +2938,10 @@ impl Stream {
 
         let length = header_data.read_u64::<LittleEndian>()?;
 
-        let encryption_algorithm = header_data.read_u16::<LittleEndian>()?;
-        let compression_algorithm = header_data.read_u16::<LittleEndian>()?;
+        let encryption_algorithm =
+            EncryptionAlgorithm::parse(header_data.read_u16::<LittleEndian>()?);
+        let compression_algorithm =
+            CompressionAlgorithm::parse(header_data.read_u16::<LittleEndian>()?);
 
         let header_checksum = header_data.read_u16::<LittleEndian>()?;
 
@@ -1023,52 +2954,65 @@ impl Stream {
             compression_algorithm,
         };
 
-        assert_eq!(
-            header_checksum, checksum,
-            "got checksum {:#b}, calculated checksum {:#b}",
-            header_checksum, checksum
-        );
+        if header_checksum != checksum {
+            return Err(format_err!(
+                "got checksum {:#b}, calculated checksum {:#b}",
+                header_checksum,
+                checksum
+            ));
+        }
 
         Ok(Some(Stream { header, base }))
     }
 
-    fn parse_all<T: AsRef<[u8]>>(data: &mut Cursor<T>) -> Result<Vec<Stream>> {
+    fn parse_all<T: AsRef<[u8]>>(data: &mut Cursor<T>) -> Result<(Vec<Stream>, StreamTerminator)> {
         let mut streams = Vec::new();
+        let len = data.get_ref().as_ref().len() as u64;
 
-        loop {
-            let new_stream = Stream::parse(data)?;
+        let terminator = loop {
+            let position = data.position();
 
-            if let Some(new_stream) = new_stream {
-                let old_position = data.position();
+            // not even enough room for a stream header left
+            if position + 22 > len {
+                break StreamTerminator::EOF;
+            }
 
-                let new = old_position + new_stream.header.length;
+            if let Some(new_stream) = Stream::parse(data)? {
+                // a zero-length stream (of an empty file, for example) still moves past its
+                // header, so the next stream is read right after it
+                let new = data.position() + new_stream.header.length;
                 let left_over = new % 4;
                 let padding = if left_over > 0 { 4 - left_over } else { 0 };
 
-                if new + padding == old_position {
-                    // We wont progress from here
-                    break;
+                if new > len {
+                    // truncated file, keep the stream, its data just gets cut off
+                    streams.push(new_stream);
+                    data.set_position(len);
+                    break StreamTerminator::EOF;
                 }
 
                 data.set_position(new + padding);
-                if data.position() != (new + padding) {
-                    // Seems like we found the end of the file?
-                    break;
-                }
 
-                let id = new_stream.header.id.clone();
+                let is_spad = new_stream.header.id == "SPAD";
 
                 streams.push(new_stream);
 
-                if id == "SPAD" {
-                    break;
+                if is_spad {
+                    break StreamTerminator::SPAD;
                 }
             } else {
-                return Ok(Vec::new());
+                // keep what we got, a block without SPAD can be directly followed by the next one
+                let magic = &data.get_ref().as_ref()[position as usize..position as usize + 4];
+
+                if magic == [0; 4] {
+                    break StreamTerminator::ZERO_FILL;
+                } else {
+                    break StreamTerminator::DBLK;
+                }
             }
-        }
+        };
 
-        Ok(streams)
+        Ok((streams, terminator))
     }
 
     pub fn data<'a>(&self, data: &'a [u8]) -> &'a [u8] {
@@ -1,19 +1,569 @@
-use crate::StreamWithData;
+use crate::{DBLKSpecific, DateTime, MTFParser, Result, SSetAttrs, StreamWithData};
+use byteorder::{ByteOrder, LittleEndian};
 use derivative::Derivative;
+use failure::format_err;
 use mdf::{PageHeader, PagePointer, PageProvider, RawPage, PAGE_SIZE};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::fmt;
 use std::hash::Hasher;
+use std::io::Write;
 use std::path::Path;
 
 #[derive(Derivative)]
 #[derivative(Debug)]
 pub struct MTFPageProvider<'a> {
     #[derivative(Debug = "ignore")]
-    data: &'a [u8],
+    data: PageData<'a>,
     #[derivative(Debug = "ignore")]
     index: MTFBackupIndex,
+    fragments: Vec<Fragment>,
+}
+
+// The pages of all MQDA fragments, at multiples of the page size in the address space of
+// Fragment::start. The data stays borrowed from the medium, only pages that continue in the next
+// fragment (or in the padding at the end of a stripe) are copied
+struct PageData<'a> {
+    // start and data of every fragment, sorted by start
+    parts: Vec<(u64, &'a [u8])>,
+    page_size: usize,
+    len: u64,
+    split_pages: HashMap<usize, Vec<u8>>,
+}
+
+impl<'a> PageData<'a> {
+    fn new(parts: Vec<(u64, &'a [u8])>, page_size: usize) -> Self {
+        let len = parts
+            .last()
+            .map_or(0, |(start, data)| start + data.len() as u64);
+
+        let num_pages = (len / page_size as u64) as usize;
+
+        let mut split_pages = HashMap::new();
+        for (start, part) in &parts {
+            let end = start + part.len() as u64;
+            let idx = (end / page_size as u64) as usize;
+            if !end.is_multiple_of(page_size as u64) && idx < num_pages {
+                split_pages
+                    .entry(idx)
+                    .or_insert_with(|| Self::copy_page(&parts, idx, page_size));
+            }
+        }
+
+        Self {
+            parts,
+            page_size,
+            len,
+            split_pages,
+        }
+    }
+
+    // Everything not covered by a fragment is zero
+    fn copy_page(parts: &[(u64, &[u8])], idx: usize, page_size: usize) -> Vec<u8> {
+        let page_start = (idx * page_size) as u64;
+        let page_end = page_start + page_size as u64;
+        let mut page = vec![0; page_size];
+
+        for (start, data) in parts {
+            let end = start + data.len() as u64;
+            if *start < page_end && end > page_start {
+                let from = page_start.max(*start);
+                let to = page_end.min(end);
+                page[(from - page_start) as usize..(to - page_start) as usize]
+                    .copy_from_slice(&data[(from - start) as usize..(to - start) as usize]);
+            }
+        }
+
+        page
+    }
+
+    fn num_pages(&self) -> usize {
+        (self.len / self.page_size as u64) as usize
+    }
+
+    fn page(&self, idx: usize) -> Option<&[u8]> {
+        if idx >= self.num_pages() {
+            return None;
+        }
+        if let Some(page) = self.split_pages.get(&idx) {
+            return Some(page);
+        }
+
+        let offset = (idx * self.page_size) as u64;
+        let part = self
+            .parts
+            .partition_point(|(start, _)| *start <= offset)
+            .checked_sub(1)?;
+        let (start, data) = self.parts[part];
+        data.get((offset - start) as usize..)?.get(..self.page_size)
+    }
+}
+
+// Where an MQDA fragment is in data and in the backup file (of the stripe)
+#[derive(Debug, Clone)]
+struct Fragment {
+    start: u64,
+    stripe: usize,
+    file_offset: u64,
+}
+
+// The MQDA data of the backup is compressed (BACKUP ... WITH COMPRESSION), which is not supported.
+// Can be told apart from other errors with downcast_ref
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressedBackupUnsupported;
+
+impl fmt::Display for CompressedBackupUnsupported {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the backup is compressed, which is not supported")
+    }
+}
+
+impl std::error::Error for CompressedBackupUnsupported {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSummary {
+    pub file_id: u16,
+    pub num_pages: u32,
+    pub present_pages: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderSummary {
+    pub page_size: usize,
+    // sorted by file_id
+    pub files: Vec<FileSummary>,
+}
+
+// Number of pages that differ between two backups, for a single allocation unit
+#[derive(Debug, Clone, Default)]
+pub struct AllocationUnitDiff {
+    pub added: u32,
+    pub removed: u32,
+    pub changed: u32,
+}
+
+// Where a backup file belongs in a media set. SQL Server writes the media set id into the media id of
+// the media label, it is shared by all stripes and mirrors of the set. Stripes are separate media
+// families, mirrors share the family and sequence number
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MediaSetMember {
+    pub media_set_id: Option<String>,
+    pub media_family_id: u32,
+    pub media_sequence_number: u16,
+}
+
+impl MediaSetMember {
+    pub fn read(parser: &mut MTFParser) -> Result<MediaSetMember> {
+        match parser.tape()?.body {
+            DBLKSpecific::TAPE {
+                media_family_id,
+                media_sequence_number,
+                media_label,
+                ..
+            } => Ok(MediaSetMember {
+                media_set_id: media_label
+                    .map(|label| label.media_id)
+                    .filter(|id| !id.is_empty()),
+                media_family_id,
+                media_sequence_number,
+            }),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Checks that the backup files belong to the same media set, before their sets are merged. Returns
+// the media set id
+pub fn verify_media_set(members: &[MediaSetMember]) -> Result<String> {
+    let first = members
+        .first()
+        .ok_or_else(|| format_err!("need at least one backup file"))?;
+
+    let id = match members
+        .iter()
+        .position(|member| member.media_set_id.is_none())
+    {
+        Some(idx) => return Err(format_err!("backup file {} has no media set id", idx)),
+        None => first.media_set_id.clone().unwrap(),
+    };
+
+    if let Some(idx) = members
+        .iter()
+        .position(|member| member.media_set_id.as_ref() != Some(&id))
+    {
+        return Err(format_err!(
+            "backup file {} belongs to media set {}, not {}",
+            idx,
+            members[idx].media_set_id.as_ref().unwrap(),
+            id
+        ));
+    }
+
+    Ok(id)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlFile {
+    pub file_id: u16,
+    // in bytes, up to the last page in the backup, as written by MTFPageProvider::write_file
+    pub size: u64,
+    // from the MQCI stream, None without one
+    pub logical_name: Option<String>,
+    pub physical_name: Option<String>,
+}
+
+// The log has no pages in the backup, only its names are known
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SqlLogFile {
+    pub logical_name: Option<String>,
+    pub physical_name: String,
+}
+
+// The files of a backup, like RESTORE FILELISTONLY. Ids and sizes of the data files come from the
+// pages, the names from the MQCI stream
+#[derive(Debug, Clone, Default)]
+pub struct SqlFileList {
+    // sorted by file_id
+    pub files: Vec<SqlFile>,
+    pub log_files: Vec<SqlLogFile>,
+}
+
+impl SqlFileList {
+    pub fn from_provider(provider: &MTFPageProvider) -> SqlFileList {
+        let files = provider
+            .file_ids()
+            .into_iter()
+            .map(|file_id| SqlFile {
+                file_id,
+                size: provider.num_pages(file_id) as u64 * provider.page_size() as u64,
+                logical_name: None,
+                physical_name: None,
+            })
+            .collect();
+
+        SqlFileList {
+            files,
+            log_files: Vec::new(),
+        }
+    }
+
+    // The record layout of the MQCI stream is undocumented, but it holds the logical and the
+    // physical name of every file as UTF-16, in file id order. A physical name is any string
+    // that ends in .mdf, .ndf or .ldf, its logical name is the string in front of it. The
+    // names of data files go to the data files in file id order, the ones ending in .ldf are
+    // the log files. Without any physical name in the data the list stays as it is
+    pub fn with_names(mut self, mqci: &[u8]) -> SqlFileList {
+        let names = database_file_names(mqci);
+        let (logs, data): (Vec<_>, Vec<_>) = names
+            .into_iter()
+            .partition(|(_, physical)| physical.to_ascii_lowercase().ends_with(".ldf"));
+
+        for (file, (logical_name, physical_name)) in self.files.iter_mut().zip(data) {
+            file.logical_name = logical_name;
+            file.physical_name = Some(physical_name);
+        }
+        self.log_files = logs
+            .into_iter()
+            .map(|(logical_name, physical_name)| SqlLogFile {
+                logical_name,
+                physical_name,
+            })
+            .collect();
+
+        self
+    }
+
+    pub fn for_set(parser: &mut MTFParser, set_number: u16) -> Result<SqlFileList> {
+        let mqci: Vec<u8> = parser
+            .set(set_number)?
+            .flat_map(|dblk| dblk.streams)
+            .filter(|stream| stream.id() == "MQCI")
+            .flat_map(|stream| stream.data.to_vec())
+            .collect();
+
+        Ok(
+            SqlFileList::from_provider(&MTFPageProvider::for_set(parser, set_number)?)
+                .with_names(&mqci),
+        )
+    }
+}
+
+// The (logical, physical) names of the files in undocumented metadata, see SqlFileList::with_names
+fn database_file_names(data: &[u8]) -> Vec<(Option<String>, String)> {
+    let is_physical_name = |name: &str| {
+        let name = name.to_ascii_lowercase();
+        [".mdf", ".ndf", ".ldf"]
+            .iter()
+            .any(|extension| name.len() > extension.len() && name.ends_with(extension))
+    };
+
+    let mut names = Vec::new();
+    for (offset, strings) in (0..2).map(|offset| (offset, utf16_strings(data, offset))) {
+        for (idx, (position, string)) in strings.iter().enumerate() {
+            if !is_physical_name(string) {
+                continue;
+            }
+
+            let logical_name = idx
+                .checked_sub(1)
+                .map(|idx| &strings[idx].1)
+                .filter(|name| !is_physical_name(name) && !name.contains(['\\', '/']))
+                .cloned();
+            names.push((offset + position, logical_name, string.clone()));
+        }
+    }
+
+    names.sort_by_key(|(position, _, _)| *position);
+    names
+        .into_iter()
+        .map(|(_, logical_name, physical_name)| (logical_name, physical_name))
+        .collect()
+}
+
+// The runs of printable UTF-16 characters of the data at the given alignment, with their byte
+// offset relative to it. Surrogates end a run, names outside the BMP are not expected
+fn utf16_strings(data: &[u8], offset: usize) -> Vec<(usize, String)> {
+    let units = data
+        .get(offset..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(LittleEndian::read_u16);
+
+    let mut strings = Vec::new();
+    let mut current = String::new();
+    let mut start = 0;
+
+    for (idx, unit) in units.enumerate() {
+        match std::char::from_u32(unit as u32) {
+            Some(c) if !c.is_control() => {
+                if current.is_empty() {
+                    start = idx * 2;
+                }
+                current.push(c);
+            }
+            _ => {
+                if !current.is_empty() {
+                    strings.push((start, std::mem::take(&mut current)));
+                }
+            }
+        }
+    }
+    if !current.is_empty() {
+        strings.push((start, current));
+    }
+
+    strings
+}
+
+// The log records of a log backup (.trn), which has an MQTL stream instead of the MQDA one. Only
+// the raw data is exposed, split over multiple DBLKs like MQDA streams
+#[derive(Debug)]
+pub struct TransactionLog<'a> {
+    pub data: Cow<'a, [u8]>,
+}
+
+impl<'a> TransactionLog<'a> {
+    pub fn from_streams(streams: Vec<StreamWithData<'a>>) -> Result<Self> {
+        if let Some(stream) = streams.iter().find(|stream| stream.id() != "MQTL") {
+            return Err(format_err!("expected an MQTL stream, got {}", stream.id()));
+        }
+
+        let data = match &streams[..] {
+            [] => return Err(format_err!("need at least one MQTL stream")),
+            [stream] => Cow::Borrowed(stream.data),
+            streams => Cow::Owned(
+                streams
+                    .iter()
+                    .flat_map(|stream| stream.data)
+                    .copied()
+                    .collect(),
+            ),
+        };
+
+        Ok(TransactionLog { data })
+    }
+
+    pub fn for_set(parser: &'a mut MTFParser, set_number: u16) -> Result<Self> {
+        let streams = parser
+            .set(set_number)?
+            .flat_map(|dblk| dblk.streams)
+            .filter(|stream| stream.id() == "MQTL")
+            .collect();
+
+        Self::from_streams(streams)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlBackupType {
+    DATABASE,
+    DIFFERENTIAL,
+    LOG,
+}
+
+impl SqlBackupType {
+    // The MTF meaning of the set attributes: a normal backup is a full one and an incremental
+    // one holds the changes since the last backup, which is what a log backup does
+    fn from_attrs(attrs: SSetAttrs) -> Option<SqlBackupType> {
+        if attrs.contains(SSetAttrs::DIFFERENTIAL) {
+            Some(SqlBackupType::DIFFERENTIAL)
+        } else if attrs.contains(SSetAttrs::INCREMENTAL) {
+            Some(SqlBackupType::LOG)
+        } else if attrs.contains(SSetAttrs::NORMAL) {
+            Some(SqlBackupType::DATABASE)
+        } else {
+            None
+        }
+    }
+}
+
+// The part of RESTORE HEADERONLY that a backup set gives: names, machine, backup type and dates
+// from the MTF blocks and the database name from the MSCI stream. The server name, the LSNs and
+// the recovery model are in the MSCI stream as well, but its layout is undocumented and, unlike
+// the database name, they can not be told apart from the other values in it, so they are not
+// decoded
+#[derive(Debug, Clone)]
+pub struct SqlBackupInfo {
+    pub data_set_number: u16,
+    // NAME and DESCRIPTION of the BACKUP statement
+    pub backup_name: Option<String>,
+    pub description: Option<String>,
+    pub user_name: Option<String>,
+    // None without an MSCI stream
+    pub database_name: Option<String>,
+    // of the VOLB, the machine (not the instance) SQL Server ran on
+    pub machine_name: Option<String>,
+    // None if the set attributes have none of the backup method bits
+    pub backup_type: Option<SqlBackupType>,
+    pub start: DateTime,
+    // None if the set has no ESET
+    pub finish: Option<DateTime>,
+}
+
+impl SqlBackupInfo {
+    pub fn for_set(parser: &mut MTFParser, set_number: u16) -> Result<SqlBackupInfo> {
+        let mut info = None;
+
+        for dblk in parser.set(set_number)? {
+            match dblk.dblk.body {
+                DBLKSpecific::SSET {
+                    attrs,
+                    data_set_number,
+                    data_set_name,
+                    data_set_description,
+                    username,
+                    write_date,
+                    ..
+                } => {
+                    info = Some(SqlBackupInfo {
+                        data_set_number,
+                        backup_name: data_set_name,
+                        description: data_set_description,
+                        user_name: username,
+                        database_name: None,
+                        machine_name: None,
+                        backup_type: SqlBackupType::from_attrs(attrs),
+                        start: write_date,
+                        finish: None,
+                    })
+                }
+                DBLKSpecific::VOLB { machine_name, .. } => {
+                    if let Some(info) = info.as_mut().filter(|info| info.machine_name.is_none()) {
+                        info.machine_name = machine_name;
+                    }
+                }
+                DBLKSpecific::ESET {
+                    media_write_date, ..
+                } => {
+                    if let Some(info) = info.as_mut() {
+                        info.finish = Some(media_write_date);
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(info) = info.as_mut().filter(|info| info.database_name.is_none()) {
+                info.database_name = dblk
+                    .streams
+                    .iter()
+                    .filter(|stream| stream.id() == "MSCI")
+                    .find_map(|stream| database_name(stream.data));
+            }
+        }
+
+        info.ok_or_else(|| format_err!("backup set {} has no SSET", set_number))
+    }
+
+    // Of all sets, like RESTORE HEADERONLY
+    pub fn all(parser: &mut MTFParser) -> Result<Vec<SqlBackupInfo>> {
+        parser
+            .backup_sets()?
+            .iter()
+            .map(|set| SqlBackupInfo::for_set(parser, set.data_set_number))
+            .collect()
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '@' | '#' | '$')
+}
+
+// The first UTF-16 string of the MSCI stream that is made of identifier characters, at either
+// alignment, which is where SQL Server puts the database name
+fn database_name(msci: &[u8]) -> Option<String> {
+    (0..2)
+        .flat_map(|offset| {
+            utf16_strings(msci, offset)
+                .into_iter()
+                .map(move |(position, string)| (offset + position, string))
+        })
+        .filter(|(_, string)| string.chars().all(is_name_char))
+        .min_by_key(|(position, _)| *position)
+        .map(|(_, string)| string)
+}
+
+// Whether the name is in the UTF-16 data as a whole, not as part of a longer name (Sales in
+// SalesArchive), at either alignment
+fn contains_name(data: &[u8], name: &[u16]) -> bool {
+    let is_name_char = |unit: u16| std::char::from_u32(unit as u32).is_some_and(is_name_char);
+
+    (0..2).any(|offset| {
+        let units: Vec<u16> = data
+            .get(offset..)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(LittleEndian::read_u16)
+            .collect();
+
+        units
+            .windows(name.len())
+            .enumerate()
+            .any(|(start, window)| {
+                let end = start + name.len();
+                window == name
+                    && (start == 0 || !is_name_char(units[start - 1]))
+                    && units.get(end).is_none_or(|unit| !is_name_char(*unit))
+            })
+    })
+}
+
+// m_lsn at offset 40: the VLF sequence number, the log block offset and the slot
+fn page_lsn(page: &[u8]) -> (u32, u32, u16) {
+    (
+        LittleEndian::read_u32(&page[40..]),
+        LittleEndian::read_u32(&page[44..]),
+        LittleEndian::read_u16(&page[48..]),
+    )
+}
+
+// The allocation unit id is not stored directly, but assembled from
+// m_objId (offset 24) and m_indexId (offset 6) of the page header
+pub(crate) fn allocation_unit_id(page: &[u8]) -> u64 {
+    let obj_id = LittleEndian::read_u32(&page[24..]) as u64;
+    let index_id = LittleEndian::read_u16(&page[6..]) as u64;
+    (index_id << 48) | (obj_id << 16)
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
@@ -26,87 +576,90 @@ struct IndexEntry {
 #[derive(Serialize, Deserialize)]
 struct MTFBackupIndex {
     // First layer is one per file_id
-    // that contains outer_level_entries entries per file_id
+    // that contains max page id / divisor + 1 entries per file_id
     // the index into that array is calculated by page_id / divisor
     // the innermost layer now contains a list of IndexEntries, that contain a
     // map of (start page id, stop page id) -> base actual index
     idx: Vec<Vec<Vec<IndexEntry>>>,
-    outer_level_entries: usize,
+    // chosen when the index is built, so it is part of the cache
     divisor: usize,
+    page_size: usize,
     max_page_ids: HashMap<u16, u32>,
 }
 
 impl MTFBackupIndex {
-    // Shoot for approximately 1024 entries in the inner level
-    // Assuming a average run length of O(100), this should work out nicely
-    const DIVISOR: usize = 1024;
+    // Shoot for a few runs per bucket, lookups scan a bucket linearly and large buckets are slow for
+    // very fragmented backups, while small ones waste memory on empty buckets for contiguous ones
+    const RUNS_PER_BUCKET: usize = 8;
+    const MIN_DIVISOR: usize = 64;
+    const MAX_DIVISOR: usize = 1 << 20;
+    // bumped whenever the layout changes, so old caches are not misread
+    const CACHE_VERSION: u32 = 2;
 
-    fn cache_name(data: &[u8]) -> String {
+    fn cache_name(data: &PageData) -> String {
         let mut hasher = DefaultHasher::new();
         // lets get some of the first pages, these should be some of the system pages, so hopefully unique
-        hasher.write(&data[..10 * PAGE_SIZE]);
-        hasher.write_usize(data.len());
+        for page in (0..data.num_pages().min(10)).filter_map(|idx| data.page(idx)) {
+            hasher.write(page);
+        }
+        hasher.write_usize(data.len as usize);
+        hasher.write_usize(data.page_size);
         let hash = hasher.finish();
-        format!(".mtf_backup_index_{:<016x}", hash)
+        format!(".mtf_backup_index_v{}_{:<016x}", Self::CACHE_VERSION, hash)
     }
 
-    fn try_load_cache(data: &[u8]) -> Option<Self> {
+    fn try_load_cache(data: &PageData) -> Option<Self> {
         let path = Self::cache_name(data);
         let path = Path::new(&path);
         if path.exists() {
-            Some(bincode::deserialize_from(std::fs::File::open(path).unwrap()).unwrap())
+            bincode::deserialize_from(std::fs::File::open(path).ok()?).ok()
         } else {
             None
         }
     }
 
-    fn write_cache(&self, data: &[u8]) {
+    fn write_cache(&self, data: &PageData) {
         let path = Self::cache_name(data);
         let file = std::fs::File::create(path).unwrap();
         bincode::serialize_into(file, self).unwrap()
     }
 
-    pub fn build(data: &[u8]) -> Self {
-        match Self::try_load_cache(data) {
+    // From the average length of the runs of consecutive pages
+    fn divisor(runs: &[(PagePointer, PagePointer, u32)]) -> usize {
+        let pages: usize = runs
+            .iter()
+            .map(|(start, end, _)| (end.page_id - start.page_id) as usize + 1)
+            .sum();
+        let average_run_length = pages / runs.len().max(1);
+
+        (average_run_length * Self::RUNS_PER_BUCKET)
+            .next_power_of_two()
+            .clamp(Self::MIN_DIVISOR, Self::MAX_DIVISOR)
+    }
+
+    fn build(data: &PageData) -> Self {
+        let page_size = data.page_size;
+        let cached = Self::try_load_cache(data);
+        crate::metrics::report(|metrics| metrics.index_cache(cached.is_some()));
+
+        match cached {
             Some(idx) => idx,
             None => {
-                let num_pages = data.len() / PAGE_SIZE;
-                let outer_level_entries = num_pages / Self::DIVISOR;
-                let divisor = Self::DIVISOR;
-                let mut idx = Vec::new();
+                let num_pages = data.num_pages();
+                let mut runs = Vec::new();
 
                 // We need to save this index, because we cannot reconstruct it if the end coincides with zero pages
                 let mut start_idx = 0;
                 // First one should be valid
-                let mut start = PageHeader::parse_ptr(data).unwrap();
+                let mut start = data.page(0).and_then(PageHeader::parse_ptr).unwrap();
                 let mut old = start;
-                let mut max_page_ids = HashMap::new();
-
-                let mut write_entry = |start: PagePointer, end: PagePointer, start_idx: u32| {
-                    while idx.len() < end.file_id as usize {
-                        idx.push(vec![vec![]; outer_level_entries]);
-                    }
-
-                    idx[(end.file_id - 1) as usize][start.page_id as usize / divisor].push(
-                        IndexEntry {
-                            start: start.page_id,
-                            stop: end.page_id,
-                            base: start_idx,
-                        },
-                    );
-
-                    max_page_ids
-                        .entry(end.file_id)
-                        .and_modify(|e| *e = end.page_id.max(*e))
-                        .or_insert(end.page_id);
-                };
 
                 for i in 1..num_pages {
-                    let new = PageHeader::parse_ptr(&data[i * PAGE_SIZE..]);
+                    let new = data.page(i).and_then(PageHeader::parse_ptr);
 
                     if let Some(new) = new {
                         if (start.file_id != new.file_id) || (old.page_id + 1) != (new.page_id) {
-                            write_entry(start, old, start_idx);
+                            runs.push((start, old, start_idx));
 
                             start = new;
                             start_idx = i as u32;
@@ -116,11 +669,40 @@ impl MTFBackupIndex {
                     }
                 }
 
-                write_entry(start, old, start_idx);
+                runs.push((start, old, start_idx));
+
+                let divisor = Self::divisor(&runs);
+                let mut idx: Vec<Vec<Vec<IndexEntry>>> = Vec::new();
+                let mut max_page_ids = HashMap::new();
+
+                for (start, end, start_idx) in runs {
+                    while idx.len() < end.file_id as usize {
+                        idx.push(vec![]);
+                    }
+
+                    // page ids are not bounded by the number of pages in the backup, so the
+                    // buckets grow with the largest page id seen
+                    let outer_entries = &mut idx[(end.file_id - 1) as usize];
+                    let outer_idx = start.page_id as usize / divisor;
+                    if outer_entries.len() <= outer_idx {
+                        outer_entries.resize(outer_idx + 1, vec![]);
+                    }
+
+                    outer_entries[outer_idx].push(IndexEntry {
+                        start: start.page_id,
+                        stop: end.page_id,
+                        base: start_idx,
+                    });
+
+                    max_page_ids
+                        .entry(end.file_id)
+                        .and_modify(|e| *e = end.page_id.max(*e))
+                        .or_insert(end.page_id);
+                }
 
                 let idx = Self {
                     divisor,
-                    outer_level_entries,
+                    page_size,
                     idx,
                     max_page_ids,
                 };
@@ -132,28 +714,30 @@ impl MTFBackupIndex {
         }
     }
 
-    pub fn lookup(&self, ptr: PagePointer) -> Option<u32> {
-        let outer_entries = &self.idx[(ptr.file_id - 1) as usize];
-        let mut outer_idx = ptr.page_id as usize / self.divisor;
-
-        loop {
-            while outer_entries[outer_idx].is_empty() {
-                outer_idx -= 1;
-            }
+    fn present_pages(&self, file_id: u16) -> u32 {
+        self.idx
+            .get((file_id as usize).wrapping_sub(1))
+            .map_or(0, |outer_entries| {
+                outer_entries
+                    .iter()
+                    .flatten()
+                    .map(|entry| entry.stop - entry.start + 1)
+                    .sum()
+            })
+    }
 
-            let entries = &outer_entries[outer_idx];
+    pub fn lookup(&self, ptr: PagePointer) -> Option<u32> {
+        let outer_entries = self.idx.get((ptr.file_id as usize).checked_sub(1)?)?;
+        let outer_idx = ptr.page_id as usize / self.divisor;
+        let outer_idx = outer_idx.min(outer_entries.len().checked_sub(1)?);
 
+        // runs are sorted into the bucket of their start page, so walk backwards from ours
+        for entries in outer_entries[..=outer_idx].iter().rev() {
             for entry in entries {
                 if entry.start <= ptr.page_id && entry.stop >= ptr.page_id {
                     return Some(entry.base + ptr.page_id - entry.start);
                 }
             }
-
-            if outer_idx == 0 {
-                break;
-            } else {
-                outer_idx -= 1;
-            }
         }
 
         // Gracefully break, to make it easier to read broken tables
@@ -163,41 +747,891 @@ impl MTFBackupIndex {
     }
 }
 
+// The MQDA data does not start with a page at any of the supported page sizes. Can be told apart
+// from other errors with downcast_ref
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotPageAligned;
+
+impl fmt::Display for NotPageAligned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "the MQDA data is not aligned to any of the page sizes {:?}",
+            PAGE_SIZES
+        )
+    }
+}
+
+impl std::error::Error for NotPageAligned {}
+
+// SQL Server 7 and later use 8 KiB pages, some very old backups 2 KiB ones with the same header
+pub const PAGE_SIZES: [usize; 2] = [PAGE_SIZE, 2048];
+
+// The first extent, the pages of a backup start with it
+const PAGE_SIZE_SAMPLES: usize = 8;
+
+// m_type of the page kinds SQL Server writes
+const PAGE_TYPES: [u8; 17] = [1, 2, 3, 4, 7, 8, 9, 10, 11, 13, 14, 15, 16, 17, 18, 19, 20];
+
+// A 7.0 and later page header: header version 1, a known m_type and m_pageId at 32
+fn is_page(data: &[u8], page_id: u32) -> bool {
+    data.len() >= 38
+        && data[0] == 1
+        && PAGE_TYPES.contains(&data[1])
+        && LittleEndian::read_u32(&data[32..]) == page_id
+}
+
+// The backup starts with the first extent of the first file, so at the right stride every page of
+// it has a valid header with that page number. 8 KiB is tried first, at 2 KiB most of those places
+// are in the middle of an 8 KiB page
+fn detect_page_size(data: &[u8]) -> Result<usize> {
+    PAGE_SIZES
+        .iter()
+        .copied()
+        .find(|&page_size| {
+            let samples = (data.len() / page_size).min(PAGE_SIZE_SAMPLES);
+            samples > 0 && (0..samples).all(|i| is_page(&data[i * page_size..], i as u32))
+        })
+        .ok_or_else(|| NotPageAligned.into())
+}
+
 impl<'a> MTFPageProvider<'a> {
-    pub fn from_stream(stream: StreamWithData<'a>) -> Self {
-        assert_eq!(stream.stream.header.id, "MQDA");
+    pub fn from_stream(stream: StreamWithData<'a>) -> Result<Self> {
+        Self::from_streams(vec![stream])
+    }
+
+    // Very large databases have their MQDA stream split into fragments spread over
+    // multiple DBLKs, these have to be glued back together before indexing. The page size is
+    // detected from the page headers
+    pub fn from_streams(streams: Vec<StreamWithData<'a>>) -> Result<Self> {
+        Self::from_stripe_streams(vec![streams], None)
+    }
+
+    // Fails for anything but MQDA streams, for backups whose pages can't be read and for page
+    // sizes not in PAGE_SIZES
+    pub fn from_streams_with_page_size(
+        streams: Vec<StreamWithData<'a>>,
+        page_size: usize,
+    ) -> Result<Self> {
+        Self::from_stripe_streams(vec![streams], Some(page_size))
+    }
+
+    // A backup written to several devices at once (BACKUP ... TO DISK = a, DISK = b) spreads the
+    // pages over all of them. Every stripe carries the same SSET, the MQDA streams of that set are
+    // merged from all stripes, pages are found by their header so their order doesn't matter
+    pub fn from_stripes(stripes: &'a mut [MTFParser], set_number: u16) -> Result<Self> {
+        let mut write_date = None;
+        let mut streams = Vec::new();
+
+        for (stripe, parser) in stripes.iter_mut().enumerate() {
+            let mut dblks = parser
+                .set(set_number)
+                .map_err(|err| format_err!("stripe {}: {}", stripe, err))?;
+
+            let sset = dblks.next().map(|dblk| dblk.dblk.body);
+            let date = match &sset {
+                Some(DBLKSpecific::SSET { write_date, .. }) => write_date.unix_timestamp(),
+                _ => {
+                    return Err(format_err!(
+                        "stripe {}: set {} does not start with an SSET",
+                        stripe,
+                        set_number
+                    ))
+                }
+            };
+
+            // the SSET is written once for all stripes, its date is the same on every one
+            match write_date {
+                Some(first) if first != date => {
+                    return Err(format_err!(
+                        "stripe {} was written at {:?}, not at {:?} like the first one",
+                        stripe,
+                        date,
+                        first
+                    ))
+                }
+                _ => write_date = Some(date),
+            }
+
+            streams.push(
+                dblks
+                    .flat_map(|dblk| dblk.streams)
+                    .filter(|stream| stream.id() == "MQDA")
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        Self::from_stripe_streams(streams, None)
+    }
+
+    fn from_stripe_streams(
+        stripes: Vec<Vec<StreamWithData<'a>>>,
+        page_size: Option<usize>,
+    ) -> Result<Self> {
+        if stripes.is_empty() || stripes.iter().any(Vec::is_empty) {
+            return Err(format_err!("need at least one MQDA stream per stripe"));
+        }
+        if let Some(stream) = stripes
+            .iter()
+            .flatten()
+            .find(|stream| stream.id() != "MQDA")
+        {
+            if stream.id() == "MQTL" {
+                return Err(format_err!(
+                    "MQTL stream of a log backup has no pages, read it with TransactionLog"
+                ));
+            }
+            return Err(format_err!("expected an MQDA stream, got {}", stream.id()));
+        }
+
+        // the data of backups written WITH COMPRESSION is flagged, indexing it would only find garbage
+        if stripes.iter().flatten().any(StreamWithData::is_compressed) {
+            return Err(CompressedBackupUnsupported.into());
+        }
 
         // For some reason there are two bytes at the start of this that don't actually belong
-        Self {
-            data: &stream.data[2..],
-            index: MTFBackupIndex::build(&stream.data[2..]),
+        if stripes.iter().any(|streams| streams[0].data.len() < 2) {
+            return Err(format_err!("MQDA stream is truncated"));
+        }
+
+        // the first stripe starts with the first page, the others with arbitrary ones
+        let page_size = match page_size {
+            Some(page_size) if !PAGE_SIZES.contains(&page_size) => {
+                return Err(format_err!(
+                    "unsupported page size {}, expected one of {:?}",
+                    page_size,
+                    PAGE_SIZES
+                ))
+            }
+            Some(page_size) => page_size,
+            None => detect_page_size(&stripes[0][0].data[2..])?,
+        };
+
+        let mut fragments = Vec::new();
+        let mut length: u64 = 0;
+        for (stripe, streams) in stripes.iter().enumerate() {
+            // every stripe starts with a page
+            length = length.div_ceil(page_size as u64) * page_size as u64;
+            fragments.push(Fragment {
+                start: length,
+                stripe,
+                file_offset: streams[0].stream.base + 2,
+            });
+            length += streams[0].data.len() as u64 - 2;
+
+            for stream in &streams[1..] {
+                fragments.push(Fragment {
+                    start: length,
+                    stripe,
+                    file_offset: stream.stream.base,
+                });
+                length += stream.data.len() as u64;
+            }
+        }
+
+        let parts = fragments
+            .iter()
+            .zip(stripes.iter().flatten())
+            .map(|(fragment, stream)| {
+                let skip = (fragment.file_offset - stream.stream.base) as usize;
+                (fragment.start, &stream.data[skip..])
+            })
+            .collect();
+        let data = PageData::new(parts, page_size);
+
+        if data.num_pages() == 0 {
+            return Err(format_err!("MQDA data does not hold a single page"));
+        }
+
+        // compressed data that is not flagged as such at least does not start with a page
+        if data.page(0).and_then(PageHeader::parse_ptr).is_none() {
+            return Err(CompressedBackupUnsupported.into());
+        }
+
+        Ok(Self {
+            index: MTFBackupIndex::build(&data),
+            data,
+            fragments,
+        })
+    }
+
+    // The database of one of several backups appended to the same file, by the data set number of
+    // its SSET (FILE = n of RESTORE)
+    pub fn for_set(parser: &'a mut MTFParser, set_number: u16) -> Result<Self> {
+        let dblks = parser.set(set_number)?;
+
+        let fragments: Vec<_> = dblks
+            .flat_map(|dblk| dblk.streams)
+            .filter(|stream| stream.id() == "MQDA")
+            .collect();
+
+        if fragments.is_empty() {
+            return Err(format_err!("backup set {} has no MQDA stream", set_number));
+        }
+
+        Self::from_streams(fragments)
+    }
+
+    // The MSCI stream layout is undocumented, but it carries the database name as UTF-16,
+    // so pick the first set whose MSCI contains the name and use the MQDA fragments following it
+    pub fn open_database_by_name(parser: &'a mut MTFParser, name: &str) -> Result<Self> {
+        let needle: Vec<u16> = name.encode_utf16().collect();
+        if needle.is_empty() {
+            return Err(format_err!("need a database name"));
+        }
+
+        let mut matches = false;
+        let mut fragments = Vec::new();
+
+        for dblk in parser.dblks()? {
+            if let DBLKSpecific::SSET { .. } = dblk.dblk.body {
+                if !fragments.is_empty() {
+                    break;
+                }
+                matches = false;
+            }
+
+            for stream in dblk.streams {
+                match &*stream.stream.header.id {
+                    "MSCI" => matches = contains_name(stream.data, &needle),
+                    "MQDA" if matches => fragments.push(stream),
+                    _ => {}
+                }
+            }
+        }
+
+        if fragments.is_empty() {
+            return Err(format_err!("no backup set of a database named {:?}", name));
+        }
+
+        Self::from_streams(fragments)
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.index.page_size
+    }
+
+    // Number of pages of the file actually contained in the backup, which can be less than num_pages
+    pub fn present_pages(&self, file_id: u16) -> u32 {
+        self.index.present_pages(file_id)
+    }
+
+    pub fn summary(&self) -> ProviderSummary {
+        let files = self
+            .file_ids()
+            .into_iter()
+            .map(|file_id| FileSummary {
+                file_id,
+                num_pages: self.num_pages(file_id),
+                present_pages: self.present_pages(file_id),
+            })
+            .collect();
+
+        ProviderSummary {
+            page_size: self.page_size(),
+            files,
         }
     }
+
+    // Where the page starts in the backup file, a page can continue in the next fragment though
+    pub fn offset_of(&self, ptr: PagePointer) -> Option<u64> {
+        self.location_of(ptr).map(|(_, offset)| offset)
+    }
+
+    // The stripe (in the order given to from_stripes) and the offset into its backup file
+    pub fn location_of(&self, ptr: PagePointer) -> Option<(usize, u64)> {
+        let offset = self.index.lookup(ptr)? as u64 * self.page_size() as u64;
+
+        self.fragments
+            .iter()
+            .rev()
+            .find(|fragment| fragment.start <= offset)
+            .map(|fragment| {
+                (
+                    fragment.stripe,
+                    fragment.file_offset + (offset - fragment.start),
+                )
+            })
+    }
+
+    fn page_data(&self, ptr: PagePointer) -> Option<&[u8]> {
+        let idx = self.index.lookup(ptr)? as usize;
+        self.data.page(idx)
+    }
+
+    // Writes the pages of a database file in page order, pages missing from the backup are zero filled
+    pub fn write_file<W: Write>(&self, file_id: u16, mut out: W) -> Result<()> {
+        let max_page_id = self
+            .index
+            .max_page_ids
+            .get(&file_id)
+            .ok_or_else(|| format_err!("backup does not contain file id {}", file_id))?;
+
+        let zero_page = vec![0u8; self.page_size()];
+
+        for page_id in 0..=*max_page_id {
+            let page = self.page_data(PagePointer { file_id, page_id });
+            out.write_all(page.unwrap_or(&zero_page))?;
+        }
+
+        Ok(())
+    }
+
+    // Compares every page of self (the older backup) against newer, grouped by allocation unit
+    pub fn diff(&self, newer: &MTFPageProvider) -> HashMap<u64, AllocationUnitDiff> {
+        let mut diffs: HashMap<u64, AllocationUnitDiff> = HashMap::new();
+
+        let mut file_ids = self.file_ids();
+        file_ids.extend(newer.file_ids());
+        file_ids.sort_unstable();
+        file_ids.dedup();
+
+        for file_id in file_ids {
+            let num_pages = |provider: &MTFPageProvider| {
+                provider
+                    .index
+                    .max_page_ids
+                    .get(&file_id)
+                    .map_or(0, |max| max + 1)
+            };
+
+            for page_id in 0..num_pages(self).max(num_pages(newer)) {
+                let ptr = PagePointer { file_id, page_id };
+
+                match (self.page_data(ptr), newer.page_data(ptr)) {
+                    (Some(old), Some(new)) if old != new => {
+                        diffs.entry(allocation_unit_id(new)).or_default().changed += 1
+                    }
+                    (Some(old), None) => {
+                        diffs.entry(allocation_unit_id(old)).or_default().removed += 1
+                    }
+                    (None, Some(new)) => {
+                        diffs.entry(allocation_unit_id(new)).or_default().added += 1
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        diffs
+    }
 }
 
 impl<'a> PageProvider for MTFPageProvider<'a> {
     fn file_ids(&self) -> Vec<u16> {
-        self.index.max_page_ids.keys().cloned().collect()
+        let mut file_ids: Vec<u16> = self.index.max_page_ids.keys().cloned().collect();
+        file_ids.sort_unstable();
+        file_ids
     }
 
     fn num_pages(&self, file_id: u16) -> u32 {
         self.index.max_page_ids[&file_id] + 1
     }
 
-    fn get(&self, ptr: PagePointer) -> Option<RawPage<Self>> {
-        let idx = self.index.lookup(ptr);
-        idx.and_then(|idx| {
-            if (idx + 1) as usize * PAGE_SIZE <= self.data.len() {
-                let page = RawPage::parse(
-                    &self.data[idx as usize * PAGE_SIZE..(idx + 1) as usize * PAGE_SIZE],
-                    self,
-                );
-                // Do some double checking here, maybe remove, when we are sure the index is working as expected
-                assert_eq!(page.header.ptr, ptr);
-                Some(page)
-            } else {
-                None
+    fn get(&self, ptr: PagePointer) -> Option<RawPage<'_, Self>> {
+        self.page_data(ptr).map(|data| {
+            let page = RawPage::parse(data, self);
+            // Do some double checking here, maybe remove, when we are sure the index is working as expected
+            assert_eq!(page.header.ptr, ptr);
+            page
+        })
+    }
+}
+
+// The state of the database as of a differential backup: pages the differential backup has are
+// taken from it, all others from the full backup it is based on
+pub struct DifferentialPageProvider<'a, 'b> {
+    base: MTFPageProvider<'a>,
+    diff: MTFPageProvider<'b>,
+}
+
+impl<'a, 'b> DifferentialPageProvider<'a, 'b> {
+    // Fails unless the differential backup can be based on base: the differential base LSN is
+    // not decoded, but a page the differential has again (it always has the system pages of the
+    // first extent) can't be older than in the full backup, and with the same LSN it has to be the
+    // same page
+    pub fn new(base: MTFPageProvider<'a>, diff: MTFPageProvider<'b>) -> Result<Self> {
+        if base.page_size() != diff.page_size() {
+            return Err(format_err!(
+                "full backup has {} byte pages, the differential {} byte ones",
+                base.page_size(),
+                diff.page_size()
+            ));
+        }
+
+        let mut common = 0;
+        for file_id in diff.file_ids() {
+            for page_id in 0..diff.num_pages(file_id) {
+                let ptr = PagePointer { file_id, page_id };
+                let (old, new) = match (base.page_data(ptr), diff.page_data(ptr)) {
+                    (Some(old), Some(new)) => (old, new),
+                    _ => continue,
+                };
+                common += 1;
+
+                if page_lsn(new) < page_lsn(old) || (page_lsn(new) == page_lsn(old) && old != new) {
+                    return Err(format_err!(
+                        "page {:?} of the differential backup is not a later version of the one in the full backup",
+                        ptr
+                    ));
+                }
+            }
+        }
+
+        if common == 0 {
+            return Err(format_err!(
+                "the differential backup has no page in common with the full backup"
+            ));
+        }
+
+        Ok(Self { base, diff })
+    }
+
+    // Additionally checks the sets: the differential has to be written later, on the same machine
+    pub fn for_sets(
+        base: &'a mut MTFParser,
+        base_set: u16,
+        diff: &'b mut MTFParser,
+        diff_set: u16,
+    ) -> Result<Self> {
+        let base_info = SqlBackupInfo::for_set(base, base_set)?;
+        let diff_info = SqlBackupInfo::for_set(diff, diff_set)?;
+
+        // as far as they are known
+        if let (Some(base_machine), Some(diff_machine)) =
+            (&base_info.machine_name, &diff_info.machine_name)
+        {
+            if base_machine != diff_machine {
+                return Err(format_err!(
+                    "full backup was written on {}, the differential on {}",
+                    base_machine,
+                    diff_machine
+                ));
+            }
+        }
+        if let (Some(base_start), Some(diff_start)) = (
+            base_info.start.unix_timestamp(),
+            diff_info.start.unix_timestamp(),
+        ) {
+            if diff_start < base_start {
+                return Err(format_err!(
+                    "differential backup was started before the full backup"
+                ));
             }
+        }
+
+        Self::new(
+            MTFPageProvider::for_set(base, base_set)?,
+            MTFPageProvider::for_set(diff, diff_set)?,
+        )
+    }
+
+    fn page_data(&self, ptr: PagePointer) -> Option<&[u8]> {
+        self.diff
+            .page_data(ptr)
+            .or_else(|| self.base.page_data(ptr))
+    }
+}
+
+impl<'a, 'b> PageProvider for DifferentialPageProvider<'a, 'b> {
+    fn file_ids(&self) -> Vec<u16> {
+        let mut file_ids = self.base.file_ids();
+        file_ids.extend(self.diff.file_ids());
+        file_ids.sort_unstable();
+        file_ids.dedup();
+        file_ids
+    }
+
+    // files can have grown since the full backup
+    fn num_pages(&self, file_id: u16) -> u32 {
+        [&self.base, &self.diff]
+            .iter()
+            .filter_map(|provider| provider.index.max_page_ids.get(&file_id))
+            .max()
+            .map_or(0, |max_page_id| max_page_id + 1)
+    }
+
+    fn get(&self, ptr: PagePointer) -> Option<RawPage<'_, Self>> {
+        self.page_data(ptr).map(|data| {
+            let page = RawPage::parse(data, self);
+            assert_eq!(page.header.ptr, ptr);
+            page
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, MQDABuilder};
+
+    // A page that passes is_page, its content tells it apart from the others
+    fn page(file_id: u16, page_id: u32, page_size: usize) -> Vec<u8> {
+        let mut page = vec![0; page_size];
+        page[0] = 1; // header version
+        page[1] = 1; // data page
+        LittleEndian::write_u32(&mut page[32..], page_id);
+        LittleEndian::write_u16(&mut page[36..], file_id);
+        LittleEndian::write_u16(&mut page[96..], file_id);
+        LittleEndian::write_u32(&mut page[98..], page_id);
+        page
+    }
+
+    fn mqda(parser: &mut MTFParser) -> Vec<StreamWithData<'_>> {
+        parser
+            .set(1)
+            .unwrap()
+            .flat_map(|dblk| dblk.streams)
+            .filter(|stream| stream.id() == "MQDA")
+            .collect()
+    }
+
+    // MTFBackupIndex::build caches the index in the working directory
+    fn remove_index_cache(provider: &MTFPageProvider) {
+        let _ = std::fs::remove_file(MTFBackupIndex::cache_name(&provider.data));
+    }
+
+    #[test]
+    fn pages_are_found_by_their_header() {
+        // the first extent of file 1, two pages of file 2 and a page of file 1 after a gap
+        let mut pages: Vec<_> = (0..8).map(|page_id| page(1, page_id, PAGE_SIZE)).collect();
+        pages.push(page(2, 0, PAGE_SIZE));
+        pages.push(page(2, 1, PAGE_SIZE));
+        pages.push(page(1, 10, PAGE_SIZE));
+
+        let medium = MQDABuilder::new()
+            .pages(pages.iter().map(Vec::as_slice))
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("pages-by-header", &medium);
+
+        let mqda = parser
+            .dblks()
+            .unwrap()
+            .flat_map(|dblk| dblk.streams)
+            .find(|stream| stream.id() == "MQDA")
+            .unwrap()
+            .stream
+            .base;
+
+        let provider = MTFPageProvider::for_set(&mut parser, 1).unwrap();
+        remove_index_cache(&provider);
+
+        assert_eq!(provider.page_size(), PAGE_SIZE);
+        assert_eq!(provider.file_ids(), [1, 2]);
+        assert_eq!(provider.num_pages(1), 11);
+        assert_eq!(provider.present_pages(1), 9);
+        assert_eq!(provider.num_pages(2), 2);
+        assert_eq!(provider.present_pages(2), 2);
+
+        for (idx, expected) in pages.iter().enumerate() {
+            let ptr = PageHeader::parse_ptr(expected).unwrap();
+            assert_eq!(provider.page_data(ptr), Some(&expected[..]));
+            assert_eq!(
+                provider.offset_of(ptr),
+                Some(mqda + 2 + (idx * PAGE_SIZE) as u64)
+            );
+        }
+        let missing = PagePointer {
+            file_id: 1,
+            page_id: 9,
+        };
+        assert_eq!(provider.page_data(missing), None);
+        assert_eq!(provider.offset_of(missing), None);
+
+        let mut file = Vec::new();
+        provider.write_file(2, &mut file).unwrap();
+        assert_eq!(file, [&pages[8][..], &pages[9][..]].concat());
+        assert!(provider.write_file(3, &mut file).is_err());
+    }
+
+    #[test]
+    fn database_by_name() {
+        let pages: Vec<_> = (0..2).map(|page_id| page(1, page_id, PAGE_SIZE)).collect();
+        let medium = MQDABuilder::new()
+            .pages(pages.iter().map(Vec::as_slice))
+            .database_name("model")
+            .build()
+            .unwrap();
+
+        let mut parser = fixture::open("database-by-name", &medium);
+        let provider = MTFPageProvider::open_database_by_name(&mut parser, "model").unwrap();
+        remove_index_cache(&provider);
+        assert_eq!(provider.file_ids(), [1]);
+        assert_eq!(provider.num_pages(1), 2);
+
+        // only the whole name matches
+        let mut parser = fixture::open("database-by-prefix", &medium);
+        assert!(MTFPageProvider::open_database_by_name(&mut parser, "mode").is_err());
+    }
+
+    #[test]
+    fn file_list_names() {
+        // the page size is detected from the first extent
+        let mut pages: Vec<_> = (0..8).map(|page_id| page(1, page_id, PAGE_SIZE)).collect();
+        pages.push(page(3, 0, PAGE_SIZE));
+        let medium = MQDABuilder::new()
+            .pages(pages.iter().map(Vec::as_slice))
+            .file("Sales", r"C:\Data\Sales.mdf")
+            .file("Sales_archive", r"D:\Data\Sales_archive.NDF")
+            .file("Sales_log", r"C:\Logs\Sales_log.ldf")
+            .build()
+            .unwrap();
+
+        let mut parser = fixture::open("file-list-names", &medium);
+        let files = SqlFileList::for_set(&mut parser, 1).unwrap();
+        remove_index_cache(&MTFPageProvider::for_set(&mut parser, 1).unwrap());
+
+        let names: Vec<_> = files
+            .files
+            .iter()
+            .map(|file| {
+                (
+                    file.file_id,
+                    file.size,
+                    file.logical_name.as_deref(),
+                    file.physical_name.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                (
+                    1,
+                    8 * PAGE_SIZE as u64,
+                    Some("Sales"),
+                    Some(r"C:\Data\Sales.mdf")
+                ),
+                (
+                    3,
+                    PAGE_SIZE as u64,
+                    Some("Sales_archive"),
+                    Some(r"D:\Data\Sales_archive.NDF")
+                ),
+            ]
+        );
+        assert_eq!(
+            files.log_files,
+            [SqlLogFile {
+                logical_name: Some("Sales_log".to_string()),
+                physical_name: r"C:\Logs\Sales_log.ldf".to_string(),
+            }]
+        );
+
+        // without an MQCI stream only ids and sizes are known
+        let medium = MQDABuilder::new()
+            .pages(pages[..8].iter().map(Vec::as_slice))
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("file-list-without-names", &medium);
+        let files = SqlFileList::for_set(&mut parser, 1).unwrap();
+        remove_index_cache(&MTFPageProvider::for_set(&mut parser, 1).unwrap());
+        assert_eq!(files.files.len(), 1);
+        assert_eq!(files.files[0].physical_name, None);
+        assert!(files.log_files.is_empty());
+    }
+
+    #[test]
+    fn backup_info() {
+        let pages: Vec<_> = (0..8).map(|page_id| page(1, page_id, PAGE_SIZE)).collect();
+        let medium = MQDABuilder::new()
+            .pages(pages.iter().map(Vec::as_slice))
+            .database_name("Sales")
+            .attrs(SSetAttrs::DIFFERENTIAL)
+            .build()
+            .unwrap();
+
+        let mut parser = fixture::open("backup-info", &medium);
+        let info = SqlBackupInfo::for_set(&mut parser, 1).unwrap();
+        assert_eq!(info.data_set_number, 1);
+        assert_eq!(info.database_name.as_deref(), Some("Sales"));
+        assert_eq!(info.backup_type, Some(SqlBackupType::DIFFERENTIAL));
+        assert_eq!(info.backup_name, None);
+        assert!(info.finish.is_some());
+
+        let all = SqlBackupInfo::all(&mut parser).unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].database_name.as_deref(), Some("Sales"));
+
+        // the set attributes tell the type, the MSCI the name
+        let medium = MQDABuilder::new()
+            .pages(pages.iter().map(Vec::as_slice))
+            .attrs(SSetAttrs::NORMAL)
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("backup-info-without-msci", &medium);
+        let info = SqlBackupInfo::for_set(&mut parser, 1).unwrap();
+        assert_eq!(info.database_name, None);
+        assert_eq!(info.backup_type, Some(SqlBackupType::DATABASE));
+        assert!(SqlBackupInfo::for_set(&mut parser, 2).is_err());
+    }
+
+    #[test]
+    fn page_size_detection() {
+        let pages = |page_size| -> Vec<u8> {
+            (0..8)
+                .flat_map(|page_id| page(1, page_id, page_size))
+                .collect()
+        };
+
+        assert_eq!(detect_page_size(&pages(PAGE_SIZE)).unwrap(), PAGE_SIZE);
+        assert_eq!(detect_page_size(&pages(2048)).unwrap(), 2048);
+
+        // a single page is enough
+        assert_eq!(detect_page_size(&page(1, 0, 2048)).unwrap(), 2048);
+
+        // the first extent has to be complete, page 3 is missing here
+        let mut data = pages(PAGE_SIZE);
+        data[3 * PAGE_SIZE] = 0;
+        let err = detect_page_size(&data).unwrap_err();
+        assert!(err.downcast_ref::<NotPageAligned>().is_some());
+
+        let err = detect_page_size(&[0xff; PAGE_SIZE]).unwrap_err();
+        assert!(err.downcast_ref::<NotPageAligned>().is_some());
+    }
+
+    #[test]
+    fn explicit_page_size() {
+        let medium = MQDABuilder::new()
+            .page(&page(1, 0, PAGE_SIZE))
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("explicit-page-size", &medium);
+
+        assert!(MTFPageProvider::from_streams_with_page_size(mqda(&mut parser), 4096).is_err());
+
+        let provider =
+            MTFPageProvider::from_streams_with_page_size(mqda(&mut parser), PAGE_SIZE).unwrap();
+        remove_index_cache(&provider);
+        assert_eq!(provider.page_size(), PAGE_SIZE);
+        assert_eq!(provider.num_pages(1), 1);
+    }
+
+    // A FixedVar record with the name as its only variable length column
+    fn record(fixed: &[u8], name: &str) -> Vec<u8> {
+        let name: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let mut record = vec![0x30, 0];
+        record.extend_from_slice(&(4 + fixed.len() as u16).to_le_bytes());
+        record.extend_from_slice(fixed);
+        record.extend_from_slice(&[1, 0, 0]); // column count and null bitmap
+        let end = record.len() + 4 + name.len();
+        record.extend_from_slice(&[1, 0]);
+        record.extend_from_slice(&(end as u16).to_le_bytes());
+        record.extend_from_slice(&name);
+        record
+    }
+
+    // A data page of the clustered index of a system base table
+    fn system_page(page_id: u32, object_id: u32, records: &[Vec<u8>]) -> Vec<u8> {
+        let mut page = page(1, page_id, PAGE_SIZE);
+        LittleEndian::write_u16(&mut page[6..], 1);
+        LittleEndian::write_u16(&mut page[22..], records.len() as u16);
+        LittleEndian::write_u32(&mut page[24..], object_id);
+
+        let mut offset = 96;
+        for (slot, record) in records.iter().enumerate() {
+            page[offset..offset + record.len()].copy_from_slice(record);
+            LittleEndian::write_u16(&mut page[PAGE_SIZE - 2 * (slot + 1)..], offset as u16);
+            offset += record.len();
+        }
+        page
+    }
+
+    #[test]
+    fn schema_from_system_base_tables() {
+        let fields = |fields: &[&[u8]]| fields.concat();
+        let int = |value: i32| value.to_le_bytes().to_vec();
+
+        // class, id, status, type, intprop, created, modified
+        let schemas = [record(&fields(&[&[50], &int(1), &[0; 26]]), "dbo")];
+        // id, nsid, nsclass, status, type, pid, pclass, intprop, created, modified
+        let objects = [
+            record(
+                &fields(&[&int(1000), &int(1), &[0; 5], b"U ", &[0; 25]]),
+                "Customers",
+            ),
+            record(
+                &fields(&[&int(1001), &int(1), &[0; 5], b"V ", &[0; 25]]),
+                "Names",
+            ),
+        ];
+        // id, number, colid, xtype, utype, length, prec, scale, collationid, status, ...
+        let column = |colid: i32, xtype: u8, length: i16, status: i32, name: &str| {
+            let fixed = fields(&[
+                &int(1000),
+                &[0; 2],
+                &int(colid),
+                &[xtype],
+                &int(xtype as i32),
+                &length.to_le_bytes(),
+                &[0, 0],
+                &int(0),
+                &int(status),
+                &[0; 14],
+            ]);
+            record(&fixed, name)
+        };
+        let columns = [column(2, 231, 100, 0, "name"), column(1, 56, 4, 1, "id")];
+        // id, indid, status, intprop, fillfact, type, tinyprop, dataspace, lobds, rowset
+        let indexes = [record(
+            &fields(&[
+                &int(1000),
+                &int(1),
+                &int(0x28),
+                &int(0),
+                &[0, 1, 0],
+                &[0; 16],
+            ]),
+            "PK_Customers",
+        )];
+        // idmajor, idminor, subid, status, intprop, tinyprop1, tinyprop2
+        let index_columns = [record(
+            &fields(&[&int(1000), &int(1), &int(1), &int(2), &int(1), &[1, 0]]),
+            "",
+        )];
+
+        let pages = [
+            page(1, 0, PAGE_SIZE),
+            system_page(1, 64, &schemas),
+            system_page(2, 34, &objects),
+            system_page(3, 41, &columns),
+            system_page(4, 54, &indexes),
+            system_page(5, 55, &index_columns),
+        ];
+        let medium = MQDABuilder::new()
+            .pages(pages.iter().map(Vec::as_slice))
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("schema", &medium);
+        let provider = MTFPageProvider::for_set(&mut parser, 1).unwrap();
+        remove_index_cache(&provider);
+
+        let schema = crate::schema::Schema::read(&provider).unwrap();
+        assert_eq!(schema.tables.len(), 1);
+        let table = &schema.tables[0];
+        assert_eq!((&*table.schema, &*table.name), ("dbo", "Customers"));
+
+        let columns: Vec<_> = table
+            .columns
+            .iter()
+            .map(|column| (&*column.name, column.type_name(), column.nullable))
+            .collect();
+        assert_eq!(
+            columns,
+            [
+                ("id", "int".to_string(), false),
+                ("name", "nvarchar(50)".to_string(), true)
+            ]
+        );
+
+        assert_eq!(table.indexes.len(), 1);
+        let index = &table.indexes[0];
+        assert_eq!(index.name.as_deref(), Some("PK_Customers"));
+        assert_eq!(index.kind, crate::schema::IndexKind::CLUSTERED);
+        assert!(index.unique && index.primary_key);
+        assert_eq!(index.columns.len(), 1);
+        assert_eq!(table.column(index.columns[0].column_id).unwrap().name, "id");
+        assert!(!index.columns[0].descending && !index.columns[0].included);
+    }
+}
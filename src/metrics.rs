@@ -0,0 +1,25 @@
+use crate::IterationSummary;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// Hooks for services that export metrics, like log installs one global logger.
+// Everything does nothing by default.
+pub trait Metrics: Send + Sync {
+    // a DBLKIterator ran to completion, blocks per second are summary.blocks / elapsed
+    fn iteration_finished(&self, _summary: &IterationSummary, _elapsed: Duration) {}
+
+    // a page index was loaded from the on disk cache (hit) or had to be built (miss)
+    fn index_cache(&self, _hit: bool) {}
+}
+
+static METRICS: RwLock<Option<Arc<dyn Metrics>>> = RwLock::new(None);
+
+pub fn set_metrics(metrics: Arc<dyn Metrics>) {
+    *METRICS.write().unwrap() = Some(metrics);
+}
+
+pub(crate) fn report<F: FnOnce(&dyn Metrics)>(f: F) {
+    if let Some(metrics) = METRICS.read().unwrap().as_ref() {
+        f(metrics.as_ref())
+    }
+}
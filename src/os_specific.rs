@@ -0,0 +1,131 @@
+use crate::{DBLKType, Result, StringDecoder, TapeAddress, OS};
+use bitflags::*;
+use byteorder::{ByteOrder, LittleEndian};
+use std::io::Cursor;
+
+bitflags! {
+    // FILE_ATTRIBUTE_* as used by Win32
+    pub struct NtFileAttrs: u32 {
+        const READONLY = 0x1;
+        const HIDDEN = 0x2;
+        const SYSTEM = 0x4;
+        const DIRECTORY = 0x10;
+        const ARCHIVE = 0x20;
+        const DEVICE = 0x40;
+        const NORMAL = 0x80;
+        const TEMPORARY = 0x100;
+        const SPARSE_FILE = 0x200;
+        const REPARSE_POINT = 0x400;
+        const COMPRESSED = 0x800;
+        const OFFLINE = 0x1000;
+        const NOT_CONTENT_INDEXED = 0x2000;
+        const ENCRYPTED = 0x4000;
+    }
+}
+
+// The layout of the NT structures, by the osver of the block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NtVersion {
+    // osver 0, written by NT 3.x: the attributes, files also have their short name
+    NT3,
+    // osver 1, written by NT 5.x: files also have the link flag, directories their short name
+    NT5,
+}
+
+impl NtVersion {
+    // later versions only ever appended fields, so they are read like the last known one
+    fn parse(osver: u8) -> NtVersion {
+        match osver {
+            0 => NtVersion::NT3,
+            _ => NtVersion::NT5,
+        }
+    }
+
+    fn file_size(self) -> usize {
+        match self {
+            NtVersion::NT3 => 8,
+            NtVersion::NT5 => 10,
+        }
+    }
+
+    fn dirb_size(self) -> usize {
+        match self {
+            NtVersion::NT3 => 4,
+            NtVersion::NT5 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum OsSpecificData {
+    NONE,
+    NT_FILE {
+        version: NtVersion,
+        attrs: NtFileAttrs,
+        short_name: Option<String>,
+        // always false for NT3
+        link: bool,
+    },
+    NT_DIRB {
+        version: NtVersion,
+        attrs: NtFileAttrs,
+        // always None for NT3
+        short_name: Option<String>,
+    },
+    // layouts we don't know (yet), kept as raw bytes. That includes the data of Unix (osid 28)
+    // blocks, MTF assigns the id but defines no structure for it, so mode, owner and group are
+    // only there for callers that know the format of their writer
+    UNKNOWN(Vec<u8>),
+}
+
+impl OsSpecificData {
+    pub(crate) fn parse<T: AsRef<[u8]>>(
+        osid: &OS,
+        osver: u8,
+        ty: &DBLKType,
+        address: TapeAddress,
+        strings: &StringDecoder,
+        data: &mut Cursor<T>,
+    ) -> Result<OsSpecificData> {
+        let base = address.base;
+        let raw = match address.read_bytes(data)? {
+            Some(raw) => raw,
+            None => return Ok(OsSpecificData::NONE),
+        };
+
+        let version = NtVersion::parse(osver);
+        // newer windows versions keep adding attributes, so don't be strict here
+        let attrs = |raw: &[u8]| NtFileAttrs::from_bits_truncate(LittleEndian::read_u32(raw));
+        let short_name = |raw: &[u8], data: &mut Cursor<T>| {
+            TapeAddress {
+                size: LittleEndian::read_u16(&raw[4..]),
+                offset: LittleEndian::read_u16(&raw[6..]),
+                base,
+            }
+            .read_str(strings, data)
+        };
+
+        // too short for the layout of its version, better keep it raw than read the wrong fields
+        Ok(match (osid, ty) {
+            (OS::WindowsNT, DBLKType::FILE) if raw.len() >= version.file_size() => {
+                OsSpecificData::NT_FILE {
+                    version,
+                    attrs: attrs(&raw),
+                    short_name: short_name(&raw, data)?,
+                    link: version == NtVersion::NT5 && LittleEndian::read_u16(&raw[8..]) != 0,
+                }
+            }
+            (OS::WindowsNT, DBLKType::DIRB) if raw.len() >= version.dirb_size() => {
+                OsSpecificData::NT_DIRB {
+                    version,
+                    attrs: attrs(&raw),
+                    short_name: match version {
+                        NtVersion::NT3 => None,
+                        NtVersion::NT5 => short_name(&raw, data)?,
+                    },
+                }
+            }
+            _ => OsSpecificData::UNKNOWN(raw),
+        })
+    }
+}
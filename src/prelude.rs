@@ -0,0 +1,5 @@
+// Everything needed for the common tasks, use mtf::prelude::*
+pub use crate::backup::{Backup, FileReader, SetFile};
+pub use crate::files::FileEntry;
+pub use crate::sets::BackupSet;
+pub use crate::{MTFOptions, MTFParser};
@@ -0,0 +1,1413 @@
+use crate::audit::{self, AuditEvent};
+use crate::backup::{FileReader, SetFile};
+use crate::compression::CompressionAlgorithm;
+use crate::crypto::EncryptionAlgorithm;
+use crate::files::{AlternateStream, FileEntry, MatchPolicy, ReparsePoint};
+use crate::os_specific::{NtFileAttrs, OsSpecificData};
+use crate::sets::{BackupSet, Directory};
+use crate::streams::StreamKind;
+use crate::{DateTime, FileAttrs, MTFParser, Result, UnsupportedFeature};
+use failure::format_err;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, FileTimes, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_SIDECAR_TEMPLATE: &str = "{name}.{id}";
+
+pub const DEFAULT_PATH_TEMPLATE: &str = "{path}";
+
+// Size of the chunks handed to an ExtractSink
+pub const SINK_CHUNK_SIZE: usize = 64 * 1024;
+
+// Receives the data of extracted files instead of the file system, for destinations like network
+// uploads. Every call may block, which stops reading from the medium until the destination caught
+// up, so at most what the sink buffers is held in memory
+pub trait ExtractSink {
+    fn begin(&mut self, file: &FileEntry) -> Result<()>;
+    fn data(&mut self, chunk: Vec<u8>) -> Result<()>;
+    fn end(&mut self) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub enum ExtractEvent {
+    BEGIN { path: PathBuf, size: u64 },
+    DATA(Vec<u8>),
+    END,
+}
+
+// A bounded channel (std::sync::mpsc::sync_channel) applies the back-pressure by blocking once it
+// is full, the receiving end does the upload on another thread
+impl ExtractSink for SyncSender<ExtractEvent> {
+    fn begin(&mut self, file: &FileEntry) -> Result<()> {
+        send(
+            self,
+            ExtractEvent::BEGIN {
+                path: file.full_path.clone(),
+                size: file.size,
+            },
+        )
+    }
+
+    fn data(&mut self, chunk: Vec<u8>) -> Result<()> {
+        send(self, ExtractEvent::DATA(chunk))
+    }
+
+    fn end(&mut self) -> Result<()> {
+        send(self, ExtractEvent::END)
+    }
+}
+
+fn send(sender: &SyncSender<ExtractEvent>, event: ExtractEvent) -> Result<()> {
+    sender
+        .send(event)
+        .map_err(|_| format_err!("the receiver of the extracted data is gone"))
+}
+
+// What extract does with files that are symbolic links or junctions (mount points)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    // Leave them out
+    SKIP,
+    // Write a regular file holding the target of the link instead
+    PLACEHOLDER,
+    // Recreate symbolic links as links with reparse_points, write their data as regular files
+    // otherwise. Either way nothing is ever created or written through a link restored earlier,
+    // every path below the target directory is checked component by component
+    #[default]
+    SAFE,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Restorer {
+    allow_protected: bool,
+    symlink_policy: SymlinkPolicy,
+    skip_metadata: bool,
+    alternate_streams: bool,
+    skip_space_check: bool,
+    reparse_points: bool,
+    skip_unknown_streams: bool,
+    sidecar_template: Option<String>,
+    path_template: Option<String>,
+}
+
+impl Restorer {
+    pub fn new() -> Restorer {
+        Restorer::default()
+    }
+
+    // Password protected sets are readable, but refuse to extract them unless asked to
+    pub fn allow_protected(mut self) -> Restorer {
+        self.allow_protected = true;
+        self
+    }
+
+    // Also restore the named NTFS data streams of files, as file:name on Windows,
+    // and as a file.name.ads sidecar file elsewhere
+    pub fn alternate_streams(mut self) -> Restorer {
+        self.alternate_streams = true;
+        self
+    }
+
+    // Don't apply the dates and attributes recorded in the backup to the restored files
+    pub fn skip_metadata(mut self) -> Restorer {
+        self.skip_metadata = true;
+        self
+    }
+
+    // Recreate files that are symbolic links as links, instead of regular files with their data.
+    // The targets are written as recorded, so they usually point to Windows paths. Only with
+    // SymlinkPolicy::SAFE
+    pub fn reparse_points(mut self) -> Restorer {
+        self.reparse_points = true;
+        self
+    }
+
+    // SymlinkPolicy::SAFE by default. extract_to leaves links out with SKIP and passes on their
+    // data otherwise
+    pub fn symlink_policy(mut self, policy: SymlinkPolicy) -> Restorer {
+        self.symlink_policy = policy;
+        self
+    }
+
+    // Streams of files this crate doesn't know (vendor data next to STAN) are written as sidecar
+    // files next to the file by default, named after the template. {name} is replaced by the name
+    // of the file and {id} by the stream id, the default is DEFAULT_SIDECAR_TEMPLATE
+    pub fn sidecar_template(mut self, template: &str) -> Restorer {
+        self.sidecar_template = Some(template.to_string());
+        self
+    }
+
+    // Where extract puts a file below the target directory, / separates directories. {path} is
+    // replaced by the path of the file relative to its volume, {set_number}, {set_name} and
+    // {volume} by the data set number, the set name and the device name of the volume. With
+    // "{set_number}_{set_name}/{path}" every set lands in a directory of its own instead of
+    // replacing the files of earlier sets. The default is DEFAULT_PATH_TEMPLATE
+    pub fn path_template(mut self, template: &str) -> Restorer {
+        self.path_template = Some(template.to_string());
+        self
+    }
+
+    // Drop the unknown streams instead of writing them as sidecar files
+    pub fn skip_unknown_streams(mut self) -> Restorer {
+        self.skip_unknown_streams = true;
+        self
+    }
+
+    // Don't check that the target has enough free space before extracting
+    pub fn skip_space_check(mut self) -> Restorer {
+        self.skip_space_check = true;
+        self
+    }
+
+    // Writes the data of every selected file below target_dir, at its path relative to the volume
+    // (see path_template). Sets are restored in media order, so files of later sets replace the
+    // ones of earlier sets.
+    pub fn extract<F: FnMut(&FileEntry) -> bool>(
+        &self,
+        parser: &mut MTFParser,
+        mut selection: F,
+        target_dir: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        let sets = parser.sets()?;
+        let files = self.select(&sets, &mut selection)?;
+
+        self.extract_selected(files, target_dir)
+    }
+
+    // Restores the merged view of a chain of sets, a full backup followed by its incremental
+    // backups, by their data set numbers in the order they were taken. Only the latest version of
+    // every path (compared like Windows does) is written, the sets of the chain replace each other
+    // in chain order, not in media order. MTF doesn't record deletions, so a file deleted between
+    // two backups of the chain is restored from the last set that has it
+    pub fn extract_chain<F: FnMut(&FileEntry) -> bool>(
+        &self,
+        parser: &mut MTFParser,
+        chain: &[u16],
+        mut selection: F,
+        target_dir: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        let sets = parser.sets()?;
+        let chain = chain
+            .iter()
+            .map(|&number| {
+                sets.iter()
+                    .find(|set| set.data_set_number() == Some(number))
+                    .ok_or_else(|| format_err!("set {} of the chain is not on the medium", number))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut files: Vec<(&BackupSet, &FileEntry)> = Vec::new();
+        let mut latest = HashMap::new();
+        for (set, file) in self.select(chain, &mut selection)? {
+            let key = (
+                file.volume
+                    .as_deref()
+                    .map(|volume| MatchPolicy::WINDOWS.key(volume)),
+                MatchPolicy::WINDOWS.key(&file.full_path.to_string_lossy()),
+            );
+
+            match latest.get(&key) {
+                Some(&idx) => files[idx] = (set, file),
+                None => {
+                    latest.insert(key, files.len());
+                    files.push((set, file));
+                }
+            }
+        }
+
+        self.extract_selected(files, target_dir)
+    }
+
+    fn extract_selected(
+        &self,
+        files: Vec<(&BackupSet, &FileEntry)>,
+        target_dir: &Path,
+    ) -> Result<Vec<PathBuf>> {
+        if !self.skip_space_check {
+            let required: u64 = files
+                .iter()
+                .map(|(_, file)| self.required_bytes(file))
+                .sum();
+
+            std::fs::create_dir_all(target_dir)?;
+            let available = fs2::available_space(target_dir)?;
+
+            if required > available {
+                return Err(format_err!(
+                    "extraction needs {} bytes, but only {} bytes are available in {:?}",
+                    required,
+                    available,
+                    target_dir
+                ));
+            }
+        }
+
+        files
+            .into_iter()
+            .map(|(set, file)| {
+                let result = self.extract_file(set, file, target_dir);
+                audit_extraction(file, &result);
+                result
+            })
+            .collect()
+    }
+
+    // Like extract, but hands the data (of the STAN streams, decompressed and decrypted) to sink in
+    // chunks of SINK_CHUNK_SIZE. Holes of sparse files are left out and only the data is passed
+    // on, no metadata or other streams. Returns the number of bytes passed on
+    pub fn extract_to<F: FnMut(&FileEntry) -> bool, S: ExtractSink>(
+        &self,
+        parser: &mut MTFParser,
+        selection: F,
+        sink: &mut S,
+    ) -> Result<u64> {
+        let mut written = 0;
+
+        self.extract_with(parser, selection, |entry, reader| {
+            sink.begin(entry)?;
+            loop {
+                let mut chunk = vec![0; SINK_CHUNK_SIZE];
+                let len = read_full(reader, &mut chunk)?;
+                if len == 0 {
+                    break;
+                }
+
+                chunk.truncate(len);
+                written += len as u64;
+                sink.data(chunk)?;
+            }
+            sink.end()
+        })?;
+
+        Ok(written)
+    }
+
+    // Hands every selected file to callback together with a reader of its data (like extract_to
+    // does), for destinations that want to pull the data themselves, like indexers or virus
+    // scanners. The reader doesn't have to be read to the end. Returns the number of files
+    pub fn extract_with<F, C>(
+        &self,
+        parser: &mut MTFParser,
+        mut selection: F,
+        mut callback: C,
+    ) -> Result<u64>
+    where
+        F: FnMut(&FileEntry) -> bool,
+        C: FnMut(&FileEntry, &mut FileReader<'_, '_>) -> Result<()>,
+    {
+        let sets = parser.sets()?;
+        let files = self.select(&sets, &mut selection)?;
+
+        for (set, entry) in &files {
+            let mut reader = SetFile {
+                entry,
+                compression_algorithm: set.compression_algorithm(),
+            }
+            .reader();
+
+            callback(entry, &mut reader)?;
+        }
+
+        Ok(files.len() as u64)
+    }
+
+    // The bytes extract would write for the selection. Files that are replaced by a later set
+    // are counted every time, so this can be more than what ends up on disk.
+    pub fn required_space<F: FnMut(&FileEntry) -> bool>(
+        &self,
+        parser: &mut MTFParser,
+        mut selection: F,
+    ) -> Result<u64> {
+        let sets = parser.sets()?;
+        let files = self.select(&sets, &mut selection)?;
+
+        Ok(files
+            .iter()
+            .map(|(_, file)| self.required_bytes(file))
+            .sum())
+    }
+
+    fn select<'s, 'a, S, F>(
+        &self,
+        sets: S,
+        selection: &mut F,
+    ) -> Result<Vec<(&'s BackupSet<'a>, &'s FileEntry<'a>)>>
+    where
+        S: IntoIterator<Item = &'s BackupSet<'a>>,
+        F: FnMut(&FileEntry) -> bool,
+    {
+        let mut selected = Vec::new();
+
+        for set in sets {
+            let mut files = Vec::new();
+            for volume in &set.volumes {
+                collect_files(&volume.root, selection, &mut files);
+            }
+
+            if self.symlink_policy == SymlinkPolicy::SKIP {
+                files.retain(|file| !is_link(file));
+            }
+
+            if !files.is_empty() && set.is_password_protected() && !self.allow_protected {
+                return Err(format_err!(
+                    "set {:?} is password protected, use allow_protected to extract it anyway",
+                    set.data_set_number()
+                ));
+            }
+
+            // fail before anything is written, not in the middle of the extraction
+            let algorithm = set.compression_algorithm();
+            if let Some(unsupported) = files
+                .iter()
+                .find_map(|file| unsupported_compression(file, algorithm))
+            {
+                return Err(UnsupportedFeature(format!(
+                    "set {:?} is compressed with {:?}, which is not supported",
+                    set.data_set_number(),
+                    unsupported
+                ))
+                .into());
+            }
+
+            if let Some(unsupported) = files.iter().find_map(|file| unsupported_encryption(file)) {
+                return Err(UnsupportedFeature(format!(
+                    "set {:?} has files encrypted with {:?}, which is not supported",
+                    set.data_set_number(),
+                    unsupported
+                ))
+                .into());
+            }
+
+            selected.extend(files.into_iter().map(|file| (set, file)));
+        }
+
+        Ok(selected)
+    }
+
+    // Only the stored data counts, the holes of sparse files don't take up space
+    fn required_bytes(&self, file: &FileEntry) -> u64 {
+        let data: u64 = file
+            .streams
+            .iter()
+            .filter(|stream| matches!(stream.id(), "STAN" | "SPAR"))
+            .map(|stream| stream.data.len() as u64)
+            .sum();
+
+        let alternate: u64 = if self.alternate_streams {
+            file.alternate_streams()
+                .iter()
+                .map(|stream| stream.data.len() as u64)
+                .sum()
+        } else {
+            0
+        };
+
+        let unknown: u64 = if self.skip_unknown_streams {
+            0
+        } else {
+            file.streams
+                .iter()
+                .filter(|stream| matches!(stream.kind(), StreamKind::UNKNOWN(_)))
+                .map(|stream| stream.data.len() as u64)
+                .sum()
+        };
+
+        data + alternate + unknown
+    }
+
+    // The path of a file below the target directory, by the path template
+    fn relative_path(&self, set: &BackupSet, file: &FileEntry, path: &Path) -> PathBuf {
+        // these are names of a single component
+        let name = |name: &str| name.replace(['/', '\\', ':', '\0'], "_");
+        let path: Vec<_> = path
+            .iter()
+            .map(|component| component.to_string_lossy())
+            .collect();
+
+        let relative = self
+            .path_template
+            .as_deref()
+            .unwrap_or(DEFAULT_PATH_TEMPLATE)
+            .replace(
+                "{set_number}",
+                &set.data_set_number()
+                    .map_or(String::new(), |n| n.to_string()),
+            )
+            .replace("{set_name}", &name(set.name().unwrap_or_default()))
+            .replace(
+                "{volume}",
+                &name(file.volume.as_deref().unwrap_or_default()),
+            )
+            .replace("{path}", &path.join("/"));
+
+        relative.split('/').filter(|c| !c.is_empty()).collect()
+    }
+
+    fn extract_file(
+        &self,
+        set: &BackupSet,
+        file: &FileEntry,
+        target_dir: &Path,
+    ) -> Result<PathBuf> {
+        // the names come straight from the medium, don't let them escape the target directory.
+        // The set name and the template can't either
+        let relative = self.relative_path(set, file, &file.full_path);
+        if ![&file.full_path, &relative].iter().all(|path| {
+            path.components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        }) {
+            return Err(format_err!(
+                "refusing to extract file {:?} to unsafe path {:?}",
+                file.full_path,
+                relative
+            ));
+        }
+
+        // don't leave a partial file behind that looks like it was restored
+        if let Some(stream) = file
+            .streams
+            .iter()
+            .find(|stream| stream.id() == "STAN" && stream.is_truncated())
+        {
+            return Err(format_err!(
+                "refusing to extract {:?}, the medium ends after {} of its {} bytes",
+                file.full_path,
+                stream.data.len(),
+                stream.len()
+            ));
+        }
+
+        let path = target_dir.join(&relative);
+
+        // a symlink restored earlier can point anywhere, don't create or write anything through it
+        if let Some(link) = path
+            .parent()
+            .and_then(|parent| symlink_below(parent, target_dir))
+        {
+            return Err(format_err!(
+                "refusing to extract {:?} through the symbolic link {:?}",
+                file.full_path,
+                link
+            ));
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        if let Some(target) = file.hard_link() {
+            let target = target_dir.join(self.relative_path(set, file, &target));
+
+            // the data was only stored for the first link, if that was not restored write it here
+            if target != path
+                && target.is_file()
+                && safe_path(&target, target_dir)
+                && symlink_below(&target, target_dir).is_none()
+            {
+                remove_existing(&path)?;
+                std::fs::hard_link(&target, &path)?;
+                return Ok(path);
+            }
+        }
+
+        if self.symlink_policy == SymlinkPolicy::PLACEHOLDER && is_link(file) {
+            let target = file
+                .reparse_point()
+                .and_then(|reparse_point| reparse_point.target)
+                .unwrap_or_default();
+
+            remove_existing(&path)?;
+            std::fs::write(&path, target)?;
+            return Ok(path);
+        }
+
+        // otherwise whatever data was stored is written as a regular file
+        if let Some(ReparsePoint {
+            tag: ReparsePoint::TAG_SYMLINK,
+            target: Some(target),
+            ..
+        }) = file
+            .reparse_point()
+            .filter(|_| self.reparse_points && self.symlink_policy == SymlinkPolicy::SAFE)
+        {
+            remove_existing(&path)?;
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(&target, &path)?;
+            #[cfg(windows)]
+            std::os::windows::fs::symlink_file(&target, &path)?;
+
+            return Ok(path);
+        }
+
+        // a symlink restored from an earlier set is replaced, not written through
+        remove_symlink(&path)?;
+
+        // a file restored read only from an earlier set can't be opened for writing
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            let mut permissions = metadata.permissions();
+            if permissions.readonly() {
+                #[allow(clippy::permissions_set_readonly_false)]
+                permissions.set_readonly(false);
+                std::fs::set_permissions(&path, permissions)?;
+            }
+        }
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::OpenOptionsExt;
+
+            if !self.skip_metadata {
+                options.attributes(windows_attributes(file));
+            }
+        }
+
+        let mut out = options.open(&path)?;
+        let mut reader = SetFile {
+            entry: file,
+            compression_algorithm: set.compression_algorithm(),
+        }
+        .reader();
+        std::io::copy(&mut reader, &mut out)
+            .map_err(|err| format_err!("could not extract {:?}: {}", file.full_path, err))?;
+
+        // seeking past the end leaves holes where the file system supports them, zeros otherwise
+        if file.is_sparse() {
+            for block in file.sparse_blocks() {
+                out.seek(SeekFrom::Start(block.offset))?;
+                out.write_all(block.data)?;
+            }
+
+            // the file can end in a hole
+            if file.size > out.metadata()?.len() {
+                out.set_len(file.size)?;
+            }
+        }
+
+        // before the metadata, writing a stream changes the modification time of the file
+        if self.alternate_streams {
+            for stream in file.alternate_streams() {
+                write_alternate_stream(&path, &stream)?;
+            }
+        }
+
+        if !self.skip_unknown_streams {
+            let template = self
+                .sidecar_template
+                .as_deref()
+                .unwrap_or(DEFAULT_SIDECAR_TEMPLATE);
+            write_unknown_streams(&path, file, template)?;
+        }
+
+        if !self.skip_metadata {
+            apply_metadata(out, &path, file)?;
+        }
+
+        Ok(path)
+    }
+}
+
+// Fills buf as far as the reader has data, so the sink gets full chunks
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut len = 0;
+
+    while len < buf.len() {
+        match reader.read(&mut buf[len..])? {
+            0 => break,
+            read => len += read,
+        }
+    }
+
+    Ok(len)
+}
+
+fn audit_extraction(file: &FileEntry, result: &Result<PathBuf>) {
+    if !audit::enabled() {
+        return;
+    }
+
+    let hashed = result
+        .as_ref()
+        .map_err(|err| format_err!("{}", err))
+        .and_then(|path| Ok((path, hash_file(path)?)));
+
+    match hashed {
+        Ok((path, (bytes, sha256))) => audit::record(AuditEvent::EXTRACTED {
+            entry: &file.full_path,
+            target: path,
+            bytes,
+            sha256,
+        }),
+        Err(err) => audit::record(AuditEvent::ERROR {
+            operation: "extract",
+            message: format!("{:?}: {}", file.full_path, err),
+        }),
+    }
+}
+
+// Of the restored file as it is on disk
+fn hash_file(path: &Path) -> Result<(u64, [u8; 32])> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    let mut bytes = 0;
+
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+        bytes += len as u64;
+    }
+
+    Ok((bytes, hasher.finalize().into()))
+}
+
+fn write_alternate_stream(path: &Path, stream: &AlternateStream) -> Result<()> {
+    if stream.name.is_empty() || stream.name.contains(['/', '\\', ':']) || stream.name == ".." {
+        return Err(format_err!(
+            "refusing to extract alternate stream with unsafe name {:?} of {:?}",
+            stream.name,
+            path
+        ));
+    }
+
+    let mut name = path.as_os_str().to_owned();
+    if cfg!(windows) {
+        name.push(":");
+        name.push(&stream.name);
+    } else {
+        name.push(".");
+        name.push(&stream.name);
+        name.push(".ads");
+    }
+
+    let name = PathBuf::from(name);
+    remove_symlink(&name)?;
+    let mut out = File::create(name)?;
+    out.write_all(stream.data)?;
+
+    Ok(())
+}
+
+// A stream that is split over multiple DBLKs continues in the next one with the same id, so all
+// streams with the same id end up in one sidecar
+fn write_unknown_streams(path: &Path, file: &FileEntry, template: &str) -> Result<()> {
+    let mut ids: Vec<&str> = Vec::new();
+    for stream in &file.streams {
+        if let StreamKind::UNKNOWN(_) = stream.kind() {
+            if !ids.contains(&stream.id()) {
+                ids.push(stream.id());
+            }
+        }
+    }
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    for id in ids {
+        let sidecar = template.replace("{name}", &name).replace("{id}", id);
+        if sidecar.is_empty() || sidecar.contains(['/', '\\', ':']) || sidecar == ".." {
+            return Err(format_err!(
+                "refusing to write {} stream of {:?} to unsafe name {:?}",
+                id,
+                path,
+                sidecar
+            ));
+        }
+
+        let sidecar = path.with_file_name(sidecar);
+        remove_symlink(&sidecar)?;
+        let mut out = File::create(sidecar)?;
+        for stream in file.streams.iter().filter(|stream| stream.id() == id) {
+            out.write_all(stream.data)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn safe_path(path: &Path, target_dir: &Path) -> bool {
+    path.strip_prefix(target_dir).is_ok_and(|path| {
+        path.components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    })
+}
+
+// The first symlink on the way from target_dir to path, path included
+fn symlink_below(path: &Path, target_dir: &Path) -> Option<PathBuf> {
+    let mut current = target_dir.to_path_buf();
+
+    for component in path.strip_prefix(target_dir).ok()?.components() {
+        current.push(component);
+        if std::fs::symlink_metadata(&current)
+            .is_ok_and(|metadata| metadata.file_type().is_symlink())
+        {
+            return Some(current);
+        }
+    }
+
+    None
+}
+
+fn remove_symlink(path: &Path) -> Result<()> {
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+fn remove_existing(path: &Path) -> Result<()> {
+    if std::fs::symlink_metadata(path).is_ok() {
+        let mut permissions = std::fs::symlink_metadata(path)?.permissions();
+        if permissions.readonly() {
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            std::fs::set_permissions(path, permissions)?;
+        }
+
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+fn system_time(date: &DateTime) -> Option<SystemTime> {
+    let timestamp = date.unix_timestamp()?;
+
+    if timestamp >= 0 {
+        SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(timestamp as u64))
+    } else {
+        SystemTime::UNIX_EPOCH.checked_sub(Duration::from_secs(timestamp.unsigned_abs()))
+    }
+}
+
+fn unsupported_compression(
+    file: &FileEntry,
+    set_algorithm: CompressionAlgorithm,
+) -> Option<CompressionAlgorithm> {
+    file.streams
+        .iter()
+        .filter(|stream| stream.id() == "STAN" && stream.is_compressed())
+        .map(|stream| match stream.compression_algorithm() {
+            CompressionAlgorithm::NONE => set_algorithm,
+            algorithm => algorithm,
+        })
+        .find(|algorithm| !algorithm.is_supported())
+}
+
+fn unsupported_encryption(file: &FileEntry) -> Option<EncryptionAlgorithm> {
+    file.streams
+        .iter()
+        .filter(|stream| stream.id() == "STAN" && stream.is_encrypted())
+        .map(|stream| stream.encryption_algorithm())
+        .find(|algorithm| !algorithm.is_supported())
+}
+
+fn is_link(file: &FileEntry) -> bool {
+    matches!(
+        file.reparse_point(),
+        Some(ReparsePoint {
+            tag: ReparsePoint::TAG_SYMLINK | ReparsePoint::TAG_MOUNT_POINT,
+            ..
+        })
+    )
+}
+
+fn nt_attrs(file: &FileEntry) -> NtFileAttrs {
+    match &file.os_specific {
+        OsSpecificData::NT_FILE { attrs, .. } => *attrs,
+        _ => NtFileAttrs::empty(),
+    }
+}
+
+// Hidden and system can only be set on Windows, when the file is created
+#[cfg(windows)]
+fn windows_attributes(file: &FileEntry) -> u32 {
+    let mut attrs = nt_attrs(file) & (NtFileAttrs::HIDDEN | NtFileAttrs::SYSTEM);
+
+    if file.attributes.contains(FileAttrs::HIDDEN) {
+        attrs |= NtFileAttrs::HIDDEN;
+    }
+    if file.attributes.contains(FileAttrs::SYSTEM) {
+        attrs |= NtFileAttrs::SYSTEM;
+    }
+
+    attrs.bits()
+}
+
+fn apply_metadata(out: File, path: &Path, file: &FileEntry) -> Result<()> {
+    let mut times = FileTimes::new();
+
+    if let Some(modified) = system_time(&file.dates.last_modification) {
+        times = times.set_modified(modified);
+    }
+    if let Some(accessed) = system_time(&file.dates.last_access) {
+        times = times.set_accessed(accessed);
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::FileTimesExt;
+
+        if let Some(created) = system_time(&file.dates.creation) {
+            times = times.set_created(created);
+        }
+    }
+
+    out.set_times(times)?;
+    drop(out);
+
+    // last, as it would prevent setting the times
+    if file.attributes.contains(FileAttrs::READ_ONLY)
+        || nt_attrs(file).contains(NtFileAttrs::READONLY)
+    {
+        let mut permissions = std::fs::metadata(path)?.permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(path, permissions)?;
+    }
+
+    Ok(())
+}
+
+fn collect_files<'d, 'a, F: FnMut(&FileEntry) -> bool>(
+    directory: &'d Directory<'a>,
+    selection: &mut F,
+    files: &mut Vec<&'d FileEntry<'a>>,
+) {
+    files.extend(directory.files.iter().filter(|file| selection(file)));
+
+    for directory in &directory.directories {
+        collect_files(directory, selection, files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{self, FileBuilder, MediumBuilder, SetBuilder, StreamBuilder};
+
+    // An empty directory of its own for every test
+    fn target_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mtf-restore-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // A file that is a symbolic link to target, as the REPARSE_DATA_BUFFER of an NTRP stream
+    // with target as the print name
+    fn symlink(name: &str, target: &str) -> FileBuilder {
+        let target: Vec<u8> = target.encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+        let mut ntrp = Vec::new();
+        ntrp.extend_from_slice(&ReparsePoint::TAG_SYMLINK.to_le_bytes());
+        ntrp.extend_from_slice(&(12 + target.len() as u16).to_le_bytes()); // data length
+        ntrp.extend_from_slice(&[0; 2]); // reserved
+        ntrp.extend_from_slice(&[0; 4]); // substitute name offset and length
+        ntrp.extend_from_slice(&0u16.to_le_bytes()); // print name offset
+        ntrp.extend_from_slice(&(target.len() as u16).to_le_bytes());
+        ntrp.extend_from_slice(&[0; 4]); // flags
+        ntrp.extend_from_slice(&target);
+
+        FileBuilder::new(name).stream(StreamBuilder::new(b"NTRP", &ntrp))
+    }
+
+    #[test]
+    fn protected_set_needs_opt_in() {
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .password("secret")
+                    .directory("docs")
+                    .file(FileBuilder::new("a.txt").data(b"hello")),
+            )
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("protected-set", &medium);
+        let target = target_dir("protected-set");
+
+        assert!(parser.backup_sets().unwrap()[0].password_protected);
+        assert!(parser.sets().unwrap()[0].is_password_protected());
+
+        let err = Restorer::new()
+            .extract(&mut parser, |_| true, &target)
+            .unwrap_err();
+        assert!(err.to_string().contains("password protected"));
+        assert!(!target.join("docs").exists());
+
+        let extracted = Restorer::new()
+            .allow_protected()
+            .extract(&mut parser, |_| true, &target)
+            .unwrap();
+        assert_eq!(extracted, [target.join("docs").join("a.txt")]);
+        assert_eq!(std::fs::read(&extracted[0]).unwrap(), b"hello");
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_do_not_redirect_later_writes() {
+        let target = target_dir("links-redirect");
+        let outside = target_dir("links-redirect-outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        let outside_name = outside.to_str().unwrap();
+
+        // the links of the first set point out of the target, the second set writes through them
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .file(symlink("docs", outside_name))
+                    .file(symlink("b.txt", &format!("{}/b.txt", outside_name))),
+            )
+            .set(SetBuilder::new(2).file(FileBuilder::new("b.txt").data(b"inside")))
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("links-redirect", &medium);
+        Restorer::new()
+            .reparse_points()
+            .extract(&mut parser, |_| true, &target)
+            .unwrap();
+
+        assert!(std::fs::symlink_metadata(target.join("docs"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(!std::fs::symlink_metadata(target.join("b.txt"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(std::fs::read(target.join("b.txt")).unwrap(), b"inside");
+        assert!(!outside.join("b.txt").exists());
+
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .directory("docs")
+                    .file(FileBuilder::new("a.txt").data(b"escaped")),
+            )
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("links-redirect-parent", &medium);
+        let err = Restorer::new()
+            .extract(&mut parser, |_| true, &target)
+            .unwrap_err();
+        assert!(err.to_string().contains("through the symbolic link"));
+        assert!(!outside.join("a.txt").exists());
+
+        std::fs::remove_dir_all(&target).unwrap();
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn symlink_policies() {
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .file(symlink("link", "C:\\Windows"))
+                    .file(FileBuilder::new("file").data(b"data")),
+            )
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("symlink-policies", &medium);
+
+        let target = target_dir("symlink-skip");
+        let extracted = Restorer::new()
+            .symlink_policy(SymlinkPolicy::SKIP)
+            .extract(&mut parser, |_| true, &target)
+            .unwrap();
+        assert_eq!(extracted, [target.join("file")]);
+        assert!(!target.join("link").exists());
+        std::fs::remove_dir_all(&target).unwrap();
+
+        let target = target_dir("symlink-placeholder");
+        Restorer::new()
+            .reparse_points()
+            .symlink_policy(SymlinkPolicy::PLACEHOLDER)
+            .extract(&mut parser, |_| true, &target)
+            .unwrap();
+        let placeholder = target.join("link");
+        assert!(!std::fs::symlink_metadata(&placeholder)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(std::fs::read(&placeholder).unwrap(), b"C:\\Windows");
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn extract_with_hands_every_file_to_the_callback() {
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .directory("docs")
+                    .file(FileBuilder::new("a.txt").data(b"hello"))
+                    .file(FileBuilder::new("b.txt").data(b"world")),
+            )
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("extract-with", &medium);
+
+        let mut files = Vec::new();
+        let count = Restorer::new()
+            .extract_with(
+                &mut parser,
+                |_| true,
+                |entry, reader| {
+                    let mut data = Vec::new();
+                    // only the first file is read to the end
+                    if files.is_empty() {
+                        reader.read_to_end(&mut data)?;
+                    } else {
+                        reader.take(2).read_to_end(&mut data)?;
+                    }
+                    files.push((entry.full_path.clone(), entry.size, data));
+                    Ok(())
+                },
+            )
+            .unwrap();
+
+        assert_eq!(count, 2);
+        assert_eq!(
+            files,
+            [
+                (Path::new("docs").join("a.txt"), 5, b"hello".to_vec()),
+                (Path::new("docs").join("b.txt"), 5, b"wo".to_vec()),
+            ]
+        );
+
+        // errors of the callback end the extraction
+        let err = Restorer::new()
+            .extract_with(&mut parser, |_| true, |_, _| Err(format_err!("stop")))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "stop");
+    }
+
+    #[test]
+    fn extract_selected_files() {
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .directory("docs")
+                    .file(FileBuilder::new("a.txt").data(b"hello"))
+                    .file(FileBuilder::new("b.txt").data(b"world!")),
+            )
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("extract-selected", &medium);
+        let target = target_dir("extract-selected");
+        let only_b = |entry: &FileEntry| entry.full_path.ends_with("b.txt");
+
+        assert_eq!(
+            Restorer::new()
+                .required_space(&mut parser, |_| true)
+                .unwrap(),
+            11
+        );
+        assert_eq!(
+            Restorer::new().required_space(&mut parser, only_b).unwrap(),
+            6
+        );
+
+        let extracted = Restorer::new()
+            .extract(&mut parser, only_b, &target)
+            .unwrap();
+        assert_eq!(extracted, [target.join("docs").join("b.txt")]);
+        assert_eq!(std::fs::read(&extracted[0]).unwrap(), b"world!");
+        assert!(!target.join("docs").join("a.txt").exists());
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(16);
+        let mut sink = sender;
+        let written = Restorer::new()
+            .extract_to(&mut parser, |_| true, &mut sink)
+            .unwrap();
+        drop(sink);
+        assert_eq!(written, 11);
+
+        let events: Vec<_> = receiver
+            .iter()
+            .map(|event| match event {
+                ExtractEvent::BEGIN { path, size } => format!("begin {:?} {}", path, size),
+                ExtractEvent::DATA(data) => String::from_utf8(data).unwrap(),
+                ExtractEvent::END => "end".to_string(),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                format!("begin {:?} 5", Path::new("docs").join("a.txt")),
+                "hello".to_string(),
+                "end".to_string(),
+                format!("begin {:?} 6", Path::new("docs").join("b.txt")),
+                "world!".to_string(),
+                "end".to_string(),
+            ]
+        );
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn unsafe_names_are_refused() {
+        let outside = target_dir("unsafe-names-outside");
+        std::fs::create_dir_all(&outside).unwrap();
+        let absolute = outside.join("escaped");
+
+        for (name, file_name) in [
+            ("unsafe-parent", ".."),
+            ("unsafe-absolute", absolute.to_str().unwrap()),
+        ] {
+            let medium = MediumBuilder::new()
+                .set(
+                    SetBuilder::new(1)
+                        .directory("docs")
+                        .file(FileBuilder::new(file_name).data(b"escaped")),
+                )
+                .build()
+                .unwrap();
+            let mut parser = fixture::open(name, &medium);
+            let target = target_dir(name);
+
+            let err = Restorer::new()
+                .extract(&mut parser, |_| true, &target)
+                .unwrap_err();
+            assert!(err.to_string().contains("unsafe path"), "{}", err);
+            assert!(!target.join("docs").exists());
+
+            let _ = std::fs::remove_dir_all(&target);
+        }
+
+        assert!(!absolute.exists());
+        std::fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn truncated_streams_are_not_extracted() {
+        // the medium ends in the middle of the data of b.txt
+        let mut medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .file(FileBuilder::new("a.txt").data(b"hello"))
+                    .file(
+                        FileBuilder::new("b.txt")
+                            .stream(StreamBuilder::new(b"STAN", b"wor").length(6)),
+                    ),
+            )
+            .build()
+            .unwrap();
+        let end = medium.windows(3).rposition(|data| data == b"wor").unwrap() + 3;
+        medium.truncate(end);
+        let mut parser = fixture::open("truncated-stream", &medium);
+        let target = target_dir("truncated-stream");
+
+        let err = Restorer::new()
+            .extract(&mut parser, |_| true, &target)
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("ends after 3 of its 6 bytes"),
+            "{}",
+            err
+        );
+        assert_eq!(std::fs::read(target.join("a.txt")).unwrap(), b"hello");
+        assert!(!target.join("b.txt").exists());
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn path_template_separates_sets() {
+        let set = |number: u16, name: &str, data: &[u8]| {
+            SetBuilder::new(number)
+                .name(name)
+                .directory("docs")
+                .file(FileBuilder::new("a.txt").data(data))
+        };
+        let medium = MediumBuilder::new()
+            .set(set(1, "Monday", b"first"))
+            .set(set(2, "Tuesday/full", b"second"))
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("path-template", &medium);
+        let target = target_dir("path-template");
+
+        let extracted = Restorer::new()
+            .path_template("{set_number}_{set_name}/{volume}/{path}")
+            .extract(&mut parser, |_| true, &target)
+            .unwrap();
+        // the / of the set name doesn't make another directory
+        assert_eq!(
+            extracted,
+            [
+                target.join("1_Monday/C_/docs/a.txt"),
+                target.join("2_Tuesday_full/C_/docs/a.txt"),
+            ]
+        );
+        assert_eq!(std::fs::read(&extracted[0]).unwrap(), b"first");
+        assert_eq!(std::fs::read(&extracted[1]).unwrap(), b"second");
+
+        // by default the second set replaces the file of the first
+        let extracted = Restorer::new()
+            .extract(&mut parser, |_| true, &target)
+            .unwrap();
+        assert_eq!(extracted[0], extracted[1]);
+        assert_eq!(std::fs::read(&extracted[1]).unwrap(), b"second");
+
+        let err = Restorer::new()
+            .path_template("../{path}")
+            .extract(&mut parser, |_| true, &target)
+            .unwrap_err();
+        assert!(err.to_string().contains("unsafe path"));
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    #[test]
+    fn chain_restores_the_latest_versions() {
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .directory("docs")
+                    .file(FileBuilder::new("a.txt").data(b"full"))
+                    .file(FileBuilder::new("b.txt").data(b"unchanged")),
+            )
+            .set(
+                SetBuilder::new(2)
+                    .directory("Docs")
+                    .file(FileBuilder::new("A.txt").data(b"incremental")),
+            )
+            // not part of the chain
+            .set(
+                SetBuilder::new(3)
+                    .directory("docs")
+                    .file(FileBuilder::new("a.txt").data(b"other")),
+            )
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("set-chain", &medium);
+        let target = target_dir("set-chain");
+
+        let extracted = Restorer::new()
+            .extract_chain(&mut parser, &[1, 2], |_| true, &target)
+            .unwrap();
+        assert_eq!(
+            extracted,
+            [target.join("Docs/A.txt"), target.join("docs/b.txt")]
+        );
+        assert_eq!(std::fs::read(&extracted[0]).unwrap(), b"incremental");
+        assert_eq!(std::fs::read(&extracted[1]).unwrap(), b"unchanged");
+
+        // the chain order wins over the media order
+        let extracted = Restorer::new()
+            .extract_chain(&mut parser, &[3, 1], |_| true, &target)
+            .unwrap();
+        assert_eq!(extracted.len(), 2);
+        assert_eq!(std::fs::read(&extracted[0]).unwrap(), b"full");
+
+        assert!(Restorer::new()
+            .extract_chain(&mut parser, &[1, 4], |_| true, &target)
+            .is_err());
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+
+    // XOR with a constant under an algorithm id no other test uses
+    struct XorDecryptor;
+
+    const XOR_ALGORITHM: u16 = 0x7e57;
+
+    impl crate::crypto::Decryptor for XorDecryptor {
+        fn supports(&self, algorithm: EncryptionAlgorithm) -> bool {
+            algorithm == EncryptionAlgorithm::VENDOR(XOR_ALGORITHM)
+        }
+
+        fn decrypt(&self, _algorithm: EncryptionAlgorithm, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(xor(data))
+        }
+    }
+
+    fn xor(data: &[u8]) -> Vec<u8> {
+        data.iter().map(|byte| byte ^ 0x5a).collect()
+    }
+
+    #[test]
+    fn encrypted_streams_are_decrypted_before_decompressing() {
+        crate::crypto::register_decryptor(std::sync::Arc::new(XorDecryptor));
+
+        // the frame headers are encrypted as well, they are only found after decrypting
+        let frames = [fixture::frame(b"hello ", 6), fixture::frame(b"world", 5)].concat();
+        let compressed = StreamBuilder::new(b"STAN", &xor(&frames))
+            .compressed(0x4c5a)
+            .encrypted(XOR_ALGORITHM);
+        let encrypted = StreamBuilder::new(b"STAN", &xor(b"plain")).encrypted(XOR_ALGORITHM);
+
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .file(FileBuilder::new("compressed.txt").stream(compressed))
+                    .file(FileBuilder::new("encrypted.txt").stream(encrypted)),
+            )
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("decrypt-then-decompress", &medium);
+
+        let mut data = Vec::new();
+        Restorer::new()
+            .extract_with(
+                &mut parser,
+                |_| true,
+                |_, reader| {
+                    let mut file = Vec::new();
+                    reader.read_to_end(&mut file)?;
+                    data.push(file);
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(data, [&b"hello world"[..], b"plain"]);
+    }
+
+    #[test]
+    fn unsupported_encryption_is_rejected_up_front() {
+        let encrypted = StreamBuilder::new(b"STAN", b"secret").encrypted(0x7e58);
+        let medium = MediumBuilder::new()
+            .set(
+                SetBuilder::new(1)
+                    .file(FileBuilder::new("a.txt").data(b"plain"))
+                    .file(FileBuilder::new("b.txt").stream(encrypted)),
+            )
+            .build()
+            .unwrap();
+        let mut parser = fixture::open("unsupported-encryption", &medium);
+        let target = target_dir("unsupported-encryption");
+
+        let err = Restorer::new()
+            .extract(&mut parser, |_| true, &target)
+            .unwrap_err();
+        assert!(err.downcast_ref::<UnsupportedFeature>().is_some());
+        assert!(!target.join("a.txt").exists());
+
+        let err = Restorer::new()
+            .required_space(&mut parser, |_| true)
+            .unwrap_err();
+        assert!(err.downcast_ref::<UnsupportedFeature>().is_some());
+
+        // fine without the encrypted file
+        let extracted = Restorer::new()
+            .extract(
+                &mut parser,
+                |file| file.full_path.ends_with("a.txt"),
+                &target,
+            )
+            .unwrap();
+        assert_eq!(extracted, [target.join("a.txt")]);
+
+        std::fs::remove_dir_all(&target).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+use crate::{DBLKIterator, DBLKWithStreams, IterationSummary};
+
+// Looks at every DBLK of a scan, see ScanPlanner
+pub trait ScanConsumer<'a> {
+    fn block(&mut self, dblk: &DBLKWithStreams<'a>);
+
+    // called once after the last block
+    fn finish(&mut self, _summary: &IterationSummary) {}
+}
+
+impl<'a, F: FnMut(&DBLKWithStreams<'a>)> ScanConsumer<'a> for F {
+    fn block(&mut self, dblk: &DBLKWithStreams<'a>) {
+        self(dblk)
+    }
+}
+
+// Runs several consumers (catalog builders, hashers, verifiers, ...) in a single pass over the
+// medium, instead of one full pass each. They see the blocks in the order they were added
+#[derive(Default)]
+pub struct ScanPlanner<'c, 'a> {
+    consumers: Vec<&'c mut dyn ScanConsumer<'a>>,
+}
+
+impl<'c, 'a> ScanPlanner<'c, 'a> {
+    pub fn new() -> ScanPlanner<'c, 'a> {
+        ScanPlanner {
+            consumers: Vec::new(),
+        }
+    }
+
+    pub fn consumer(mut self, consumer: &'c mut dyn ScanConsumer<'a>) -> ScanPlanner<'c, 'a> {
+        self.consumers.push(consumer);
+        self
+    }
+
+    pub fn run(mut self, mut dblks: DBLKIterator<'a>) -> IterationSummary {
+        for dblk in dblks.by_ref() {
+            for consumer in &mut self.consumers {
+                consumer.block(&dblk);
+            }
+        }
+
+        let summary = dblks.finish();
+        for consumer in &mut self.consumers {
+            consumer.finish(&summary);
+        }
+
+        summary
+    }
+}
+
+// Checks the CSUM streams of every block, blocks and streams are identified by
+// (offset of the DBLK, index of the checksummed stream)
+#[derive(Debug, Clone, Default)]
+pub struct ChecksumVerifier {
+    pub verified: u64,
+    pub mismatches: Vec<(u64, usize)>,
+    // truncated streams or malformed CSUM streams
+    pub unverifiable: Vec<(u64, usize)>,
+}
+
+impl<'a> ScanConsumer<'a> for ChecksumVerifier {
+    fn block(&mut self, dblk: &DBLKWithStreams<'a>) {
+        for (idx, result) in dblk.verify_checksums() {
+            match result {
+                Ok(true) => self.verified += 1,
+                Ok(false) => self.mismatches.push((dblk.offset, idx)),
+                Err(_) => self.unverifiable.push((dblk.offset, idx)),
+            }
+        }
+    }
+}
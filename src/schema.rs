@@ -0,0 +1,410 @@
+use crate::mdf::allocation_unit_id;
+use crate::Result;
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
+use failure::format_err;
+use mdf::{PagePointer, PageProvider};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+// Tables, columns and indexes of a database, read from the hidden system base tables behind
+// sys.objects, sys.columns, sys.indexes and sys.index_columns. Only user tables are listed
+#[derive(Debug, Clone)]
+pub struct Schema {
+    pub tables: Vec<Table>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub object_id: i32,
+    pub schema: String,
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub indexes: Vec<Index>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub column_id: i32,
+    pub name: String,
+    pub system_type_id: u8,
+    pub max_length: i16,
+    pub precision: u8,
+    pub scale: u8,
+    pub nullable: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    HEAP,
+    CLUSTERED,
+    NONCLUSTERED,
+    OTHER(u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub index_id: i32,
+    // heaps have no name
+    pub name: Option<String>,
+    pub kind: IndexKind,
+    pub unique: bool,
+    pub primary_key: bool,
+    pub columns: Vec<IndexColumn>,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexColumn {
+    pub column_id: i32,
+    pub descending: bool,
+    pub included: bool,
+}
+
+// object ids of the system base tables, their clustered index (index id 1) holds the rows
+const SYSSCHOBJS: u32 = 34;
+const SYSCOLPARS: u32 = 41;
+const SYSIDXSTATS: u32 = 54;
+const SYSISCOLS: u32 = 55;
+const SYSCLSOBJS: u32 = 64;
+
+// sysclsobjs class of schemas
+const SCHEMA_CLASS: u8 = 50;
+
+const DATA_PAGE: u8 = 1;
+const PAGE_HEADER_SIZE: usize = 96;
+
+// FixedVar record
+const PRIMARY_RECORD: u8 = 0;
+const HAS_NULL_BITMAP: u8 = 0x10;
+const HAS_VARIABLE_COLUMNS: u8 = 0x20;
+
+impl Column {
+    // The type as written in a CREATE TABLE, lengths of the n types are in bytes on disk
+    pub fn type_name(&self) -> String {
+        let length = |bytes: i16, per_char: i16| match bytes {
+            -1 => "max".to_string(),
+            bytes => (bytes / per_char).to_string(),
+        };
+
+        match self.system_type_id {
+            34 => "image".into(),
+            35 => "text".into(),
+            36 => "uniqueidentifier".into(),
+            40 => "date".into(),
+            41 => format!("time({})", self.scale),
+            42 => format!("datetime2({})", self.scale),
+            43 => format!("datetimeoffset({})", self.scale),
+            48 => "tinyint".into(),
+            52 => "smallint".into(),
+            56 => "int".into(),
+            58 => "smalldatetime".into(),
+            59 => "real".into(),
+            60 => "money".into(),
+            61 => "datetime".into(),
+            62 => "float".into(),
+            98 => "sql_variant".into(),
+            99 => "ntext".into(),
+            104 => "bit".into(),
+            106 => format!("decimal({}, {})", self.precision, self.scale),
+            108 => format!("numeric({}, {})", self.precision, self.scale),
+            122 => "smallmoney".into(),
+            127 => "bigint".into(),
+            165 => format!("varbinary({})", length(self.max_length, 1)),
+            167 => format!("varchar({})", length(self.max_length, 1)),
+            173 => format!("binary({})", self.max_length),
+            175 => format!("char({})", self.max_length),
+            189 => "timestamp".into(),
+            231 => format!("nvarchar({})", length(self.max_length, 2)),
+            239 => format!("nchar({})", self.max_length / 2),
+            241 => "xml".into(),
+            other => format!("type {}", other),
+        }
+    }
+}
+
+impl IndexKind {
+    fn parse(kind: u8) -> IndexKind {
+        match kind {
+            0 => IndexKind::HEAP,
+            1 => IndexKind::CLUSTERED,
+            2 => IndexKind::NONCLUSTERED,
+            other => IndexKind::OTHER(other),
+        }
+    }
+}
+
+impl Schema {
+    // The system base tables have fixed allocation unit ids, so their rows are found by the page
+    // headers alone, without following the boot page and sysallocunits
+    pub fn read<P: PageProvider>(provider: &P) -> Result<Schema> {
+        let mut rows: HashMap<u32, Vec<Record>> = HashMap::new();
+
+        for file_id in provider.file_ids() {
+            for page_id in 0..provider.num_pages(file_id) {
+                let page = match provider.get(PagePointer { file_id, page_id }) {
+                    Some(page) => page,
+                    None => continue,
+                };
+                let data = page.data;
+                if data.len() < PAGE_HEADER_SIZE || data[1] != DATA_PAGE {
+                    continue;
+                }
+
+                let au = allocation_unit_id(data);
+                let object_id = (au >> 16) as u32;
+                if au >> 48 != 1
+                    || ![SYSSCHOBJS, SYSCOLPARS, SYSIDXSTATS, SYSISCOLS, SYSCLSOBJS]
+                        .contains(&object_id)
+                {
+                    continue;
+                }
+
+                rows.entry(object_id)
+                    .or_default()
+                    .extend(records(data, (file_id, page_id))?);
+            }
+        }
+
+        let rows_of = |object_id| rows.get(&object_id).map_or(&[][..], Vec::as_slice);
+
+        let mut schemas = HashMap::new();
+        for record in rows_of(SYSCLSOBJS) {
+            // class tinyint, id int, name
+            let mut fixed = record.fixed();
+            if fixed.read_u8()? == SCHEMA_CLASS {
+                schemas.insert(fixed.read_i32::<LittleEndian>()?, record.name()?);
+            }
+        }
+
+        let mut tables = Vec::new();
+        for record in rows_of(SYSSCHOBJS) {
+            // id int, nsid int, nsclass tinyint, status int, type char(2), ..., name
+            let mut fixed = record.fixed();
+            let object_id = fixed.read_i32::<LittleEndian>()?;
+            let nsid = fixed.read_i32::<LittleEndian>()?;
+            fixed.set_position(13);
+            let mut kind = [0; 2];
+            fixed.read_exact(&mut kind)?;
+
+            if &kind == b"U " {
+                tables.push(Table {
+                    object_id,
+                    schema: schemas
+                        .get(&nsid)
+                        .cloned()
+                        .unwrap_or_else(|| format!("schema {}", nsid)),
+                    name: record.name()?,
+                    columns: Vec::new(),
+                    indexes: Vec::new(),
+                });
+            }
+        }
+
+        let mut by_id: HashMap<i32, usize> = HashMap::new();
+        for (idx, table) in tables.iter().enumerate() {
+            by_id.insert(table.object_id, idx);
+        }
+
+        for record in rows_of(SYSCOLPARS) {
+            // id int, number smallint, colid int, xtype tinyint, utype int, length smallint,
+            // prec tinyint, scale tinyint, collationid int, status int, ..., name
+            let mut fixed = record.fixed();
+            let object_id = fixed.read_i32::<LittleEndian>()?;
+            // parameters of procedures have a number
+            let number = fixed.read_i16::<LittleEndian>()?;
+            let table = match by_id.get(&object_id) {
+                Some(&idx) if number == 0 => &mut tables[idx],
+                _ => continue,
+            };
+
+            let column_id = fixed.read_i32::<LittleEndian>()?;
+            let system_type_id = fixed.read_u8()?;
+            let _user_type_id = fixed.read_i32::<LittleEndian>()?;
+            let max_length = fixed.read_i16::<LittleEndian>()?;
+            let precision = fixed.read_u8()?;
+            let scale = fixed.read_u8()?;
+            let _collation_id = fixed.read_i32::<LittleEndian>()?;
+            let status = fixed.read_i32::<LittleEndian>()?;
+
+            table.columns.push(Column {
+                column_id,
+                name: record.name()?,
+                system_type_id,
+                max_length,
+                precision,
+                scale,
+                nullable: status & 1 == 0,
+            });
+        }
+
+        for record in rows_of(SYSIDXSTATS) {
+            // id int, indid int, status int, intprop int, fillfact tinyint, type tinyint, ..., name
+            let mut fixed = record.fixed();
+            let object_id = fixed.read_i32::<LittleEndian>()?;
+            let table = match by_id.get(&object_id) {
+                Some(&idx) => &mut tables[idx],
+                None => continue,
+            };
+
+            let index_id = fixed.read_i32::<LittleEndian>()?;
+            let status = fixed.read_i32::<LittleEndian>()?;
+            let _intprop = fixed.read_i32::<LittleEndian>()?;
+            let _fill_factor = fixed.read_u8()?;
+            let kind = IndexKind::parse(fixed.read_u8()?);
+
+            table.indexes.push(Index {
+                index_id,
+                name: match kind {
+                    IndexKind::HEAP => None,
+                    _ => Some(record.name()?),
+                },
+                kind,
+                unique: status & 0x8 != 0,
+                primary_key: status & 0x20 != 0,
+                columns: Vec::new(),
+            });
+        }
+
+        let mut index_columns = Vec::new();
+        for record in rows_of(SYSISCOLS) {
+            // idmajor int, idminor int, subid int, status int, intprop int
+            let mut fixed = record.fixed();
+            let object_id = fixed.read_i32::<LittleEndian>()?;
+            let index_id = fixed.read_i32::<LittleEndian>()?;
+            let ordinal = fixed.read_i32::<LittleEndian>()?;
+            let status = fixed.read_i32::<LittleEndian>()?;
+            let column_id = fixed.read_i32::<LittleEndian>()?;
+
+            index_columns.push((
+                (object_id, index_id, ordinal),
+                IndexColumn {
+                    column_id,
+                    descending: status & 0x4 != 0,
+                    included: status & 0x10 != 0,
+                },
+            ));
+        }
+        index_columns.sort_by_key(|(key, _)| *key);
+
+        for ((object_id, index_id, _), column) in index_columns {
+            if let Some(&idx) = by_id.get(&object_id) {
+                if let Some(index) = tables[idx]
+                    .indexes
+                    .iter_mut()
+                    .find(|index| index.index_id == index_id)
+                {
+                    index.columns.push(column);
+                }
+            }
+        }
+
+        for table in &mut tables {
+            table.columns.sort_by_key(|column| column.column_id);
+            table.indexes.sort_by_key(|index| index.index_id);
+        }
+        tables.sort_by(|a, b| (&a.schema, &a.name).cmp(&(&b.schema, &b.name)));
+
+        Ok(Schema { tables })
+    }
+}
+
+impl Table {
+    pub fn column(&self, column_id: i32) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|column| column.column_id == column_id)
+    }
+}
+
+// A primary FixedVar record: the fixed length columns and the variable length ones, the name is
+// the first variable length column of all the system base tables used here
+struct Record<'a> {
+    fixed: &'a [u8],
+    variable: Vec<&'a [u8]>,
+}
+
+impl<'a> Record<'a> {
+    fn fixed(&self) -> Cursor<&'a [u8]> {
+        Cursor::new(self.fixed)
+    }
+
+    fn name(&self) -> Result<String> {
+        let name = self
+            .variable
+            .first()
+            .ok_or_else(|| format_err!("record without a name"))?;
+        let name: Vec<u16> = name.chunks_exact(2).map(LittleEndian::read_u16).collect();
+        Ok(String::from_utf16_lossy(&name))
+    }
+}
+
+// The primary records of a data page, by the slot array at the end of the page. Ghost, forwarded
+// and other records are skipped
+fn records(page: &[u8], (file_id, page_id): (u16, u32)) -> Result<Vec<Record<'_>>> {
+    let corrupt = |what| format_err!("page {}:{}: {}", file_id, page_id, what);
+
+    let slot_count = LittleEndian::read_u16(&page[22..]) as usize;
+    if PAGE_HEADER_SIZE + slot_count * 2 > page.len() {
+        return Err(corrupt("slot array overlaps the header"));
+    }
+
+    let mut records = Vec::new();
+    for slot in 0..slot_count {
+        let offset = LittleEndian::read_u16(&page[page.len() - 2 * (slot + 1)..]) as usize;
+        // empty slot
+        if offset == 0 {
+            continue;
+        }
+        let record = page
+            .get(offset..)
+            .filter(|record| record.len() >= 4)
+            .ok_or_else(|| corrupt("record outside of the page"))?;
+
+        let status = record[0];
+        if (status >> 1) & 7 != PRIMARY_RECORD {
+            continue;
+        }
+
+        let fixed_end = LittleEndian::read_u16(&record[2..]) as usize;
+        let fixed = record
+            .get(4..fixed_end)
+            .ok_or_else(|| corrupt("fixed length columns outside of the page"))?;
+
+        let mut pos = fixed_end;
+        if status & HAS_NULL_BITMAP != 0 {
+            let columns = record
+                .get(pos..pos + 2)
+                .map(LittleEndian::read_u16)
+                .ok_or_else(|| corrupt("truncated null bitmap"))?
+                as usize;
+            pos += 2 + columns.div_ceil(8);
+        }
+
+        let mut variable = Vec::new();
+        if status & HAS_VARIABLE_COLUMNS != 0 {
+            let count = record
+                .get(pos..pos + 2)
+                .map(LittleEndian::read_u16)
+                .ok_or_else(|| corrupt("truncated variable column count"))?
+                as usize;
+            let ends = record
+                .get(pos + 2..pos + 2 + 2 * count)
+                .ok_or_else(|| corrupt("truncated variable column offsets"))?;
+
+            let mut start = pos + 2 + 2 * count;
+            for end in ends.chunks_exact(2) {
+                // the high bit marks columns stored off row
+                let end = (LittleEndian::read_u16(end) & 0x7fff) as usize;
+                variable.push(
+                    record
+                        .get(start..end)
+                        .ok_or_else(|| corrupt("variable length column outside of the page"))?,
+                );
+                start = end;
+            }
+        }
+
+        records.push(Record { fixed, variable });
+    }
+
+    Ok(records)
+}
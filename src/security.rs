@@ -0,0 +1,613 @@
+use crate::Result;
+use bitflags::*;
+use byteorder::{ByteOrder, LittleEndian};
+use failure::format_err;
+use std::fmt;
+
+bitflags! {
+    pub struct SecurityDescriptorControl: u16 {
+        const OWNER_DEFAULTED = 0x1;
+        const GROUP_DEFAULTED = 0x2;
+        const DACL_PRESENT = 0x4;
+        const DACL_DEFAULTED = 0x8;
+        const SACL_PRESENT = 0x10;
+        const SACL_DEFAULTED = 0x20;
+        const DACL_AUTO_INHERIT_REQ = 0x100;
+        const SACL_AUTO_INHERIT_REQ = 0x200;
+        const DACL_AUTO_INHERITED = 0x400;
+        const SACL_AUTO_INHERITED = 0x800;
+        const DACL_PROTECTED = 0x1000;
+        const SACL_PROTECTED = 0x2000;
+        const RM_CONTROL_VALID = 0x4000;
+        const SELF_RELATIVE = 0x8000;
+    }
+}
+
+bitflags! {
+    pub struct AceFlags: u8 {
+        const OBJECT_INHERIT = 0x1;
+        const CONTAINER_INHERIT = 0x2;
+        const NO_PROPAGATE_INHERIT = 0x4;
+        const INHERIT_ONLY = 0x8;
+        const INHERITED = 0x10;
+        const SUCCESSFUL_ACCESS = 0x40;
+        const FAILED_ACCESS = 0x80;
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16> {
+    data.get(offset..offset + 2)
+        .map(LittleEndian::read_u16)
+        .ok_or_else(|| format_err!("security descriptor truncated at {}", offset))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(LittleEndian::read_u32)
+        .ok_or_else(|| format_err!("security descriptor truncated at {}", offset))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sid {
+    pub revision: u8,
+    pub authority: u64,
+    pub sub_authorities: Vec<u32>,
+}
+
+// SDDL abbreviations of well known SIDs
+const SID_ALIASES: [(&str, &str); 32] = [
+    ("S-1-1-0", "WD"),
+    ("S-1-3-0", "CO"),
+    ("S-1-3-1", "CG"),
+    ("S-1-3-4", "OW"),
+    ("S-1-5-2", "NU"),
+    ("S-1-5-4", "IU"),
+    ("S-1-5-6", "SU"),
+    ("S-1-5-7", "AN"),
+    ("S-1-5-9", "ED"),
+    ("S-1-5-10", "PS"),
+    ("S-1-5-11", "AU"),
+    ("S-1-5-12", "RC"),
+    ("S-1-5-18", "SY"),
+    ("S-1-5-19", "LS"),
+    ("S-1-5-20", "NS"),
+    ("S-1-5-32-544", "BA"),
+    ("S-1-5-32-545", "BU"),
+    ("S-1-5-32-546", "BG"),
+    ("S-1-5-32-547", "PU"),
+    ("S-1-5-32-548", "AO"),
+    ("S-1-5-32-549", "SO"),
+    ("S-1-5-32-550", "PO"),
+    ("S-1-5-32-551", "BO"),
+    ("S-1-5-32-552", "RE"),
+    ("S-1-5-32-554", "RU"),
+    ("S-1-5-32-555", "RD"),
+    ("S-1-5-32-556", "NO"),
+    ("S-1-5-32-558", "MU"),
+    ("S-1-16-4096", "LW"),
+    ("S-1-16-8192", "ME"),
+    ("S-1-16-12288", "HI"),
+    ("S-1-16-16384", "SI"),
+];
+
+impl Sid {
+    fn parse(data: &[u8], offset: usize) -> Result<Sid> {
+        let header = data
+            .get(offset..offset + 8)
+            .ok_or_else(|| format_err!("security descriptor truncated at sid at {}", offset))?;
+
+        let revision = header[0];
+        let count = header[1] as usize;
+        // the only big endian value in here
+        let authority = header[2..8]
+            .iter()
+            .fold(0u64, |authority, &b| (authority << 8) | b as u64);
+
+        let sub_authorities = (0..count)
+            .map(|i| read_u32(data, offset + 8 + 4 * i))
+            .collect::<Result<_>>()?;
+
+        Ok(Sid {
+            revision,
+            authority,
+            sub_authorities,
+        })
+    }
+
+    pub fn to_sddl(&self) -> String {
+        let sid = self.to_string();
+
+        SID_ALIASES
+            .iter()
+            .find(|(full, _)| *full == sid)
+            .map_or(sid, |(_, alias)| alias.to_string())
+    }
+}
+
+impl fmt::Display for Sid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S-{}-", self.revision)?;
+
+        if self.authority >> 32 == 0 {
+            write!(f, "{}", self.authority)?;
+        } else {
+            write!(f, "0x{:012X}", self.authority)?;
+        }
+
+        for sub_authority in &self.sub_authorities {
+            write!(f, "-{}", sub_authority)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AceType {
+    ACCESS_ALLOWED,
+    ACCESS_DENIED,
+    SYSTEM_AUDIT,
+    SYSTEM_ALARM,
+    ACCESS_ALLOWED_OBJECT,
+    ACCESS_DENIED_OBJECT,
+    SYSTEM_AUDIT_OBJECT,
+    SYSTEM_ALARM_OBJECT,
+    UNKNOWN(u8),
+}
+
+impl AceType {
+    fn parse(ty: u8) -> AceType {
+        use AceType::*;
+
+        match ty {
+            0 => ACCESS_ALLOWED,
+            1 => ACCESS_DENIED,
+            2 => SYSTEM_AUDIT,
+            3 => SYSTEM_ALARM,
+            5 => ACCESS_ALLOWED_OBJECT,
+            6 => ACCESS_DENIED_OBJECT,
+            7 => SYSTEM_AUDIT_OBJECT,
+            8 => SYSTEM_ALARM_OBJECT,
+            _ => UNKNOWN(ty),
+        }
+    }
+
+    fn is_object(&self) -> bool {
+        use AceType::*;
+
+        matches!(
+            self,
+            ACCESS_ALLOWED_OBJECT
+                | ACCESS_DENIED_OBJECT
+                | SYSTEM_AUDIT_OBJECT
+                | SYSTEM_ALARM_OBJECT
+        )
+    }
+
+    fn sddl(&self) -> Option<&'static str> {
+        use AceType::*;
+
+        match self {
+            ACCESS_ALLOWED => Some("A"),
+            ACCESS_DENIED => Some("D"),
+            SYSTEM_AUDIT => Some("AU"),
+            SYSTEM_ALARM => Some("AL"),
+            ACCESS_ALLOWED_OBJECT => Some("OA"),
+            ACCESS_DENIED_OBJECT => Some("OD"),
+            SYSTEM_AUDIT_OBJECT => Some("OU"),
+            SYSTEM_ALARM_OBJECT => Some("OL"),
+            UNKNOWN(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ace {
+    pub ace_type: AceType,
+    pub flags: AceFlags,
+    pub mask: u32,
+    pub object_type: Option<[u8; 16]>,
+    pub inherited_object_type: Option<[u8; 16]>,
+    // None for unknown ace types, their layout is not known
+    pub sid: Option<Sid>,
+}
+
+pub(crate) fn guid_to_string(guid: &[u8; 16]) -> String {
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{}",
+        LittleEndian::read_u32(&guid[0..]),
+        LittleEndian::read_u16(&guid[4..]),
+        LittleEndian::read_u16(&guid[6..]),
+        guid[8],
+        guid[9],
+        guid[10..]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+impl Ace {
+    fn parse(data: &[u8], offset: usize) -> Result<(Ace, usize)> {
+        let header = data
+            .get(offset..offset + 4)
+            .ok_or_else(|| format_err!("security descriptor truncated at ace at {}", offset))?;
+
+        let ace_type = AceType::parse(header[0]);
+        let flags = AceFlags::from_bits_truncate(header[1]);
+        let size = LittleEndian::read_u16(&header[2..]) as usize;
+
+        if size < 4 {
+            return Err(format_err!("invalid ace size {} at {}", size, offset));
+        }
+
+        let mut ace = Ace {
+            ace_type,
+            flags,
+            mask: 0,
+            object_type: None,
+            inherited_object_type: None,
+            sid: None,
+        };
+
+        if let AceType::UNKNOWN(_) = ace_type {
+            return Ok((ace, size));
+        }
+
+        ace.mask = read_u32(data, offset + 4)?;
+        let mut position = offset + 8;
+
+        if ace_type.is_object() {
+            let object_flags = read_u32(data, position)?;
+            position += 4;
+
+            let mut read_guid = |present: bool| -> Result<Option<[u8; 16]>> {
+                if !present {
+                    return Ok(None);
+                }
+
+                let mut guid = [0; 16];
+                guid.copy_from_slice(data.get(position..position + 16).ok_or_else(|| {
+                    format_err!("security descriptor truncated at guid at {}", position)
+                })?);
+                position += 16;
+
+                Ok(Some(guid))
+            };
+
+            ace.object_type = read_guid(object_flags & 0x1 != 0)?;
+            ace.inherited_object_type = read_guid(object_flags & 0x2 != 0)?;
+        }
+
+        ace.sid = Some(Sid::parse(data, position)?);
+
+        Ok((ace, size))
+    }
+
+    // None for unknown ace types, those can't be represented
+    pub fn to_sddl(&self) -> Option<String> {
+        let ty = self.ace_type.sddl()?;
+
+        let flag_names = [
+            (AceFlags::OBJECT_INHERIT, "OI"),
+            (AceFlags::CONTAINER_INHERIT, "CI"),
+            (AceFlags::NO_PROPAGATE_INHERIT, "NP"),
+            (AceFlags::INHERIT_ONLY, "IO"),
+            (AceFlags::INHERITED, "ID"),
+            (AceFlags::SUCCESSFUL_ACCESS, "SA"),
+            (AceFlags::FAILED_ACCESS, "FA"),
+        ];
+        let flags: String = flag_names
+            .iter()
+            .filter(|(flag, _)| self.flags.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect();
+
+        let rights = match self.mask {
+            0x1f01ff => "FA".to_string(),
+            0x120089 => "FR".to_string(),
+            0x120116 => "FW".to_string(),
+            0x1200a0 => "FX".to_string(),
+            mask => format!("0x{:x}", mask),
+        };
+
+        let guid = |guid: &Option<[u8; 16]>| guid.as_ref().map(guid_to_string).unwrap_or_default();
+
+        Some(format!(
+            "({};{};{};{};{};{})",
+            ty,
+            flags,
+            rights,
+            guid(&self.object_type),
+            guid(&self.inherited_object_type),
+            self.sid.as_ref().map(Sid::to_sddl).unwrap_or_default()
+        ))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Acl {
+    pub revision: u8,
+    pub aces: Vec<Ace>,
+}
+
+impl Acl {
+    fn parse(data: &[u8], offset: usize) -> Result<Acl> {
+        let revision = *data
+            .get(offset)
+            .ok_or_else(|| format_err!("security descriptor truncated at acl at {}", offset))?;
+        let count = read_u16(data, offset + 4)?;
+
+        let mut position = offset + 8;
+        let mut aces = Vec::new();
+        for _ in 0..count {
+            let (ace, size) = Ace::parse(data, position)?;
+            aces.push(ace);
+            position += size;
+        }
+
+        Ok(Acl { revision, aces })
+    }
+
+    fn to_sddl(&self) -> String {
+        self.aces.iter().filter_map(Ace::to_sddl).collect()
+    }
+}
+
+// A security descriptor in self relative format, as stored in the NACL stream
+#[derive(Debug, Clone)]
+pub struct SecurityDescriptor {
+    pub revision: u8,
+    pub control: SecurityDescriptorControl,
+    pub owner: Option<Sid>,
+    pub group: Option<Sid>,
+    pub sacl: Option<Acl>,
+    pub dacl: Option<Acl>,
+}
+
+impl SecurityDescriptor {
+    pub fn parse(data: &[u8]) -> Result<SecurityDescriptor> {
+        let revision = *data
+            .first()
+            .ok_or_else(|| format_err!("empty security descriptor"))?;
+        let control = SecurityDescriptorControl::from_bits_truncate(read_u16(data, 2)?);
+
+        // an offset of zero means the part is not present
+        let offset = |position| -> Result<Option<usize>> {
+            Ok(Some(read_u32(data, position)? as usize).filter(|&offset| offset != 0))
+        };
+
+        let owner = offset(4)?.map(|o| Sid::parse(data, o)).transpose()?;
+        let group = offset(8)?.map(|o| Sid::parse(data, o)).transpose()?;
+        let sacl = offset(12)?.map(|o| Acl::parse(data, o)).transpose()?;
+        let dacl = offset(16)?.map(|o| Acl::parse(data, o)).transpose()?;
+
+        Ok(SecurityDescriptor {
+            revision,
+            control,
+            owner,
+            group,
+            sacl,
+            dacl,
+        })
+    }
+
+    pub fn to_sddl(&self) -> String {
+        use SecurityDescriptorControl as C;
+
+        let mut sddl = String::new();
+
+        if let Some(owner) = &self.owner {
+            sddl += &format!("O:{}", owner.to_sddl());
+        }
+        if let Some(group) = &self.group {
+            sddl += &format!("G:{}", group.to_sddl());
+        }
+
+        let acl_flags = |protected, inherited, inherit_req| {
+            [(protected, "P"), (inherited, "AI"), (inherit_req, "AR")]
+                .iter()
+                .filter(|(flag, _)| self.control.contains(*flag))
+                .map(|(_, name)| *name)
+                .collect::<String>()
+        };
+
+        if self.control.contains(C::DACL_PRESENT) {
+            let flags = acl_flags(
+                C::DACL_PROTECTED,
+                C::DACL_AUTO_INHERITED,
+                C::DACL_AUTO_INHERIT_REQ,
+            );
+            match &self.dacl {
+                Some(dacl) => sddl += &format!("D:{}{}", flags, dacl.to_sddl()),
+                // a NULL DACL grants everyone full access
+                None => sddl += &format!("D:{}NO_ACCESS_CONTROL", flags),
+            }
+        }
+
+        if self.control.contains(C::SACL_PRESENT) {
+            let flags = acl_flags(
+                C::SACL_PROTECTED,
+                C::SACL_AUTO_INHERITED,
+                C::SACL_AUTO_INHERIT_REQ,
+            );
+            match &self.sacl {
+                Some(sacl) => sddl += &format!("S:{}{}", flags, sacl.to_sddl()),
+                None => sddl += &format!("S:{}NO_ACCESS_CONTROL", flags),
+            }
+        }
+
+        sddl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sid(authority: u64, sub_authorities: &[u32]) -> Vec<u8> {
+        let mut sid = vec![1, sub_authorities.len() as u8];
+        sid.extend_from_slice(&authority.to_be_bytes()[2..]);
+        for sub_authority in sub_authorities {
+            sid.extend_from_slice(&sub_authority.to_le_bytes());
+        }
+        sid
+    }
+
+    fn ace(ace_type: u8, flags: u8, mask: u32, body: &[u8]) -> Vec<u8> {
+        let mut ace = vec![ace_type, flags];
+        ace.extend_from_slice(&(8 + body.len() as u16).to_le_bytes());
+        ace.extend_from_slice(&mask.to_le_bytes());
+        ace.extend_from_slice(body);
+        ace
+    }
+
+    fn acl(aces: &[Vec<u8>]) -> Vec<u8> {
+        let size = 8 + aces.iter().map(Vec::len).sum::<usize>();
+        let mut acl = vec![2, 0];
+        acl.extend_from_slice(&(size as u16).to_le_bytes());
+        acl.extend_from_slice(&(aces.len() as u16).to_le_bytes());
+        acl.extend_from_slice(&[0; 2]);
+        for ace in aces {
+            acl.extend_from_slice(ace);
+        }
+        acl
+    }
+
+    // Self relative, the parts follow the header in the order owner, group, sacl, dacl
+    fn descriptor(
+        control: SecurityDescriptorControl,
+        owner: Option<&[u8]>,
+        group: Option<&[u8]>,
+        sacl: Option<&[u8]>,
+        dacl: Option<&[u8]>,
+    ) -> Vec<u8> {
+        let mut header = vec![1, 0];
+        header.extend_from_slice(&control.bits().to_le_bytes());
+        let mut parts = Vec::new();
+
+        for part in [owner, group, sacl, dacl] {
+            let offset = match part {
+                Some(part) => {
+                    let offset = 20 + parts.len();
+                    parts.extend_from_slice(part);
+                    offset as u32
+                }
+                None => 0,
+            };
+            header.extend_from_slice(&offset.to_le_bytes());
+        }
+
+        [header, parts].concat()
+    }
+
+    #[test]
+    fn sddl() {
+        use SecurityDescriptorControl as C;
+
+        let guid: Vec<u8> = (1..=16).collect();
+        let object_ace = [&1u32.to_le_bytes()[..], &guid, &sid(1, &[0])].concat();
+        let dacl = acl(&[
+            ace(0, 0x3, 0x1f01ff, &sid(5, &[18])),
+            ace(0, 0x13, 0x120089, &sid(5, &[32, 545])),
+            ace(1, 0, 0x10000, &sid(5, &[21, 1, 2, 3, 1001])),
+            ace(5, 0, 0x100, &object_ace),
+            // a mandatory label, skipped
+            ace(0x11, 0, 0x1, &sid(16, &[8192])),
+        ]);
+        let sacl = acl(&[ace(2, 0x80, 0x120116, &sid(1, &[0]))]);
+
+        let data = descriptor(
+            C::SELF_RELATIVE
+                | C::DACL_PRESENT
+                | C::DACL_PROTECTED
+                | C::DACL_AUTO_INHERITED
+                | C::SACL_PRESENT,
+            Some(&sid(5, &[32, 544])),
+            Some(&sid(5, &[21, 1, 2, 3, 513])),
+            Some(&sacl),
+            Some(&dacl),
+        );
+        let descriptor = SecurityDescriptor::parse(&data).unwrap();
+
+        assert_eq!(descriptor.dacl.as_ref().unwrap().aces.len(), 5);
+        assert_eq!(
+            descriptor.to_sddl(),
+            "O:BAG:S-1-5-21-1-2-3-513\
+             D:PAI(A;OICI;FA;;;SY)(A;OICIID;FR;;;BU)(D;;0x10000;;;S-1-5-21-1-2-3-1001)\
+             (OA;;0x100;04030201-0605-0807-090a-0b0c0d0e0f10;;WD)\
+             S:(AU;FA;FW;;;WD)"
+        );
+    }
+
+    #[test]
+    fn null_dacl_and_sids() {
+        use SecurityDescriptorControl as C;
+
+        let data = descriptor(C::SELF_RELATIVE | C::DACL_PRESENT, None, None, None, None);
+        assert_eq!(
+            SecurityDescriptor::parse(&data).unwrap().to_sddl(),
+            "D:NO_ACCESS_CONTROL"
+        );
+
+        // no DACL_PRESENT, nothing to restrict
+        let data = descriptor(C::SELF_RELATIVE, None, None, None, None);
+        assert_eq!(SecurityDescriptor::parse(&data).unwrap().to_sddl(), "");
+
+        let sid = |authority, sub_authorities: &[u32]| {
+            Sid::parse(&sid(authority, sub_authorities), 0).unwrap()
+        };
+        assert_eq!(sid(5, &[18]).to_sddl(), "SY");
+        assert_eq!(sid(5, &[21, 7]).to_sddl(), "S-1-5-21-7");
+        assert_eq!(
+            sid(0x0100_0000_0000, &[1]).to_string(),
+            "S-1-0x010000000000-1"
+        );
+        assert_eq!(sid(0, &[]).to_string(), "S-1-0");
+    }
+
+    #[test]
+    fn truncated_descriptors() {
+        use SecurityDescriptorControl as C;
+
+        let error = |data: &[u8]| SecurityDescriptor::parse(data).unwrap_err().to_string();
+
+        assert_eq!(error(&[]), "empty security descriptor");
+        assert!(error(&[1, 0, 4]).contains("truncated"));
+
+        // two sub authorities announced, one there
+        let mut owner = sid(5, &[32, 544]);
+        owner.truncate(owner.len() - 4);
+        let data = descriptor(C::SELF_RELATIVE, Some(&owner), None, None, None);
+        assert!(error(&data).contains("truncated at 32"), "{}", error(&data));
+
+        let data = descriptor(C::SELF_RELATIVE, Some(&[1, 0, 0]), None, None, None);
+        assert!(
+            error(&data).contains("truncated at sid at 20"),
+            "{}",
+            error(&data)
+        );
+
+        // two aces announced, one there
+        let mut dacl = acl(&[ace(0, 0, 0x1f01ff, &sid(1, &[0]))]);
+        dacl[4] = 2;
+        let data = descriptor(C::DACL_PRESENT, None, None, None, Some(&dacl));
+        assert!(
+            error(&data).contains("truncated at ace at"),
+            "{}",
+            error(&data)
+        );
+
+        // an ace cut off in its mask
+        let mut dacl = acl(&[ace(0, 0, 0x1f01ff, &sid(1, &[0]))]);
+        dacl.truncate(8 + 6);
+        let data = descriptor(C::DACL_PRESENT, None, None, None, Some(&dacl));
+        assert!(error(&data).contains("truncated"), "{}", error(&data));
+
+        // a size below the ace header would not move on to the next ace
+        let mut dacl = acl(&[ace(0, 0, 0x1f01ff, &sid(1, &[0]))]);
+        dacl[8 + 2..8 + 4].copy_from_slice(&2u16.to_le_bytes());
+        let data = descriptor(C::DACL_PRESENT, None, None, None, Some(&dacl));
+        assert_eq!(error(&data), "invalid ace size 2 at 28");
+    }
+}
@@ -0,0 +1,283 @@
+use crate::backup::SetFile;
+use crate::compression::CompressionAlgorithm;
+use crate::files::{FileEntry, MatchPolicy, PathResolver};
+use crate::{DBLKIterator, DBLKSpecific, DateTime, DBLK};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+// A data set (SSET) with everything that was backed up in it
+#[derive(Debug)]
+pub struct BackupSet<'a> {
+    pub sset: DBLK,
+    pub volumes: Vec<Volume<'a>>,
+}
+
+#[derive(Debug)]
+pub struct Volume<'a> {
+    pub volb: DBLK,
+    pub root: Directory<'a>,
+}
+
+#[derive(Debug)]
+pub struct Directory<'a> {
+    // shared with the other directories of the same name
+    pub name: Arc<str>,
+    // None for directories that only appear as part of the path of another DIRB
+    pub dirb: Option<DBLK>,
+    pub directories: Vec<Directory<'a>>,
+    pub files: Vec<FileEntry<'a>>,
+}
+
+impl<'a> BackupSet<'a> {
+    pub fn data_set_number(&self) -> Option<u16> {
+        match self.sset.body {
+            DBLKSpecific::SSET {
+                data_set_number, ..
+            } => Some(data_set_number),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        match &self.sset.body {
+            DBLKSpecific::SSET { data_set_name, .. } => data_set_name.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn compression_algorithm(&self) -> CompressionAlgorithm {
+        match self.sset.body {
+            DBLKSpecific::SSET {
+                software_compression_algorithm,
+                ..
+            } => software_compression_algorithm,
+            _ => CompressionAlgorithm::NONE,
+        }
+    }
+
+    // All files of all volumes, depth first
+    pub fn files(&self) -> Vec<SetFile<'_, 'a>> {
+        let compression_algorithm = self.compression_algorithm();
+        let mut files = Vec::new();
+
+        for volume in &self.volumes {
+            volume.root.collect_files(&mut files, compression_algorithm);
+        }
+
+        files
+    }
+
+    // The data of a password protected set is not encrypted, the password is only enforced by the
+    // backup software, so it is still readable
+    pub fn is_password_protected(&self) -> bool {
+        match &self.sset.body {
+            DBLKSpecific::SSET {
+                data_set_password, ..
+            } => data_set_password
+                .as_ref()
+                .is_some_and(|password| !password.is_empty()),
+            _ => false,
+        }
+    }
+
+    pub(crate) fn build(dblks: &mut DBLKIterator<'a>) -> Vec<BackupSet<'a>> {
+        let options = dblks.options;
+        let mut resolver = PathResolver::default();
+        let mut sets: Vec<BackupSet> = Vec::new();
+
+        for dblk in dblks {
+            match dblk.dblk.body {
+                DBLKSpecific::SSET { .. } => {
+                    sets.push(BackupSet {
+                        sset: dblk.dblk,
+                        volumes: Vec::new(),
+                    });
+                    continue;
+                }
+                DBLKSpecific::VOLB { .. } => {
+                    let volb = dblk.dblk.clone();
+                    resolver.resolve(dblk, options);
+
+                    if let Some(set) = sets.last_mut() {
+                        set.volumes.push(Volume {
+                            volb,
+                            root: Directory::new("".into()),
+                        });
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+
+            let dirb = match dblk.dblk.body {
+                DBLKSpecific::DIRB { .. } => Some(dblk.dblk.clone()),
+                _ => None,
+            };
+            let entry = resolver.resolve(dblk, options);
+
+            // Blocks outside of any set or volume have nowhere to go
+            let volume = match sets.last_mut().and_then(|set| set.volumes.last_mut()) {
+                Some(volume) => volume,
+                None => continue,
+            };
+            let directory = volume.root.get_or_insert(resolver.directory());
+
+            if let Some(dirb) = dirb {
+                directory.dirb = Some(dirb);
+            }
+
+            if let Some(entry) = entry {
+                directory.files.push(entry);
+            }
+        }
+
+        sets
+    }
+}
+
+// SHA-256 over the names, sizes, dates and attributes of everything in the sets, in media order.
+// Offsets, padding and the raw data are left out, so two images of the same tape hash the same
+// even if one has trailing garbage or different filemark padding
+pub fn content_hash(sets: &[BackupSet]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+
+    for set in sets {
+        if let DBLKSpecific::SSET {
+            data_set_number,
+            data_set_name,
+            ..
+        } = &set.sset.body
+        {
+            hasher.update(b"SSET");
+            hasher.update(data_set_number.to_le_bytes());
+            hash_str(&mut hasher, data_set_name.as_deref().unwrap_or_default());
+        }
+
+        for volume in &set.volumes {
+            if let DBLKSpecific::VOLB {
+                device_name,
+                volume_name,
+                ..
+            } = &volume.volb.body
+            {
+                hasher.update(b"VOLB");
+                hash_str(&mut hasher, device_name.as_deref().unwrap_or_default());
+                hash_str(&mut hasher, volume_name.as_deref().unwrap_or_default());
+            }
+
+            volume.root.hash(&mut hasher);
+        }
+    }
+
+    hasher.finalize().into()
+}
+
+// Length prefixed, so neighbouring fields can't run into each other
+fn hash_str(hasher: &mut Sha256, s: &str) {
+    hasher.update((s.len() as u64).to_le_bytes());
+    hasher.update(s);
+}
+
+fn hash_date(hasher: &mut Sha256, date: &DateTime) {
+    match date.unix_timestamp() {
+        Some(timestamp) => {
+            hasher.update([1]);
+            hasher.update(timestamp.to_le_bytes());
+        }
+        None => hasher.update([0]),
+    }
+}
+
+impl<'a> Directory<'a> {
+    fn hash(&self, hasher: &mut Sha256) {
+        hasher.update(b"DIRB");
+        hash_str(hasher, &self.name);
+
+        for file in &self.files {
+            hasher.update(b"FILE");
+            hash_str(hasher, &file.full_path.to_string_lossy());
+            hasher.update(file.size.to_le_bytes());
+            hasher.update(file.attributes.bits().to_le_bytes());
+            hash_date(hasher, &file.dates.last_modification);
+            hash_date(hasher, &file.dates.creation);
+            hash_date(hasher, &file.dates.backup);
+            hash_date(hasher, &file.dates.last_access);
+        }
+
+        for directory in &self.directories {
+            directory.hash(hasher);
+        }
+
+        // empty directories only show up in the nesting
+        hasher.update(b"DEND");
+    }
+
+    fn new(name: Arc<str>) -> Self {
+        Self {
+            name,
+            dirb: None,
+            directories: Vec::new(),
+            files: Vec::new(),
+        }
+    }
+
+    fn get_or_insert(&mut self, path: &[Arc<str>]) -> &mut Directory<'a> {
+        match path.split_first() {
+            None => self,
+            Some((name, rest)) => {
+                let idx = match self.directories.iter().position(|dir| &dir.name == name) {
+                    Some(idx) => idx,
+                    None => {
+                        self.directories.push(Directory::new(name.clone()));
+                        self.directories.len() - 1
+                    }
+                };
+
+                self.directories[idx].get_or_insert(rest)
+            }
+        }
+    }
+
+    fn collect_files<'s>(
+        &'s self,
+        files: &mut Vec<SetFile<'s, 'a>>,
+        compression_algorithm: CompressionAlgorithm,
+    ) {
+        files.extend(self.files.iter().map(|entry| SetFile {
+            entry,
+            compression_algorithm,
+        }));
+
+        for directory in &self.directories {
+            directory.collect_files(files, compression_algorithm);
+        }
+    }
+
+    // Looks up a directory by its path relative to this one
+    pub fn directory(&self, path: &[&str]) -> Option<&Directory<'a>> {
+        self.directory_with(path, MatchPolicy::EXACT)
+    }
+
+    pub fn directory_with(&self, path: &[&str], policy: MatchPolicy) -> Option<&Directory<'a>> {
+        match path.split_first() {
+            None => Some(self),
+            Some((name, rest)) => self
+                .directories
+                .iter()
+                .find(|dir| policy.matches(&dir.name, name))?
+                .directory_with(rest, policy),
+        }
+    }
+
+    pub fn file(&self, name: &str) -> Option<&FileEntry<'a>> {
+        self.file_with(name, MatchPolicy::EXACT)
+    }
+
+    pub fn file_with(&self, name: &str, policy: MatchPolicy) -> Option<&FileEntry<'a>> {
+        self.files.iter().find(|file| {
+            file.full_path
+                .file_name()
+                .is_some_and(|n| policy.matches(&n.to_string_lossy(), name))
+        })
+    }
+}
@@ -0,0 +1,123 @@
+use crate::ea::{self, ExtendedAttribute};
+use crate::files::{AlternateStream, ObjectId, SparseBlock};
+use crate::security::SecurityDescriptor;
+use crate::{Result, StreamWithData};
+use byteorder::{ByteOrder, LittleEndian};
+use failure::format_err;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamKind {
+    // standard data
+    STAN,
+    // path name of a DIRB, if it did not fit into the DBLK
+    PNAM,
+    // file name of a FILE, if it did not fit into the DBLK
+    FNAM,
+    // checksum of the preceding stream
+    CSUM,
+    // the preceding stream is corrupt
+    CRPT,
+    SPAD,
+    SPAR,
+    // the media based catalog: set map and file/directory detail
+    TSMP,
+    TFDD,
+    // set map and file/directory detail of the type 2 catalog
+    MAP2,
+    FDD2,
+    // file size, for FILE blocks that are written before their size is known
+    FSIZ,
+    NACL,
+    NTEA,
+    NTOI,
+    NTRP,
+    NTLK,
+    ADAT,
+    OS2A,
+    // SQL server database data, configuration and file list, log backups have the log instead
+    // of the data
+    MQDA,
+    MQTL,
+    MSCI,
+    MQCI,
+    UNKNOWN(String),
+}
+
+impl StreamKind {
+    pub fn parse(id: &str) -> StreamKind {
+        use StreamKind::*;
+
+        match id {
+            "STAN" => STAN,
+            "PNAM" => PNAM,
+            "FNAM" => FNAM,
+            "CSUM" => CSUM,
+            "CRPT" => CRPT,
+            "SPAD" => SPAD,
+            "SPAR" => SPAR,
+            "TSMP" => TSMP,
+            "TFDD" => TFDD,
+            "MAP2" => MAP2,
+            "FDD2" => FDD2,
+            "FSIZ" => FSIZ,
+            "NACL" => NACL,
+            "NTEA" => NTEA,
+            "NTOI" => NTOI,
+            "NTRP" => NTRP,
+            "NTLK" => NTLK,
+            "ADAT" => ADAT,
+            "OS2A" => OS2A,
+            "MQDA" => MQDA,
+            "MQTL" => MQTL,
+            "MSCI" => MSCI,
+            "MQCI" => MQCI,
+            _ => UNKNOWN(id.to_string()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StreamPayload<'a> {
+    SECURITY(SecurityDescriptor),
+    EXTENDED_ATTRIBUTES(Vec<ExtendedAttribute<'a>>),
+    OBJECT_ID(ObjectId),
+    SPARSE(SparseBlock<'a>),
+    ALTERNATE(AlternateStream<'a>),
+    CHECKSUM(u32),
+    FILE_SIZE(u64),
+    // everything without a parser, names need the string type of their DBLK for example
+    RAW(&'a [u8]),
+}
+
+impl<'a> StreamWithData<'a> {
+    pub fn kind(&self) -> StreamKind {
+        StreamKind::parse(self.id())
+    }
+
+    pub fn payload(&self) -> Result<StreamPayload<'a>> {
+        let data = self.data;
+        let truncated = || format_err!("{} stream truncated, {} bytes", self.id(), data.len());
+
+        Ok(match self.kind() {
+            StreamKind::NACL => StreamPayload::SECURITY(SecurityDescriptor::parse(data)?),
+            StreamKind::NTEA => StreamPayload::EXTENDED_ATTRIBUTES(ea::parse_nt(data)?),
+            StreamKind::OS2A => StreamPayload::EXTENDED_ATTRIBUTES(ea::parse_os2(data)?),
+            StreamKind::NTOI => {
+                StreamPayload::OBJECT_ID(ObjectId::parse(data).ok_or_else(truncated)?)
+            }
+            StreamKind::SPAR => {
+                StreamPayload::SPARSE(SparseBlock::parse(data).ok_or_else(truncated)?)
+            }
+            StreamKind::ADAT => {
+                StreamPayload::ALTERNATE(AlternateStream::parse(data).ok_or_else(truncated)?)
+            }
+            StreamKind::CSUM => StreamPayload::CHECKSUM(LittleEndian::read_u32(
+                data.get(..4).ok_or_else(truncated)?,
+            )),
+            StreamKind::FSIZ => StreamPayload::FILE_SIZE(LittleEndian::read_u64(
+                data.get(..8).ok_or_else(truncated)?,
+            )),
+            _ => StreamPayload::RAW(data),
+        })
+    }
+}